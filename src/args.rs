@@ -1,20 +1,171 @@
-use clap::Parser;
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
 
-//#[derive(Debug, Clone)]
-/*enum OutputFormat{
-    JSON,
-    CSV
-}*/
+use crate::predicate::Predicate;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// If present, print DEBUG info to stdout
+    #[arg(long, short, global = true, action)]
+    pub debug: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print the headers of the main file and, if present, of its WAL and rollback journal
+    Header {
+        /// Path of the main SQLite file
+        filepath: String,
+    },
+    /// Print the schema (tables, indices, views and triggers) stored in sqlite_master
+    Schema {
+        /// Path of the main SQLite file
+        filepath: String,
+
+        /// If present, print the entries deleted from sqlite_master as well (left in the free
+        /// space of page 1 and in freelist pages)
+        #[arg(long, action)]
+        dropped_tables: bool,
+    },
+    /// Extract tables: live rows and, with --wal, rows inserted, deleted and modified in the WAL
+    Dump(DumpArgs),
+    /// List the frames of the WAL, grouped by transaction
+    Wal {
+        /// Path of the main SQLite file (the WAL is next to it)
+        filepath: String,
+    },
+    /// Extract only recovered records: deleted rows, also from the WAL if present (plus missing
+    /// rowids, FTS5 terms and rows of dropped tables and freelist pages, if requested)
+    Carve(DumpArgs),
+    /// List the pages of the main file, or print the header and cells of one of them
+    Pages {
+        /// Path of the main SQLite file
+        filepath: String,
+
+        /// Number of the page to print (1 is the first one)
+        #[arg(long)]
+        page: Option<u32>,
+    },
+    /// Extract only the rows changed by the WAL: inserted, deleted and modified
+    Diff(DumpArgs),
+    /// Carve table leaf pages from data without a database header (e.g.: unallocated space),
+    /// writing their records grouped by inferred schema
+    CarvePages {
+        /// Path of the file to carve
+        filepath: String,
+
+        /// SQLite file whose tables are matched against the inferred schemas
+        #[arg(long, value_name = "DB")]
+        schema: Option<String>,
+
+        /// Output format (JSON or CSV)
+        #[arg(long, value_enum, ignore_case = true, default_value_t = Format::Json)]
+        format: Format,
+
+        /// Output directory contaning generated files
+        #[arg(short, long, default_value_t = String::from("output"))]
+        output_dir: String,
+    },
+    /// Find SQLite databases embedded at page-aligned offsets of any file (e.g.: memory dumps, disk images)
+    /// and extract them
+    Scan {
+        /// Path of the file to scan
+        filepath: String,
+
+        /// Output directory of the extracted databases
+        #[arg(short, long, default_value_t = String::from("output"))]
+        output_dir: String,
+    },
+    /// Dump every SQLite file (found by its magic) under a directory, reading its WAL if present
+    Batch(BatchArgs),
+}
+
+/// Output formats of extracted rows
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Ndjson,
+    Csv,
+    Sqlite,
+    Sql,
+    Html,
+    Timeline,
+}
+
+/// Options of the commands writing extracted rows
+#[derive(ClapArgs, Debug)]
+pub struct DumpArgs {
     /// Path of the main SQLite file
     pub filepath: String,
 
-    /// Output format: JSON | CSV
-    #[arg(long, default_value_t = String::from("JSON"))]
-    pub format: String,
+    /// Output format
+    #[arg(long, value_enum, ignore_case = true, default_value_t = Format::Json)]
+    pub format: Format,
+
+    /// Glob of the names of the tables to extract (e.g.: "msg*"); can be repeated
+    #[arg(long = "table", value_name = "GLOB")]
+    pub tables: Vec<String>,
+
+    /// Glob of the names of the tables not to extract; can be repeated
+    #[arg(long = "exclude-table", value_name = "GLOB")]
+    pub exclude_tables: Vec<String>,
+
+    /// Columns to extract from a table, as TABLE:COLUMN,COLUMN; can be repeated
+    #[arg(long, value_name = "TABLE:COLUMNS", value_parser = parse_columns)]
+    pub columns: Vec<(String, Vec<String>)>,
+
+    /// Range of the rowids to extract, as FROM..TO (bounds included, either can be omitted)
+    #[arg(long, value_name = "FROM..TO", value_parser = parse_rowid_range)]
+    pub rowid_range: Option<(i64, i64)>,
+
+    /// Condition on the values of the rows to extract (live, WAL and recovered ones), e.g.:
+    /// "sender LIKE '%@example.com' AND date > 1700000000"; tables without its columns are empty
+    #[arg(long = "where", value_name = "EXPR", value_parser = Predicate::parse)]
+    pub predicate: Option<Predicate>,
+
+    /// Field delimiter of CSV files
+    #[arg(long, default_value_t = ';')]
+    pub delimiter: char,
+
+    /// If present, quote every field of CSV files
+    #[arg(long, action)]
+    pub quote_all: bool,
+
+    /// If present, start CSV files with the UTF-8 BOM (for Excel)
+    #[arg(long, action)]
+    pub bom: bool,
+
+    /// If present, add the ISO-8601 value of timestamp columns to JSON (keyed rows), NDJSON and CSV output
+    #[arg(long, action)]
+    pub decode_timestamps: bool,
+
+    /// If present, add the kind (plist, protobuf, image, ...) and the SHA-256 of blobs to JSON
+    /// (keyed rows), NDJSON and CSV output, with plists, protobuf and JSON decoded as JSON trees
+    #[arg(long, action)]
+    pub decode_blobs: bool,
+
+    /// If present, write blobs to the `blobs` directory inside the output directory,
+    /// named by their SHA-256 (which is added to the rows)
+    #[arg(long, action)]
+    pub extract_blobs: bool,
+
+    /// If present, add the source file, page, offset and WAL frame of each row
+    /// to JSON (keyed rows), NDJSON and CSV output
+    #[arg(long, action)]
+    pub provenance: bool,
+
+    /// If present, also add the bytes of each cell, in hex (implies --provenance)
+    #[arg(long, action)]
+    pub raw_cells: bool,
+
+    /// JSON file with the formats of timestamp columns, by table and column
+    /// (e.g.: {"message": {"date": "cocoa", "flags": null}}); other columns are detected
+    /// from their names and values
+    #[arg(long)]
+    pub timestamps: Option<String>,
 
     /// Output directory contaning generated files
     #[arg(short, long, default_value_t = String::from("output"))]
@@ -28,10 +179,6 @@ pub struct Args {
     #[arg(long, short, action)]
     pub parsed_files: bool,
 
-    /// If present, only print SQLite file header
-    #[arg(long, short, action)]
-    pub fileheader: bool,
-
     /// If present, try to discover missing row ids for each table
     #[arg(long, short, action)]
     pub missingids: bool,
@@ -40,11 +187,75 @@ pub struct Args {
     #[arg(long, short, action)]
     pub triggers: bool,
 
+    /// If present, JSON rows are objects keyed by column name, with rowid and status
+    #[arg(long, short, action)]
+    pub keyed_rows: bool,
+
+    /// If present, decode FTS5 indexes to recover terms of deleted rows
+    #[arg(long, action)]
+    pub fts5_index: bool,
+
+    /// If present, recover tables dropped from sqlite_master (entries left in its free space)
+    /// and their rows still in freelist pages; the records of the other freelist leaf pages are
+    /// grouped by inferred schema
+    #[arg(long, action)]
+    pub dropped_tables: bool,
+
     /// If present, try to extract indices
     #[arg(long, short, action)]
     pub indices: bool,
+}
 
-    /// If present, print DEBUG info to stdout
-    #[arg(long, short, action)]
-    pub debug: bool,
+/// Options of the batch command
+#[derive(ClapArgs, Debug)]
+pub struct BatchArgs {
+    /// Directory to search, recursively, for SQLite files
+    pub directory: String,
+
+    /// Output directory, with a folder for each database (mirroring its path) and the batch index
+    #[arg(short, long, default_value_t = String::from("output"))]
+    pub output_dir: String,
+
+    /// Number of databases processed at the same time (default: number of CPUs)
+    #[arg(long, short)]
+    pub jobs: Option<usize>,
+
+    /// Options of the dump command used for every database, after `--`
+    /// (e.g.: -- --format csv --provenance)
+    #[arg(last = true)]
+    pub dump_options: Vec<String>,
+}
+
+/// Parses TABLE:COLUMN,COLUMN
+fn parse_columns(value: &str) -> Result<(String, Vec<String>), &'static str> {
+    let (table, columns): (&str, &str) = value
+        .split_once(':')
+        .ok_or("expected TABLE:COLUMN,COLUMN")?;
+    let columns: Vec<String> = columns
+        .split(',')
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty())
+        .collect();
+    if table.is_empty() || columns.is_empty() {
+        return Err("expected TABLE:COLUMN,COLUMN");
+    }
+
+    Ok((table.to_string(), columns))
+}
+
+/// Parses FROM..TO, FROM.. or ..TO
+fn parse_rowid_range(value: &str) -> Result<(i64, i64), &'static str> {
+    let (from, to): (&str, &str) = value.split_once("..").ok_or("expected FROM..TO")?;
+    let bound = |bound: &str, default: i64| -> Result<i64, &'static str> {
+        match bound.trim() {
+            "" => Ok(default),
+            b => b.parse::<i64>().map_err(|_| "rowids must be integers"),
+        }
+    };
+    let (from, to): (i64, i64) = (bound(from, i64::MIN)?, bound(to, i64::MAX)?);
+    if from > to {
+        return Err("FROM must not be greater than TO");
+    }
+
+    Ok((from, to))
 }
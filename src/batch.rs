@@ -0,0 +1,374 @@
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use log::{debug, error, info, log_enabled, warn, Level};
+use serde::Serialize;
+use std::fs::{create_dir_all, read_dir, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::args::{Args, Command as ArgsCommand};
+use crate::evidence::{companion_paths, now};
+use crate::formatters::RunOptions;
+use crate::scan::check_header;
+
+/// Name of the summary of a batch, written in the output directory
+pub const INDEX_FILE_NAME: &str = "batch_index.json";
+
+/// Name of the file, in the output folder of each database, with what has been printed while processing it
+const LOG_FILE_NAME: &str = "rustbish.log";
+
+/// A database processed by a batch
+#[derive(Serialize)]
+pub struct BatchEntry {
+    pub database: String,
+    /// WAL, journal and shm files found next to the database
+    pub companions: Vec<String>,
+    pub output_dir: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+}
+
+/// Summary of a batch
+#[derive(Serialize)]
+pub struct BatchIndex {
+    pub tool: String,
+    pub version: String,
+    pub root: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub databases: usize,
+    pub failed: usize,
+    pub entries: Vec<BatchEntry>,
+}
+
+impl BatchIndex {
+    /// Summary of the processed databases, finished now
+    pub fn new(root: &Path, started_at: String, entries: Vec<BatchEntry>) -> BatchIndex {
+        BatchIndex {
+            tool: String::from(env!("CARGO_PKG_NAME")),
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            root: root.display().to_string(),
+            started_at,
+            finished_at: now(),
+            databases: entries.len(),
+            failed: entries.iter().filter(|e| !e.success).count(),
+            entries,
+        }
+    }
+}
+
+/// The file starts with a valid SQLite header, whatever its name
+pub fn is_sqlite_file(path: &Path) -> bool {
+    let mut header: [u8; 100] = [0; 100];
+    match File::open(path).and_then(|mut f| f.read_exact(&mut header)) {
+        Ok(_) => check_header(&header).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Finds the SQLite files under a directory, recursively (symbolic links are not followed).
+/// Files inside `skip` (e.g.: the output directory) are ignored
+pub fn discover(dir: &Path, skip: &Path, databases: &mut Vec<PathBuf>) {
+    let entries = match read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => {
+            warn!("{}: {}", dir.display(), e);
+            return;
+        }
+    };
+    let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    paths.sort();
+
+    for path in paths.into_iter() {
+        let file_type = match path.symlink_metadata() {
+            Ok(m) => m.file_type(),
+            Err(_) => continue,
+        };
+        if file_type.is_dir() {
+            if path.canonicalize().ok().as_deref() != Some(skip) {
+                discover(&path, skip, databases);
+            }
+        } else if file_type.is_file() && is_sqlite_file(&path) {
+            debug!("SQLite file: {}", path.display());
+            databases.push(path);
+        }
+    }
+}
+
+/// Processes every SQLite file found under `root` with the `dump` command, running `jobs` of them at
+/// the same time, each in its own process. The output of each database goes to a folder mirroring its
+/// path under `output_dir`; the WAL is read if present. Returns the summary, written in `output_dir`
+pub fn run(
+    root: &Path,
+    output_dir: &Path,
+    jobs: usize,
+    dump_options: &[String],
+) -> Result<BatchIndex, &'static str> {
+    check_dump_options(dump_options)?;
+
+    let started_at: String = now();
+    create_dir_all(output_dir).map_err(|_| "Cannot create output directory")?;
+    let skip: PathBuf = output_dir
+        .canonicalize()
+        .map_err(|_| "Cannot resolve output directory")?;
+
+    let mut databases: Vec<PathBuf> = vec![];
+    discover(root, &skip, &mut databases);
+    info!(
+        "Found {} SQLite files in {}",
+        databases.len(),
+        root.display()
+    );
+
+    let executable: PathBuf =
+        std::env::current_exe().map_err(|_| "Cannot find the path of rustbish")?;
+    let next: AtomicUsize = AtomicUsize::new(0);
+    let entries: Mutex<Vec<(usize, BatchEntry)>> = Mutex::new(vec![]);
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| loop {
+                let i: usize = next.fetch_add(1, Ordering::SeqCst);
+                let database: &PathBuf = match databases.get(i) {
+                    Some(d) => d,
+                    None => break,
+                };
+                let entry: BatchEntry =
+                    process(&executable, root, database, output_dir, dump_options);
+                entries.lock().unwrap().push((i, entry));
+            });
+        }
+    });
+
+    let mut entries: Vec<(usize, BatchEntry)> = entries.into_inner().unwrap();
+    entries.sort_by_key(|(i, _)| *i);
+    let entries: Vec<BatchEntry> = entries.into_iter().map(|(_, e)| e).collect();
+
+    let index: BatchIndex = BatchIndex::new(root, started_at, entries);
+    let index_file: File =
+        File::create(output_dir.join(INDEX_FILE_NAME)).map_err(|_| "Cannot create batch index")?;
+    serde_json::to_writer_pretty(index_file, &index).map_err(|_| "Cannot write batch index")?;
+
+    Ok(index)
+}
+
+/// Checks the options of the dump command once, before starting a process for every database.
+/// The path of each database and its output directory are set by the batch: they cannot be
+/// among the options
+fn check_dump_options(dump_options: &[String]) -> Result<(), &'static str> {
+    let mut argv: Vec<String> = vec![String::from(env!("CARGO_PKG_NAME")), String::from("dump")];
+    argv.extend(dump_options.iter().cloned());
+    // Without a path, the dump command cannot be parsed
+    match Args::command().try_get_matches_from(&argv) {
+        Ok(_) => return Err("Paths cannot be given in the dump options of a batch"),
+        Err(e) if e.kind() == ErrorKind::MissingRequiredArgument => (),
+        Err(e) => {
+            error!("{}", e);
+            return Err("Invalid dump options");
+        }
+    }
+
+    argv.insert(2, String::from("database"));
+    let matches: ArgMatches = Args::command().try_get_matches_from(&argv).map_err(|e| {
+        error!("{}", e);
+        "Invalid dump options"
+    })?;
+    if let Some(("dump", dump)) = matches.subcommand() {
+        if dump.value_source("output_dir") == Some(ValueSource::CommandLine) {
+            return Err(
+                "The output directory of a batch is given before `--`, not in the dump options",
+            );
+        }
+    }
+    match Args::from_arg_matches(&matches) {
+        Ok(Args {
+            command: ArgsCommand::Dump(args),
+            ..
+        }) => match RunOptions::new(&args, None) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{}", e);
+                Err("Invalid dump options")
+            }
+        },
+        _ => Err("Invalid dump options"),
+    }
+}
+
+/// Runs `dump` on a database, writing what it prints to the log of its output folder
+fn process(
+    executable: &Path,
+    root: &Path,
+    database: &Path,
+    output_dir: &Path,
+    dump_options: &[String],
+) -> BatchEntry {
+    let relative: &Path = database.strip_prefix(root).unwrap_or(database);
+    let database_output: PathBuf = output_dir.join(relative);
+    let companions: Vec<String> = companion_paths(&database.display().to_string())
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect();
+
+    let mut command: Command = Command::new(executable);
+    command
+        .arg("dump")
+        .arg(database)
+        .arg("--output-dir")
+        .arg(&database_output)
+        .args(dump_options);
+    if companions.iter().any(|c| c.ends_with("-wal"))
+        && !dump_options.iter().any(|o| o == "--wal" || o == "-w")
+    {
+        command.arg("--wal");
+    }
+    if log_enabled!(Level::Debug) {
+        command.arg("--debug");
+    }
+
+    let exit_code: Option<i32> = match create_dir_all(&database_output)
+        .and_then(|_| File::create(database_output.join(LOG_FILE_NAME)))
+    {
+        Ok(log) => {
+            let stderr: Stdio = match log.try_clone() {
+                Ok(l) => Stdio::from(l),
+                Err(_) => Stdio::null(),
+            };
+            info!("Processing {}", database.display());
+            match command.stdout(Stdio::from(log)).stderr(stderr).status() {
+                Ok(status) => status.code(),
+                Err(e) => {
+                    error!("{}: {}", database.display(), e);
+                    None
+                }
+            }
+        }
+        Err(e) => {
+            error!("{}: {}", database_output.display(), e);
+            None
+        }
+    };
+    if exit_code != Some(0) {
+        warn!(
+            "{}: failed, see {}",
+            database.display(),
+            database_output.join(LOG_FILE_NAME).display()
+        );
+    }
+
+    BatchEntry {
+        database: database.display().to_string(),
+        companions,
+        output_dir: database_output.display().to_string(),
+        success: exit_code == Some(0),
+        exit_code,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{sqlite3, test_dir};
+    use std::fs::{read_to_string, remove_dir_all, write};
+    #[cfg(unix)]
+    use std::os::unix::fs::symlink;
+
+    fn options(options: &[&str]) -> Vec<String> {
+        options.iter().map(|o| o.to_string()).collect()
+    }
+
+    #[test]
+    fn dump_options_of_a_batch() {
+        assert!(check_dump_options(&options(&["--format", "csv", "--provenance"])).is_ok());
+        assert!(check_dump_options(&options(&["--format", "xml"])).is_err());
+        for output in [
+            &["-o", "out"][..],
+            &["--output-dir", "out"],
+            &["--output-dir=out"],
+        ] {
+            assert_eq!(
+                check_dump_options(&options(output)),
+                Err(
+                    "The output directory of a batch is given before `--`, not in the dump options"
+                )
+            );
+        }
+        assert_eq!(
+            check_dump_options(&options(&["--format", "csv", "other.db"])),
+            Err("Paths cannot be given in the dump options of a batch")
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn discover_by_magic() {
+        let dir: PathBuf = test_dir("discover");
+        let root: PathBuf = dir.join("root");
+        let output: PathBuf = root.join("output");
+        let outside: PathBuf = dir.join("outside");
+        for d in [&root.join("sub"), &output, &outside] {
+            create_dir_all(d).unwrap();
+        }
+        for db in [
+            root.join("sub").join("store.bin"),
+            output.join("copy.db"),
+            outside.join("linked.db"),
+        ] {
+            sqlite3(&db, "CREATE TABLE t (a);");
+        }
+        write(root.join("notes.db"), "not a database").unwrap();
+        symlink(&outside, root.join("link")).unwrap();
+        symlink(outside.join("linked.db"), root.join("linked.db")).unwrap();
+
+        let mut databases: Vec<PathBuf> = vec![];
+        discover(&root, &output.canonicalize().unwrap(), &mut databases);
+        remove_dir_all(&dir).unwrap();
+        assert_eq!(databases, vec![root.join("sub").join("store.bin")]);
+    }
+
+    // echo and false stand in for rustbish
+    #[test]
+    #[cfg(unix)]
+    fn companions_and_failures() {
+        let dir: PathBuf = test_dir("batch_process");
+        let root: PathBuf = dir.join("root");
+        let output: PathBuf = dir.join("output");
+        create_dir_all(&root).unwrap();
+        let database: PathBuf = root.join("a.db");
+        for path in [
+            &database,
+            &root.join("a.db-wal"),
+            &root.join("a.db-journal"),
+        ] {
+            write(path, "").unwrap();
+        }
+
+        // The arguments of the dump command are written to the log
+        let entry: BatchEntry = process(
+            Path::new("echo"),
+            &root,
+            &database,
+            &output,
+            &options(&["--format", "csv"]),
+        );
+        let log: String = read_to_string(output.join("a.db").join(LOG_FILE_NAME)).unwrap();
+        assert!(entry.success);
+        assert_eq!(entry.companions.len(), 2);
+        assert!(entry.companions.iter().any(|c| c.ends_with("a.db-wal")));
+        assert!(log.ends_with("--format csv --wal\n"));
+
+        let failed: BatchEntry = process(Path::new("false"), &root, &database, &output, &[]);
+        remove_dir_all(&dir).unwrap();
+        assert!(!failed.success);
+        assert_eq!(failed.exit_code, Some(1));
+
+        let index: BatchIndex = BatchIndex::new(&root, now(), vec![entry, failed]);
+        assert_eq!(index.databases, 2);
+        assert_eq!(index.failed, 1);
+    }
+}
@@ -0,0 +1,451 @@
+use base64::{engine::general_purpose, Engine as _};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use serde::Serialize;
+use serde_json::{json, Map, Number, Value};
+use sha2::{Digest, Sha256};
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+
+use crate::utils::to_hex;
+
+/// Decompressed content beyond this size is not inspected
+const MAX_DECOMPRESSED_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Nested protobuf messages deeper than this are not decoded
+const MAX_PROTOBUF_DEPTH: usize = 32;
+
+/// Highest field number allowed by protobuf
+const MAX_PROTOBUF_FIELD: u64 = (1 << 29) - 1;
+
+/// Shorter blobs are not sniffed as protobuf messages
+const MIN_PROTOBUF_SIZE: usize = 4;
+
+/// Brands of the ISO base media files holding HEIF images
+const HEIC_BRANDS: [&[u8; 4]; 8] = [
+    b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1",
+];
+
+/// Content of a blob, guessed from its first bytes
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlobKind {
+    /// Binary property list (bplist00)
+    BinaryPlist,
+    /// Binary property list written by NSKeyedArchiver
+    KeyedArchive,
+    Gzip,
+    Zlib,
+    Png,
+    Jpeg,
+    Heic,
+    Json,
+    /// Protocol Buffers message (decoded without schema)
+    Protobuf,
+    Unknown,
+}
+
+impl BlobKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlobKind::BinaryPlist => "binary_plist",
+            BlobKind::KeyedArchive => "keyed_archive",
+            BlobKind::Gzip => "gzip",
+            BlobKind::Zlib => "zlib",
+            BlobKind::Png => "png",
+            BlobKind::Jpeg => "jpeg",
+            BlobKind::Heic => "heic",
+            BlobKind::Json => "json",
+            BlobKind::Protobuf => "protobuf",
+            BlobKind::Unknown => "unknown",
+        }
+    }
+
+    /// Extension of the file a blob is extracted to
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BlobKind::BinaryPlist | BlobKind::KeyedArchive => "plist",
+            BlobKind::Gzip => "gz",
+            BlobKind::Zlib => "zlib",
+            BlobKind::Png => "png",
+            BlobKind::Jpeg => "jpg",
+            BlobKind::Heic => "heic",
+            BlobKind::Json => "json",
+            BlobKind::Protobuf => "pb",
+            BlobKind::Unknown => "bin",
+        }
+    }
+}
+
+/// Options of blob inspection
+pub struct BlobOptions {
+    /// Decode plists, protobuf messages and JSON documents into JSON trees
+    pub decode: bool,
+    /// Directory blobs are extracted to, named by their SHA-256
+    pub extract_dir: Option<PathBuf>,
+}
+
+/// What has been found in a blob
+#[derive(Serialize)]
+pub struct BlobInfo {
+    pub kind: BlobKind,
+    pub size: usize,
+    pub sha256: String,
+    /// Kind of the decompressed content of gzip and zlib blobs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_kind: Option<BlobKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decoded: Option<Value>,
+}
+
+impl BlobInfo {
+    /// Name of the file the blob is extracted to
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.sha256, self.kind.extension())
+    }
+}
+
+/// Bytes of a parsed blob value (base64 encoded), if any
+pub fn blob_bytes(value: &str) -> Option<Vec<u8>> {
+    if value.is_empty() || value == "NULL" {
+        return None;
+    }
+    general_purpose::STANDARD.decode(value).ok()
+}
+
+/// Classifies a blob and, if requested, decodes its content (decompressing it if needed)
+pub fn inspect(bytes: &[u8], decode: bool) -> BlobInfo {
+    let kind: BlobKind = sniff(bytes);
+    let (content_kind, decoded): (Option<BlobKind>, Option<Value>) = match kind {
+        BlobKind::Gzip | BlobKind::Zlib => match decompress(bytes, kind) {
+            Some(content) => {
+                let content_kind: BlobKind = sniff(&content);
+                (
+                    Some(content_kind),
+                    match decode {
+                        true => decode_content(&content, content_kind),
+                        false => None,
+                    },
+                )
+            }
+            None => (None, None),
+        },
+        _ => (
+            None,
+            match decode {
+                true => decode_content(bytes, kind),
+                false => None,
+            },
+        ),
+    };
+
+    BlobInfo {
+        kind,
+        size: bytes.len(),
+        sha256: sha256_hex(bytes),
+        content_kind,
+        decoded,
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+/// Guesses the content of a blob from its magic bytes
+pub fn sniff(bytes: &[u8]) -> BlobKind {
+    if bytes.starts_with(b"bplist00") {
+        return match bytes.windows(15).any(|w| w == b"NSKeyedArchiver") {
+            true => BlobKind::KeyedArchive,
+            false => BlobKind::BinaryPlist,
+        };
+    }
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        return BlobKind::Gzip;
+    }
+    if bytes.starts_with(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]) {
+        return BlobKind::Png;
+    }
+    if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        return BlobKind::Jpeg;
+    }
+    if bytes.len() >= 12
+        && &bytes[4..8] == b"ftyp"
+        && HEIC_BRANDS.iter().any(|b| &bytes[8..12] == *b)
+    {
+        return BlobKind::Heic;
+    }
+    match bytes.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'{') | Some(b'[') if serde_json::from_slice::<Value>(bytes).is_ok() => {
+            return BlobKind::Json
+        }
+        _ => (),
+    }
+    // CMF (deflate, window up to 32K) and FLG bytes, with their checksum
+    if bytes.len() >= 2
+        && bytes[0] & 0x0f == 8
+        && bytes[0] >> 4 <= 7
+        && ((bytes[0] as u16) << 8 | bytes[1] as u16).is_multiple_of(31)
+    {
+        return BlobKind::Zlib;
+    }
+    if is_protobuf(bytes) {
+        return BlobKind::Protobuf;
+    }
+
+    BlobKind::Unknown
+}
+
+fn decompress(bytes: &[u8], kind: BlobKind) -> Option<Vec<u8>> {
+    let reader: Box<dyn Read + '_> = match kind {
+        BlobKind::Gzip => Box::new(GzDecoder::new(bytes)),
+        BlobKind::Zlib => Box::new(ZlibDecoder::new(bytes)),
+        _ => return None,
+    };
+    let mut content: Vec<u8> = vec![];
+    match reader.take(MAX_DECOMPRESSED_SIZE).read_to_end(&mut content) {
+        Ok(_) => Some(content),
+        Err(_) => None,
+    }
+}
+
+fn decode_content(bytes: &[u8], kind: BlobKind) -> Option<Value> {
+    match kind {
+        BlobKind::BinaryPlist | BlobKind::KeyedArchive => {
+            plist::Value::from_reader(Cursor::new(bytes))
+                .ok()
+                .map(plist_to_json)
+        }
+        BlobKind::Json => serde_json::from_slice(bytes).ok(),
+        BlobKind::Protobuf => decode_protobuf(bytes, 0),
+        _ => None,
+    }
+}
+
+/// JSON tree of a plist: data as base64, dates as ISO-8601 and UIDs as `{"CF$UID": n}`
+fn plist_to_json(value: plist::Value) -> Value {
+    match value {
+        plist::Value::Array(a) => Value::Array(a.into_iter().map(plist_to_json).collect()),
+        plist::Value::Dictionary(d) => {
+            Value::Object(d.into_iter().map(|(k, v)| (k, plist_to_json(v))).collect())
+        }
+        plist::Value::Boolean(b) => Value::Bool(b),
+        plist::Value::Data(d) => Value::String(general_purpose::STANDARD.encode(d)),
+        plist::Value::Date(d) => Value::String(d.to_xml_format()),
+        plist::Value::Real(r) => match Number::from_f64(r) {
+            Some(n) => Value::Number(n),
+            None => Value::String(r.to_string()),
+        },
+        plist::Value::Integer(i) => match i.as_signed() {
+            Some(i) => Value::from(i),
+            None => i.as_unsigned().map(Value::from).unwrap_or(Value::Null),
+        },
+        plist::Value::String(s) => Value::String(s),
+        plist::Value::Uid(u) => json!({ "CF$UID": u.get() }),
+        _ => Value::Null,
+    }
+}
+
+fn protobuf_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value: u64 = 0;
+    for (i, byte) in bytes.iter().take(10).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+
+    None
+}
+
+/// Most short blobs are well formed messages by chance: a message is only assumed if it has
+/// at least two fields, or a single length-delimited one holding text or a message
+fn is_protobuf(bytes: &[u8]) -> bool {
+    if bytes.len() < MIN_PROTOBUF_SIZE {
+        return false;
+    }
+    match decode_protobuf(bytes, 0) {
+        Some(Value::Object(fields)) if fields.len() >= 2 => true,
+        Some(_) => {
+            let (key, key_len): (u64, usize) = match protobuf_varint(bytes) {
+                Some(k) => k,
+                None => return false,
+            };
+            if key & 0x07 != 2 {
+                return false;
+            }
+            let (len, len_len): (u64, usize) = match protobuf_varint(&bytes[key_len..]) {
+                Some(l) => l,
+                None => return false,
+            };
+            let start: usize = key_len + len_len;
+            let field: &[u8] = match usize::try_from(len)
+                .ok()
+                .and_then(|len| bytes.get(start..start.checked_add(len)?))
+            {
+                Some(f) => f,
+                None => return false,
+            };
+            match std::str::from_utf8(field) {
+                Ok(s) if s.chars().all(|c| !c.is_control() || c.is_whitespace()) => true,
+                _ => decode_protobuf(field, 1).is_some(),
+            }
+        }
+        None => false,
+    }
+}
+
+/// Decodes a message without its schema: fields are keyed by number and repeated fields become arrays.
+/// Length-delimited fields are read as text if printable, else as nested messages, else as base64.
+/// Returns `None` if the bytes are not a well formed message
+fn decode_protobuf(bytes: &[u8], depth: usize) -> Option<Value> {
+    if bytes.is_empty() || depth > MAX_PROTOBUF_DEPTH {
+        return None;
+    }
+
+    let mut fields: Map<String, Value> = Map::new();
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        let (key, key_len): (u64, usize) = protobuf_varint(&bytes[i..])?;
+        i += key_len;
+        let number: u64 = key >> 3;
+        if number == 0 || number > MAX_PROTOBUF_FIELD {
+            return None;
+        }
+
+        let value: Value = match key & 0x07 {
+            0 => {
+                let (v, len): (u64, usize) = protobuf_varint(&bytes[i..])?;
+                i += len;
+                Value::from(v)
+            }
+            1 => {
+                let v: [u8; 8] = bytes.get(i..i + 8)?.try_into().ok()?;
+                i += 8;
+                Value::from(u64::from_le_bytes(v))
+            }
+            2 => {
+                let (len, len_len): (u64, usize) = protobuf_varint(&bytes[i..])?;
+                i += len_len;
+                let end: usize = i.checked_add(usize::try_from(len).ok()?)?;
+                let field: &[u8] = bytes.get(i..end)?;
+                i = end;
+                match std::str::from_utf8(field) {
+                    Ok(s) if s.chars().all(|c| !c.is_control() || c.is_whitespace()) => {
+                        Value::String(s.to_string())
+                    }
+                    _ => match decode_protobuf(field, depth + 1) {
+                        Some(message) => message,
+                        None => Value::String(general_purpose::STANDARD.encode(field)),
+                    },
+                }
+            }
+            5 => {
+                let v: [u8; 4] = bytes.get(i..i + 4)?.try_into().ok()?;
+                i += 4;
+                Value::from(u32::from_le_bytes(v))
+            }
+            // Groups are deprecated and other wire types do not exist
+            _ => return None,
+        };
+
+        match fields.get_mut(&number.to_string()) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(previous) => *previous = Value::Array(vec![previous.take(), value]),
+            None => {
+                fields.insert(number.to_string(), value);
+            }
+        }
+    }
+
+    Some(Value::Object(fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn sniff_magic_bytes() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), BlobKind::Png);
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0\0\x10JFIF"), BlobKind::Jpeg);
+        assert_eq!(sniff(b"\0\0\0\x18ftypheic\0\0\0\0"), BlobKind::Heic);
+        assert_eq!(sniff(b"\0\0\0\x18ftypisom\0\0\0\0"), BlobKind::Unknown);
+        assert_eq!(sniff(b" {\"a\": [1, 2]}"), BlobKind::Json);
+        assert_eq!(sniff(b"{not json"), BlobKind::Unknown);
+        assert_eq!(sniff(b"bplist00\xd1\x01\x02"), BlobKind::BinaryPlist);
+        assert_eq!(
+            sniff(b"bplist00\x5f\x10\x0fNSKeyedArchiver"),
+            BlobKind::KeyedArchive
+        );
+        assert_eq!(sniff(&[0x78, 0x9c, 0x03, 0x00]), BlobKind::Zlib);
+    }
+
+    #[test]
+    fn sniff_protobuf() {
+        // Two fields: text and varint
+        assert_eq!(sniff(b"\x0a\x03abc\x10\x2a"), BlobKind::Protobuf);
+        // A single length-delimited field holding text or a message
+        assert_eq!(sniff(b"\x0a\x04abcd"), BlobKind::Protobuf);
+        assert_eq!(sniff(b"\x0a\x02\x08\x01"), BlobKind::Protobuf);
+        // Too short, a single varint, binary data or plain text are not assumed to be messages
+        assert_eq!(sniff(b"\x08\x01"), BlobKind::Unknown);
+        assert_eq!(sniff(b"\x08\x96\x96\x01"), BlobKind::Unknown);
+        assert_eq!(sniff(b"\x0a\x02\xff\xff"), BlobKind::Unknown);
+        assert_eq!(sniff(b"hello world"), BlobKind::Unknown);
+    }
+
+    #[test]
+    fn decode_protobuf_fields() {
+        let decoded: Value =
+            decode_protobuf(b"\x0a\x03abc\x10\x01\x10\x02\x1a\x02\x08\x01", 0).unwrap();
+        assert_eq!(decoded, json!({"1": "abc", "2": [1, 2], "3": {"1": 1}}));
+        // Truncated length-delimited field and field number 0
+        assert!(decode_protobuf(b"\x0a\x05abc", 0).is_none());
+        assert!(decode_protobuf(b"\x00\x01", 0).is_none());
+    }
+
+    #[test]
+    fn inspect_compressed_content() {
+        let mut encoder: GzEncoder<Vec<u8>> = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"{\"a\": 1}").unwrap();
+        let gzip: Vec<u8> = encoder.finish().unwrap();
+
+        let info: BlobInfo = inspect(&gzip, true);
+        assert_eq!(info.kind, BlobKind::Gzip);
+        assert_eq!(info.size, gzip.len());
+        assert_eq!(info.content_kind, Some(BlobKind::Json));
+        assert_eq!(info.decoded, Some(json!({"a": 1})));
+        assert_eq!(info.file_name(), format!("{}.gz", sha256_hex(&gzip)));
+
+        assert_eq!(inspect(&gzip, false).decoded, None);
+    }
+
+    #[test]
+    fn inspect_plist() {
+        let mut dictionary: plist::Dictionary = plist::Dictionary::new();
+        dictionary.insert(String::from("name"), plist::Value::from("x"));
+        dictionary.insert(String::from("data"), plist::Value::Data(vec![1, 2]));
+        let mut bytes: Vec<u8> = vec![];
+        plist::Value::Dictionary(dictionary)
+            .to_writer_binary(&mut bytes)
+            .unwrap();
+
+        let info: BlobInfo = inspect(&bytes, true);
+        assert_eq!(info.kind, BlobKind::BinaryPlist);
+        assert_eq!(info.decoded, Some(json!({"name": "x", "data": "AQI="})));
+    }
+
+    #[test]
+    fn blob_bytes_and_hash() {
+        assert_eq!(blob_bytes("YWJj"), Some(b"abc".to_vec()));
+        assert_eq!(blob_bytes("NULL"), None);
+        assert_eq!(blob_bytes(""), None);
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use log::{debug, warn};
+
+use crate::constants::{LEAF_BTREE_HEADER_LEN, LEAF_TABLE_BTREE_PAGE};
+use crate::structs::{LeafCell, PageHeader};
+use crate::utils::read_varint;
+
+/// Pages are looked for at offsets multiple of the smallest page size
+const MIN_PAGE_SIZE: usize = 512;
+const MAX_PAGE_SIZE: usize = 65536;
+
+/// Header of the record of a table leaf cell stored entirely in its page
+pub struct RecordShape {
+    /// Bytes of the cell: payload size, rowid and payload
+    pub cell_len: usize,
+    pub serial_types: Vec<i64>,
+}
+
+/// A table leaf page found in unstructured data
+pub struct CarvedPage {
+    /// Inferred from the end of the cell content area
+    pub page_size: usize,
+    pub cells: Vec<LeafCell>,
+    /// Cells whose payload continues in overflow pages, which cannot be followed
+    pub overflowing_cells: usize,
+}
+
+/// Bytes of a value of the given serial type, `None` for the reserved ones
+fn serial_type_len(serial_type: i64) -> Option<usize> {
+    match serial_type {
+        0 | 8 | 9 => Some(0),
+        1..=4 => Some(serial_type as usize),
+        5 => Some(6),
+        6 | 7 => Some(8),
+        10 | 11 => None,
+        n if n < 0 => None,
+        n => Some((n as usize - 12) / 2),
+    }
+}
+
+/// Parses the header of the record of a table leaf cell, checking that the sizes of its values
+/// add up to the payload size and that the cell ends before `limit`.
+/// Returns `None` for cells not fitting in their page (overflow) or not being records
+pub fn parse_record_header(
+    bytearray: &[u8],
+    cell_address: usize,
+    limit: usize,
+) -> Option<RecordShape> {
+    let limit: usize = limit.min(bytearray.len());
+    if cell_address >= limit {
+        return None;
+    }
+    let (payload_size, payload_size_len): (i64, usize) =
+        read_varint(&bytearray[cell_address..limit]);
+    let (_, rowid_len): (i64, usize) =
+        read_varint(&bytearray[(cell_address + payload_size_len).min(limit)..limit]);
+    let payload_start: usize = cell_address + payload_size_len + rowid_len;
+    if payload_size <= 0 || payload_start >= limit {
+        return None;
+    }
+    let payload_end: usize = payload_start.checked_add(payload_size as usize)?;
+    if payload_end > limit {
+        return None;
+    }
+
+    let (header_len, header_len_size): (i64, usize) =
+        read_varint(&bytearray[payload_start..payload_end]);
+    if header_len < header_len_size as i64 || header_len > payload_size {
+        return None;
+    }
+    let header_end: usize = payload_start + header_len as usize;
+
+    let mut serial_types: Vec<i64> = vec![];
+    let mut content_len: usize = 0;
+    let mut i: usize = payload_start + header_len_size;
+    while i < header_end {
+        let (serial_type, size): (i64, usize) = read_varint(&bytearray[i..header_end]);
+        content_len += serial_type_len(serial_type)?;
+        serial_types.push(serial_type);
+        i += size;
+    }
+    if i != header_end
+        || serial_types.is_empty()
+        || header_len as usize + content_len != payload_size as usize
+    {
+        return None;
+    }
+
+    Some(RecordShape {
+        cell_len: payload_end - cell_address,
+        serial_types,
+    })
+}
+
+/// Checks whether a table leaf page starts at the given offset and decodes its live cells.
+/// The page size is the smallest one (a power of 2) containing the cell content area
+fn carve_page(bytearray: &[u8], offset: usize) -> Option<CarvedPage> {
+    let header: PageHeader = PageHeader::new(bytearray, offset);
+    let cell_count: usize = header.cell_count() as usize;
+    let content_offset: usize = match header.cell_content_offset() {
+        0 => MAX_PAGE_SIZE,
+        c => c as usize,
+    };
+    let array_end: usize = LEAF_BTREE_HEADER_LEN + 2 * cell_count;
+    let freeblock: usize = header.first_freeblock_offset() as usize;
+    if cell_count == 0
+        || content_offset < array_end
+        || header.fragmented_free_bytes() > 60
+        || (freeblock != 0 && freeblock < content_offset)
+        || offset + array_end > bytearray.len()
+    {
+        return None;
+    }
+
+    let limit: usize = (offset + MAX_PAGE_SIZE).min(bytearray.len());
+    let mut cells: Vec<(usize, RecordShape)> = vec![];
+    let mut overflowing_cells: usize = 0;
+    let mut content_end: usize = content_offset;
+    for i in 0..cell_count {
+        let pointer: usize = offset + LEAF_BTREE_HEADER_LEN + 2 * i;
+        let cell_offset: usize =
+            u16::from_be_bytes([bytearray[pointer], bytearray[pointer + 1]]) as usize;
+        if cell_offset < content_offset || cells.iter().any(|(c, _)| *c == cell_offset) {
+            return None;
+        }
+        match parse_record_header(bytearray, offset + cell_offset, limit) {
+            Some(shape) => {
+                content_end = content_end.max(cell_offset + shape.cell_len);
+                cells.push((cell_offset, shape));
+            }
+            None => {
+                // Cells which do not fit are accepted only if they can overflow
+                let (payload_size, _): (i64, usize) =
+                    read_varint(&bytearray[(offset + cell_offset).min(limit)..limit]);
+                match payload_size as usize > MIN_PAGE_SIZE - 35 {
+                    true => overflowing_cells += 1,
+                    false => return None,
+                }
+            }
+        }
+    }
+    if cells.is_empty() {
+        return None;
+    }
+
+    let page_size: usize = content_end.next_power_of_two().max(MIN_PAGE_SIZE);
+    if page_size > MAX_PAGE_SIZE {
+        return None;
+    }
+    debug!("Leaf page at 0x{:02x?} ({} bytes)", offset, page_size);
+
+    // Cells are decoded as they would be in a database with this page size
+    let cells: Vec<LeafCell> = cells
+        .iter()
+        .map(|(cell_offset, shape)| {
+            let cell_address: usize = offset + cell_offset;
+            let mut cell: LeafCell =
+                LeafCell::new_in_page(bytearray, cell_address, LEAF_TABLE_BTREE_PAGE, page_size);
+            cell.set_location(
+                None,
+                cell_address,
+                false,
+                &bytearray[cell_address..cell_address + shape.cell_len],
+            );
+            cell
+        })
+        .collect();
+
+    Some(CarvedPage {
+        page_size,
+        cells,
+        overflowing_cells,
+    })
+}
+
+/// Looks for table leaf pages at any offset multiple of 512 bytes of data without a database header
+/// (e.g.: unallocated space, memory images)
+pub fn carve_pages(bytearray: &[u8]) -> Vec<CarvedPage> {
+    let mut pages: Vec<CarvedPage> = vec![];
+
+    let mut offset: usize = 0;
+    while offset + LEAF_BTREE_HEADER_LEN <= bytearray.len() {
+        if bytearray[offset] == LEAF_TABLE_BTREE_PAGE {
+            if let Some(page) = carve_page(bytearray, offset) {
+                offset += page.page_size;
+                pages.push(page);
+                continue;
+            }
+        }
+        offset += MIN_PAGE_SIZE;
+    }
+
+    let overflowing: usize = pages.iter().map(|p| p.overflowing_cells).sum();
+    if overflowing > 0 {
+        warn!("{} cells with overflow pages skipped", overflowing);
+    }
+
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::write_varint;
+
+    /// Table leaf page of 512 bytes with a record of text values for each rowid
+    fn leaf_page(rows: &[(i64, Vec<&str>)]) -> Vec<u8> {
+        let mut page: Vec<u8> = vec![0; MIN_PAGE_SIZE];
+        let mut content_offset: usize = MIN_PAGE_SIZE;
+        for (i, (rowid, values)) in rows.iter().enumerate() {
+            let serial_types: Vec<u8> = values
+                .iter()
+                .flat_map(|v| write_varint(13 + 2 * v.len() as i64))
+                .collect();
+            let mut payload: Vec<u8> = write_varint(serial_types.len() as i64 + 1);
+            payload.extend(serial_types);
+            values.iter().for_each(|v| payload.extend(v.as_bytes()));
+
+            let mut cell: Vec<u8> = write_varint(payload.len() as i64);
+            cell.extend(write_varint(*rowid));
+            cell.extend(payload);
+            content_offset -= cell.len();
+            page[content_offset..content_offset + cell.len()].copy_from_slice(&cell);
+            let pointer: usize = LEAF_BTREE_HEADER_LEN + 2 * i;
+            page[pointer..pointer + 2].copy_from_slice(&(content_offset as u16).to_be_bytes());
+        }
+        page[0] = LEAF_TABLE_BTREE_PAGE;
+        page[3..5].copy_from_slice(&(rows.len() as u16).to_be_bytes());
+        page[5..7].copy_from_slice(&(content_offset as u16).to_be_bytes());
+
+        page
+    }
+
+    #[test]
+    fn carves_page_at_offset() {
+        let mut data: Vec<u8> = vec![0; 3 * MIN_PAGE_SIZE];
+        data.extend(leaf_page(&[
+            (1, vec!["alice", "hi"]),
+            (2, vec!["bob", "hello"]),
+        ]));
+        data.extend(vec![0xFF; MIN_PAGE_SIZE]);
+
+        let pages: Vec<CarvedPage> = carve_pages(&data);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].page_size, MIN_PAGE_SIZE);
+        assert_eq!(pages[0].overflowing_cells, 0);
+        let cells: &[LeafCell] = &pages[0].cells;
+        assert_eq!(cells.len(), 2);
+        assert_eq!(cells[0].rowid(), Some(1));
+        assert_eq!(cells[0].values(), ["alice", "hi"]);
+        assert_eq!(cells[1].values(), ["bob", "hello"]);
+        let provenance = cells[1].provenance().unwrap();
+        assert_eq!(provenance.page, None);
+        assert!(provenance.offset > 3 * MIN_PAGE_SIZE && provenance.offset < 4 * MIN_PAGE_SIZE);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        // Pseudo-random bytes, each block starting with the type of a table leaf page
+        let mut state: u32 = 12345;
+        let mut data: Vec<u8> = (0..64 * MIN_PAGE_SIZE)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect();
+        for offset in (0..data.len()).step_by(MIN_PAGE_SIZE) {
+            data[offset] = LEAF_TABLE_BTREE_PAGE;
+        }
+        assert!(carve_pages(&data).is_empty());
+
+        // A valid header whose cell pointer goes before the content area
+        let mut page: Vec<u8> = leaf_page(&[(1, vec!["x"])]);
+        page[LEAF_BTREE_HEADER_LEN..LEAF_BTREE_HEADER_LEN + 2].copy_from_slice(&[0, 9]);
+        assert!(carve_pages(&page).is_empty());
+        assert!(parse_record_header(&[0x05, 0x01, 0x02, 0x0F], 0, 4).is_none());
+    }
+}
@@ -1,14 +1,43 @@
 use log::{debug, info, warn};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-
-use crate::structs::{Cell, LeafCell, Page};
-
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::structs::{
+    align_storage_classes, select_storage_classes, set_rowid_alias, Cell, LeafCell, Page,
+    Provenance, StorageClass, StorageClasses,
+};
+
+use crate::blobs::{blob_bytes, inspect, BlobInfo};
+use crate::dropped::SchemaEntry;
+use crate::fts5::Fts5Entry;
+use crate::inference::{infer_tables, InferredSchema};
 use crate::mainfile::{MainFile, TableInfo};
-use crate::utils::get_column_names_from_creation_query;
+use crate::predicate::Predicate;
+use crate::schema::{Affinity, TableSchema};
+use crate::timestamps::{iso8601_field, timestamp_columns, Epoch, TimestampConfig};
+use crate::utils::{glob_match, to_hex};
+use crate::vtab::{content_table, VirtualTable};
 use crate::wal::WALFile;
 
-fn diff_pages(first: Option<Page>, second: Page, diff: &mut Diff) -> () {
+/// Values of a record aligned with the columns of the table (missing ones defaulted),
+/// followed by the values exceeding the columns
+fn aligned_values(cell: &LeafCell, schema: &TableSchema) -> Row {
+    let mut values: Row = cell.data();
+    let (_, extra): (Vec<String>, Row) = schema.align_record(&mut values);
+    values.extend(extra);
+    values
+}
+
+/// Whether the values of a record differ from the ones of its previous version, which may have
+/// been written with less columns (before an `ALTER TABLE ... ADD COLUMN`)
+fn is_modified(first: &LeafCell, second: &LeafCell, schema: &TableSchema) -> bool {
+    aligned_values(first, schema) != aligned_values(second, schema)
+}
+
+fn diff_pages(first: Option<Page>, second: Page, diff: &mut Diff, schema: &TableSchema) {
     let mut second_page_rowids = second.get_all_rowids();
     match first {
         Some(f) => {
@@ -23,22 +52,16 @@ fn diff_pages(first: Option<Page>, second: Page, diff: &mut Diff) -> () {
                     let first_cell = f.get_cell_by_rowid(first_page_rowids[i]).unwrap();
                     let second_cell = second.get_cell_by_rowid(first_page_rowids[i]).unwrap();
 
-                    let records1 = first_cell.data();
-                    let records2 = second_cell.data();
-                    for (j, field) in records1.iter().enumerate() {
-                        if field != records2.get(j).unwrap() {
-                            debug!("RECORD IN FIRST WAL PAGE DIFFERENT FORM RECORD IN MAIN DB");
-                            debug!("{:?}", first_cell);
-                            debug!("{:?}", second_cell);
-                            debug!("\t\t\t---\t\t\t");
-
-                            if diff.modifications_contains_rowid(first_page_rowids[i]) {
-                                diff.aggregate_modified_cells(second_cell);
-                            } else {
-                                diff.add_modification(second_cell)
-                            }
+                    if is_modified(&first_cell, &second_cell, schema) {
+                        debug!("RECORD IN FIRST WAL PAGE DIFFERENT FORM RECORD IN MAIN DB");
+                        debug!("{:?}", first_cell);
+                        debug!("{:?}", second_cell);
+                        debug!("\t\t\t---\t\t\t");
 
-                            break;
+                        if diff.modifications_contains_rowid(first_page_rowids[i]) {
+                            diff.aggregate_modified_cells(second_cell);
+                        } else {
+                            diff.add_modification(second_cell)
                         }
                     }
 
@@ -74,12 +97,55 @@ fn diff_pages(first: Option<Page>, second: Page, diff: &mut Diff) -> () {
     };
 }
 
+/// Maximum number of missing rowids listed for a table
+const MAX_MISSING_ROWIDS: usize = 100_000;
+
 type Row = Vec<String>;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ModsSequence {
-    rowid: u32,
+    rowid: i64,
     sequence: Vec<Row>,
+    /// Where each row of the sequence has been read from
+    #[serde(skip)]
+    locations: Vec<Option<Provenance>>,
+    /// Storage classes of the values of each row of the sequence
+    #[serde(skip)]
+    storage_classes: Vec<StorageClasses>,
+    /// Columns missing from each row of the sequence and filled with their DEFAULT value
+    #[serde(default, skip_serializing_if = "all_none")]
+    defaulted_columns: Vec<Option<Vec<String>>>,
+    /// Values of each row of the sequence exceeding the columns of the table
+    #[serde(default, skip_serializing_if = "all_none")]
+    extra_values: Vec<Option<Row>>,
+}
+
+/// Replaces the definitions of the tables altered by a committed transaction of the WAL
+/// (e.g.: `ALTER TABLE ... ADD COLUMN`) with the ones of its version of sqlite_master
+fn apply_wal_schema(
+    db_file: &MainFile,
+    wal_file: &WALFile,
+    table_info: &mut HashMap<String, TableInfo>,
+) {
+    let rows: Vec<Vec<String>> = match db_file.get_wal_schema_rows(wal_file) {
+        Some(r) => r,
+        None => return,
+    };
+    for row in rows.iter() {
+        if let [kind, name, _, _, sql, ..] = row.as_slice() {
+            match table_info.get_mut(name) {
+                Some(info) if kind == "table" && info.sql != *sql => {
+                    warn!("Table {} has been altered in the WAL: {}", name, sql);
+                    info.sql = sql.to_string();
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+fn all_none<T>(values: &[Option<T>]) -> bool {
+    values.iter().all(|v| v.is_none())
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -102,10 +168,14 @@ impl Diff {
         self.modifications.push(ModsSequence {
             rowid: mod_cell.rowid().unwrap(),
             sequence: vec![mod_cell.data()],
+            locations: vec![mod_cell.provenance().cloned()],
+            storage_classes: vec![mod_cell.storage_classes().to_vec()],
+            defaulted_columns: vec![None],
+            extra_values: vec![None],
         })
     }
 
-    fn modifications_contains_rowid(&self, rowid: u32) -> bool {
+    fn modifications_contains_rowid(&self, rowid: i64) -> bool {
         for mod_seq in self.modifications.iter() {
             if mod_seq.rowid == rowid {
                 return true;
@@ -119,35 +189,207 @@ impl Diff {
         for mods_seq in self.modifications.iter_mut() {
             if mods_seq.rowid == cell.rowid().unwrap() {
                 mods_seq.sequence.push(cell.data());
+                mods_seq.locations.push(cell.provenance().cloned());
+                mods_seq
+                    .storage_classes
+                    .push(cell.storage_classes().to_vec());
+                mods_seq.defaulted_columns.push(None);
+                mods_seq.extra_values.push(None);
             }
         }
     }
 
-    fn to_csv(&self) -> String {
-        let mut csv_string = String::from("");
+    fn align(&mut self, schema: &TableSchema) {
+        self.insertions
+            .iter_mut()
+            .for_each(|cell| cell.align(schema));
+        self.deletions
+            .iter_mut()
+            .for_each(|cell| cell.align(schema));
+        self.modifications.iter_mut().for_each(|mods_seq| {
+            for (i, row) in mods_seq.sequence.iter_mut().enumerate() {
+                let (defaulted, extra): (Vec<String>, Row) = schema.align_record(row);
+                match mods_seq.storage_classes.get_mut(i) {
+                    Some(classes) if !classes.is_empty() => align_storage_classes(classes, schema),
+                    _ => (),
+                }
+                if !defaulted.is_empty() {
+                    mods_seq.defaulted_columns[i] = Some(defaulted);
+                }
+                if !extra.is_empty() {
+                    debug!(
+                        "{}: modified row {} has {} values exceeding table columns",
+                        schema.name,
+                        mods_seq.rowid,
+                        extra.len()
+                    );
+                    mods_seq.extra_values[i] = Some(extra);
+                }
+            }
+        });
+    }
+
+    fn drop_values(&mut self, count: usize) {
+        self.insertions
+            .iter_mut()
+            .for_each(|cell| cell.drop_values(count));
+        self.deletions
+            .iter_mut()
+            .for_each(|cell| cell.drop_values(count));
+        self.modifications.iter_mut().for_each(|mods_seq| {
+            mods_seq.sequence.iter_mut().for_each(|row| {
+                row.drain(..count.min(row.len()));
+            });
+            mods_seq.storage_classes.iter_mut().for_each(|classes| {
+                classes.drain(..count.min(classes.len()));
+            });
+        });
+    }
+
+    fn select_values(&mut self, positions: &[usize]) {
+        self.insertions
+            .iter_mut()
+            .for_each(|cell| cell.select_values(positions));
+        self.deletions
+            .iter_mut()
+            .for_each(|cell| cell.select_values(positions));
+        self.modifications.iter_mut().for_each(|mods_seq| {
+            mods_seq.sequence.iter_mut().for_each(|row| {
+                *row = positions
+                    .iter()
+                    .map(|&i| match row.get(i) {
+                        Some(v) => v.to_string(),
+                        None => String::from("NULL"),
+                    })
+                    .collect();
+            });
+            mods_seq
+                .storage_classes
+                .iter_mut()
+                .for_each(|classes| *classes = select_storage_classes(classes, positions));
+        });
+    }
+
+    /// Keeps the sequences of modifications in which at least a row matches
+    fn retain_matching(
+        &mut self,
+        matches: impl Fn(Option<i64>, &[String], &[Option<StorageClass>]) -> bool,
+    ) {
+        self.insertions
+            .retain(|cell| matches(cell.rowid(), &cell.data(), cell.storage_classes()));
+        self.deletions
+            .retain(|cell| matches(cell.rowid(), &cell.data(), cell.storage_classes()));
+        self.modifications.retain(|mods_seq| {
+            mods_seq.sequence.iter().enumerate().any(|(i, row)| {
+                let classes: &[Option<StorageClass>] = match mods_seq.storage_classes.get(i) {
+                    Some(c) => c,
+                    None => &[],
+                };
+                matches(Some(mods_seq.rowid), row, classes)
+            })
+        });
+    }
+
+    fn retain_rowids(&mut self, from: i64, to: i64) {
+        let in_range = |cell: &LeafCell| -> bool {
+            match cell.rowid() {
+                Some(rowid) => rowid >= from && rowid <= to,
+                None => true,
+            }
+        };
+        self.insertions.retain(in_range);
+        self.deletions.retain(in_range);
+        self.modifications
+            .retain(|mods_seq| mods_seq.rowid >= from && mods_seq.rowid <= to);
+    }
+
+    fn set_rowid_alias(&mut self, column: usize) {
+        self.insertions
+            .iter_mut()
+            .for_each(|cell| cell.set_rowid_alias(column));
+        self.deletions
+            .iter_mut()
+            .for_each(|cell| cell.set_rowid_alias(column));
+        self.modifications.iter_mut().for_each(|mods_seq| {
+            let rowid: i64 = mods_seq.rowid;
+            mods_seq
+                .storage_classes
+                .resize(mods_seq.sequence.len(), vec![]);
+            mods_seq
+                .sequence
+                .iter_mut()
+                .zip(mods_seq.storage_classes.iter_mut())
+                .for_each(|(row, classes)| set_rowid_alias(row, classes, column, rowid))
+        });
+    }
+
+    /// All the rows found in WAL frames, with their rowid
+    pub fn rows(&self) -> Vec<(Option<i64>, Row)> {
+        let mut rows: Vec<(Option<i64>, Row)> = vec![];
+        self.insertions
+            .iter()
+            .chain(self.deletions.iter())
+            .for_each(|cell| rows.push((cell.rowid(), cell.data())));
+        self.modifications.iter().for_each(|mods_seq| {
+            mods_seq
+                .sequence
+                .iter()
+                .for_each(|row| rows.push((Some(mods_seq.rowid), row.to_vec())))
+        });
+
+        rows
+    }
+
+    /// Where the rows of the WAL have been read from, in the same order as `to_csv_records`
+    fn provenances(&self) -> Vec<Option<Provenance>> {
+        let mut provenances: Vec<Option<Provenance>> = self
+            .insertions
+            .iter()
+            .chain(self.deletions.iter())
+            .map(|cell| cell.provenance().cloned())
+            .collect();
+        self.modifications.iter().for_each(|mods_seq| {
+            for i in 0..mods_seq.sequence.len() {
+                provenances.push(mods_seq.locations.get(i).cloned().flatten());
+            }
+        });
+
+        provenances
+    }
+
+    /// Rows of the WAL, followed by the inserted, deleted and modified flags
+    fn to_csv_records(&self) -> Vec<Vec<String>> {
+        let mut records: Vec<Vec<String>> = vec![];
+        let flags = |inserted: &str, deleted: &str, modified: &str| -> Vec<String> {
+            vec![
+                inserted.to_string(),
+                deleted.to_string(),
+                modified.to_string(),
+            ]
+        };
 
         self.insertions.iter().for_each(|ins| {
-            csv_string.push_str(ins.to_csv().as_str());
-            csv_string.push_str("1;0;0\n");
+            let mut record: Vec<String> = ins.to_csv_record();
+            record.extend(flags("1", "0", "0"));
+            records.push(record);
         });
 
         self.deletions.iter().for_each(|del| {
-            csv_string.push_str(del.to_csv().as_str());
-            csv_string.push_str("0;1;0\n");
+            let mut record: Vec<String> = del.to_csv_record();
+            record.extend(flags("0", "1", "0"));
+            records.push(record);
         });
 
         self.modifications.iter().for_each(|modif| {
             modif.sequence.iter().for_each(|mod_row| {
-                let mut row_csv = String::from(format!("{};", modif.rowid));
-                mod_row
-                    .iter()
-                    .for_each(|col| row_csv.push_str(format!("{};", col).as_str()));
-                csv_string.push_str(row_csv.as_str());
-                csv_string.push_str("0;0;1\n");
+                let mut record: Vec<String> = vec![modif.rowid.to_string()];
+                record.extend(mod_row.iter().cloned());
+                record.extend(flags("0", "0", "1"));
+                records.push(record);
             });
         });
 
-        csv_string
+        records
     }
 }
 
@@ -161,14 +403,272 @@ impl std::fmt::Debug for Diff {
     }
 }
 
+/// Where a row comes from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RowStatus {
+    Live,
+    Inserted,
+    Deleted,
+    Modified,
+    /// Row of a table dropped from sqlite_master, read from the freelist
+    Dropped,
+    /// Record carved from a page which does not belong to a table (e.g.: unallocated space)
+    Carved,
+}
+
+impl RowStatus {
+    /// Status written in the provenance columns of rebuilt databases
+    pub fn label(&self) -> &'static str {
+        match self {
+            RowStatus::Live => "live",
+            RowStatus::Inserted => "wal-inserted",
+            RowStatus::Deleted => "deleted",
+            RowStatus::Modified => "wal-modified",
+            RowStatus::Dropped => "dropped",
+            RowStatus::Carved => "carved",
+        }
+    }
+}
+
+/// Tables, columns and rows kept when extracting tables
+#[derive(Clone, Default)]
+pub struct RowFilter {
+    /// Statuses of the rows to keep (all of them, if none)
+    pub statuses: Option<Vec<RowStatus>>,
+    /// Globs of the names of the tables to extract (all of them, if empty)
+    pub tables: Vec<String>,
+    /// Globs of the names of the tables not to extract
+    pub exclude_tables: Vec<String>,
+    /// Columns to extract, by table name (all of them for the other tables)
+    pub columns: HashMap<String, Vec<String>>,
+    /// Inclusive range of the rowids of the rows to keep (rows without rowid are always kept)
+    pub rowids: Option<(i64, i64)>,
+    /// Condition the values of the rows to keep must satisfy
+    pub predicate: Option<Predicate>,
+}
+
+impl RowFilter {
+    pub fn selects_table(&self, name: &str) -> bool {
+        (self.tables.is_empty()
+            || self
+                .tables
+                .iter()
+                .any(|g| glob_match(g, name, '*', '?', true)))
+            && !self
+                .exclude_tables
+                .iter()
+                .any(|g| glob_match(g, name, '*', '?', true))
+    }
+
+    /// Columns to extract from a table, if not all of them
+    pub fn selected_columns(&self, name: &str) -> Option<&Vec<String>> {
+        self.columns
+            .iter()
+            .find(|(table, _)| table.eq_ignore_ascii_case(name))
+            .map(|(_, columns)| columns)
+    }
+
+    /// Drops the rows and columns of the table which have not been requested
+    fn apply(&self, table: &mut Table) {
+        if let Some((from, to)) = self.rowids {
+            table.retain_rowids(from, to);
+        }
+        if let Some(statuses) = &self.statuses {
+            table.retain_statuses(statuses);
+        }
+        if let Some(predicate) = &self.predicate {
+            table.retain_matching(predicate);
+        }
+        if let Some(columns) = self.selected_columns(&table.name) {
+            table.select_columns(columns);
+        }
+    }
+}
+
+/// A row serialized as an object keyed by column name, plus its rowid and provenance
+pub struct KeyedRow<'a> {
+    table: &'a Table,
+    pub rowid: Option<i64>,
+    pub status: RowStatus,
+    /// Position of the row in the sequence of modifications of the same rowid
+    pub sequence: Option<usize>,
+    pub values: &'a [String],
+    /// Storage classes of the values, if known
+    pub storage_classes: &'a [Option<StorageClass>],
+    pub defaulted_columns: Option<&'a [String]>,
+    pub extra_values: Option<&'a [String]>,
+    /// Where the cell has been read from, if known
+    pub provenance: Option<&'a Provenance>,
+}
+
+impl<'a> KeyedRow<'a> {
+    /// Storage class of the value at the given position, if known
+    pub fn storage_class(&self, position: usize) -> Option<StorageClass> {
+        self.storage_classes.get(position).cloned().flatten()
+    }
+
+    fn from_cell(table: &'a Table, cell: &'a LeafCell, status: RowStatus) -> KeyedRow<'a> {
+        KeyedRow {
+            table,
+            rowid: cell.rowid(),
+            status,
+            sequence: None,
+            values: cell.values(),
+            storage_classes: cell.storage_classes(),
+            defaulted_columns: cell.defaulted_columns(),
+            extra_values: cell.extra_values(),
+            provenance: cell.provenance(),
+        }
+    }
+}
+
+impl Serialize for KeyedRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("__ROWID__", &self.rowid)?;
+        map.serialize_entry("__STATUS__", &self.status)?;
+        if let Some(sequence) = self.sequence {
+            map.serialize_entry("__SEQUENCE__", &sequence)?;
+        }
+        for (column, value) in self.table.columns.iter().zip(self.values.iter()) {
+            map.serialize_entry(column, value)?;
+            if let Some(epoch) = self.table.timestamp_format(column) {
+                map.serialize_entry(&iso8601_field(column), &epoch.decode(value))?;
+            }
+            if self.table.is_blob_column(column) {
+                map.serialize_entry(&format!("{}_blob", column), &self.table.blob_info(value))?;
+            }
+        }
+        if let Some(defaulted) = &self.defaulted_columns {
+            map.serialize_entry("__DEFAULTED__", defaulted)?;
+        }
+        if let Some(extra) = &self.extra_values {
+            map.serialize_entry("__EXTRA__", extra)?;
+        }
+        if let Some(source) = &self.table.provenance_source {
+            map.serialize_entry(
+                "__PROVENANCE__",
+                &self
+                    .provenance
+                    .as_ref()
+                    .map(|p| ProvenanceRecord::new(p, source, self.table.raw_cells)),
+            )?;
+        }
+        map.end()
+    }
+}
+
+/// Provenance of a row, as written in the output
+#[derive(Serialize)]
+struct ProvenanceRecord {
+    file: String,
+    /// Unknown for carved records
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<u32>,
+    offset: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wal_frame: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wal_frame_offset: Option<usize>,
+    /// Bytes of the cell, in hex
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw: Option<String>,
+}
+
+impl ProvenanceRecord {
+    fn new(provenance: &Provenance, source: &str, raw_cells: bool) -> ProvenanceRecord {
+        ProvenanceRecord {
+            file: match provenance.from_wal {
+                true => format!("{}-wal", source),
+                false => source.to_string(),
+            },
+            page: provenance.page,
+            offset: provenance.offset,
+            wal_frame: provenance.wal_frame,
+            wal_frame_offset: provenance.wal_frame_offset,
+            raw: match raw_cells {
+                true => Some(to_hex(&provenance.raw)),
+                false => None,
+            },
+        }
+    }
+
+    /// Values of the provenance columns of CSV files
+    fn to_csv_values(&self) -> Vec<String> {
+        let optional = |value: Option<usize>| -> String {
+            match value {
+                Some(v) => v.to_string(),
+                None => String::new(),
+            }
+        };
+        let mut values: Vec<String> = vec![
+            self.file.to_string(),
+            optional(self.page.map(|p| p as usize)),
+            self.offset.to_string(),
+            optional(self.wal_frame.map(|f| f as usize)),
+            optional(self.wal_frame_offset),
+        ];
+        if let Some(raw) = &self.raw {
+            values.push(raw.to_string());
+        }
+
+        values
+    }
+}
+
+#[derive(Serialize)]
+struct KeyedTable<'a> {
+    name: &'a str,
+    columns: &'a [String],
+    rows_count: usize,
+    rows: Vec<KeyedRow<'a>>,
+    missing_rowids: &'a Option<Vec<i64>>,
+    /// Name of the virtual table this shadow table belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shadow_of: &'a Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dropped: &'a Option<SchemaEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamps: &'a Option<BTreeMap<String, Epoch>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blob_columns: &'a Option<BTreeSet<String>>,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Table {
     pub name: String,
     pub columns: Vec<String>,
     pub rows_count: usize,
     pub rows: Vec<LeafCell>,
-    pub missing_rowids: Option<Vec<u32>>,
+    pub missing_rowids: Option<Vec<i64>>,
     pub wal: Option<Diff>,
+    /// Name of the virtual table this shadow table belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow_of: Option<String>,
+    /// Entry of sqlite_master, recovered from free space, of the dropped table the rows come from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dropped: Option<SchemaEntry>,
+    /// The rows have been carved from pages not belonging to the table, grouped by inferred schema
+    #[serde(skip)]
+    pub carved: bool,
+    /// Formats of the timestamp columns, whose values get an ISO-8601 companion field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamps: Option<BTreeMap<String, Epoch>>,
+    /// Columns holding blobs, whose values get a companion field describing their content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob_columns: Option<BTreeSet<String>>,
+    /// Plists, protobuf messages and JSON documents of the blobs are decoded
+    #[serde(skip)]
+    pub decode_blobs: bool,
+    /// Path of the main file, if the provenance of rows is written in the output
+    #[serde(skip)]
+    pub provenance_source: Option<String>,
+    /// The bytes of the cells are written with their provenance
+    #[serde(skip)]
+    pub raw_cells: bool,
+    #[serde(skip)]
+    pub schema: TableSchema,
 }
 
 impl Table {
@@ -177,19 +677,18 @@ impl Table {
         wal_file: &Option<WALFile>,
         table_name: String,
         info: &TableInfo,
+        schema: TableSchema,
+        rowids: Option<(i64, i64)>,
     ) -> Result<Table, &'static str> {
         debug!("{} - {}", table_name, info.sql);
-        let columns: Vec<String> = match get_column_names_from_creation_query(&(info.sql)) {
-            Ok(columns) => columns,
-            Err(e) => return Err(e),
-        };
+        let columns: Vec<String> = schema.column_names();
 
         debug!("{} - {:?} - {}", table_name, columns, columns.len());
 
         let mut rows: Vec<LeafCell> = vec![];
         let root_page_num: u32 = info.root_page.parse::<u32>().unwrap();
         let (mut leaves, mut internals): (Vec<u32>, Vec<u32>) =
-            Table::init_leaf_internal_array(&db_file, root_page_num);
+            Table::init_leaf_internal_array(db_file, root_page_num, rowids);
         debug!("LEAVES: {:?}\nINTERNALS: {:?}", leaves, internals);
         for &page_num in leaves.iter() {
             for cell in db_file
@@ -207,7 +706,7 @@ impl Table {
             }
         }
 
-        let wal: Option<Diff> = match wal_file.as_ref() {
+        let mut wal: Option<Diff> = match wal_file.as_ref() {
             Some(f) => {
                 /* Check if in WAL has been added/removed pages linked to current table */
                 let diff = &mut Diff {
@@ -228,11 +727,17 @@ impl Table {
                             Some(p) => Some(p.clone()),
                             None => db_file.get_page_by_number(page_num),
                         };
-                        diff_pages(previous_page, frame.page(), diff);
+                        diff_pages(previous_page, frame.page(), diff, &schema);
 
                         previous.insert(page_num, frame.page());
                     } else if internals.contains(&page_num) {
-                        Table::update_arrays(&db_file, frame.page(), &mut leaves, &mut internals);
+                        Table::update_arrays(
+                            db_file,
+                            frame.page(),
+                            rowids,
+                            &mut leaves,
+                            &mut internals,
+                        );
                         debug!("LEAVES: {:?}\nINTERNALS: {:?}", leaves, internals);
                     }
                 }
@@ -242,6 +747,20 @@ impl Table {
             None => None,
         };
 
+        /* Records written before an ALTER TABLE ... ADD COLUMN are shorter than the schema */
+        rows.iter_mut().for_each(|row| row.align(&schema));
+        if let Some(diff) = wal.as_mut() {
+            diff.align(&schema);
+        }
+
+        /* INTEGER PRIMARY KEY columns are stored as NULL: their value is the rowid */
+        if let Some(column) = schema.rowid_alias {
+            rows.iter_mut().for_each(|row| row.set_rowid_alias(column));
+            if let Some(diff) = wal.as_mut() {
+                diff.set_rowid_alias(column);
+            }
+        }
+
         let rows_count = rows.len();
 
         Ok(Table {
@@ -251,19 +770,112 @@ impl Table {
             rows_count,
             missing_rowids: None,
             wal,
+            shadow_of: None,
+            dropped: None,
+            carved: false,
+            timestamps: None,
+            blob_columns: None,
+            decode_blobs: false,
+            provenance_source: None,
+            raw_cells: false,
+            schema,
         })
     }
 
-    fn init_leaf_internal_array(db_file: &MainFile, root_page_num: u32) -> (Vec<u32>, Vec<u32>) {
+    /// Creates a table from rows which have not been read from its own b-tree, aligning their
+    /// records with the schema before setting the rowid alias, as `Table::new` does
+    pub fn from_rows(schema: TableSchema, mut rows: Vec<LeafCell>) -> Table {
+        rows.iter_mut().for_each(|row| row.align(&schema));
+        if let Some(column) = schema.rowid_alias {
+            rows.iter_mut().for_each(|row| row.set_rowid_alias(column));
+        }
+
+        Table {
+            name: schema.name.to_string(),
+            columns: schema.column_names(),
+            rows_count: rows.len(),
+            rows,
+            missing_rowids: None,
+            wal: None,
+            shadow_of: None,
+            dropped: None,
+            carved: false,
+            timestamps: None,
+            blob_columns: None,
+            decode_blobs: false,
+            provenance_source: None,
+            raw_cells: false,
+            schema,
+        }
+    }
+
+    /// Creates a table with the content of this one, skipping the first `skip` values of each record
+    pub fn rebuild_as(&self, schema: TableSchema, skip: usize) -> Table {
+        let mut rows: Vec<LeafCell> = self.rows.clone();
+        rows.iter_mut().for_each(|row| {
+            row.drop_values(skip);
+            row.align(&schema);
+        });
+
+        let mut wal: Option<Diff> = self.wal.clone();
+        if let Some(diff) = wal.as_mut() {
+            diff.drop_values(skip);
+            diff.align(&schema);
+        }
+
+        Table {
+            name: schema.name.to_string(),
+            columns: schema.column_names(),
+            rows_count: rows.len(),
+            rows,
+            missing_rowids: None,
+            wal,
+            shadow_of: None,
+            dropped: None,
+            carved: false,
+            timestamps: None,
+            blob_columns: None,
+            decode_blobs: false,
+            provenance_source: None,
+            raw_cells: false,
+            schema,
+        }
+    }
+
+    /// Whether the child page holding the rowids in `(lower, upper]` (no bound if None) may have
+    /// rows in the requested range, if any
+    fn child_in_range(rowids: Option<(i64, i64)>, lower: Option<i64>, upper: Option<i64>) -> bool {
+        match rowids {
+            Some((from, to)) => {
+                upper.is_none_or(|upper| upper >= from) && lower.is_none_or(|lower| lower < to)
+            }
+            None => true,
+        }
+    }
+
+    /// Finds the leaf and interior pages of the b-tree, skipping the children whose keys are
+    /// outside of the range of rowids, if any
+    fn init_leaf_internal_array(
+        db_file: &MainFile,
+        root_page_num: u32,
+        rowids: Option<(i64, i64)>,
+    ) -> (Vec<u32>, Vec<u32>) {
         let mut leaves = vec![];
         let mut internals = vec![];
         let root_page = db_file.get_page_by_number(root_page_num).unwrap();
 
         if root_page.is_internal_table_page() {
             internals.push(root_page_num);
+            let mut lower: Option<i64> = None;
             for cell in root_page.live_cells().iter() {
                 match cell {
                     Cell::ITC(c) => {
+                        let in_range: bool = Table::child_in_range(rowids, lower, Some(c.key()));
+                        lower = Some(c.key());
+                        if !in_range {
+                            debug!("Skipping page {}: rowids out of range", c.left_pointer());
+                            continue;
+                        }
                         if db_file
                             .get_page_by_number(c.left_pointer())
                             .unwrap()
@@ -271,7 +883,7 @@ impl Table {
                         {
                             internals.push(c.left_pointer());
                             let (mut l, mut i) =
-                                Table::init_leaf_internal_array(&db_file, c.left_pointer());
+                                Table::init_leaf_internal_array(db_file, c.left_pointer(), rowids);
                             leaves.append(l.as_mut());
                             internals.append(i.as_mut());
                         } else {
@@ -282,7 +894,9 @@ impl Table {
                 }
             }
 
-            if root_page.header().rightmost_ptr().is_some() {
+            if root_page.header().rightmost_ptr().is_some()
+                && Table::child_in_range(rowids, lower, None)
+            {
                 debug!("OF PAGE:{}", root_page.header().rightmost_ptr().unwrap());
                 let overflow_page_num: u32 = root_page.header().rightmost_ptr().unwrap();
                 if db_file
@@ -292,7 +906,7 @@ impl Table {
                 {
                     internals.push(overflow_page_num);
                     let (mut l, mut i) =
-                        Table::init_leaf_internal_array(&db_file, overflow_page_num);
+                        Table::init_leaf_internal_array(db_file, overflow_page_num, rowids);
                     leaves.append(l.as_mut());
                     internals.append(i.as_mut());
                 } else {
@@ -309,12 +923,19 @@ impl Table {
     fn update_arrays(
         db_file: &MainFile,
         page: Page,
+        rowids: Option<(i64, i64)>,
         leaves: &mut Vec<u32>,
         internals: &mut Vec<u32>,
     ) -> () {
+        let mut lower: Option<i64> = None;
         for cell in page.live_cells().iter() {
             match cell {
                 Cell::ITC(c) => {
+                    let in_range: bool = Table::child_in_range(rowids, lower, Some(c.key()));
+                    lower = Some(c.key());
+                    if !in_range {
+                        continue;
+                    }
                     if db_file.get_page_by_number(c.left_pointer()).is_none() {
                         leaves.push(c.left_pointer());
                     } else if db_file
@@ -336,7 +957,7 @@ impl Table {
             }
         }
 
-        if page.header().rightmost_ptr().is_some() {
+        if page.header().rightmost_ptr().is_some() && Table::child_in_range(rowids, lower, None) {
             let overflow_page_num: u32 = page.header().rightmost_ptr().unwrap();
             if db_file.get_page_by_number(overflow_page_num).is_none() {
                 leaves.push(overflow_page_num);
@@ -346,7 +967,8 @@ impl Table {
                 .is_internal_table_page()
             {
                 internals.push(overflow_page_num);
-                let (mut l, mut i) = Table::init_leaf_internal_array(&db_file, overflow_page_num);
+                let (mut l, mut i) =
+                    Table::init_leaf_internal_array(db_file, overflow_page_num, rowids);
                 leaves.append(l.as_mut());
                 internals.append(i.as_mut());
             } else {
@@ -355,30 +977,286 @@ impl Table {
         }
     }
 
+    /// Keeps only the given columns (in the order of the table), dropping unknown ones
+    pub fn select_columns(&mut self, columns: &[String]) {
+        for column in columns.iter() {
+            if !self.columns.iter().any(|c| c.eq_ignore_ascii_case(column)) {
+                warn!("{}: column {} not found", self.name, column);
+            }
+        }
+        let positions: Vec<usize> = (0..self.columns.len())
+            .filter(|&i| {
+                columns
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(&self.columns[i]))
+            })
+            .collect();
+
+        self.rows
+            .iter_mut()
+            .for_each(|row| row.select_values(&positions));
+        if let Some(diff) = &mut self.wal {
+            diff.select_values(&positions);
+        }
+        self.columns = positions
+            .iter()
+            .map(|&i| self.columns[i].to_string())
+            .collect();
+        self.schema.rowid_alias = self
+            .schema
+            .rowid_alias
+            .and_then(|alias| positions.iter().position(|&i| i == alias));
+    }
+
+    /// Drops the rows (and missing rowids) whose rowid is outside of the range (bounds included)
+    pub fn retain_rowids(&mut self, from: i64, to: i64) {
+        self.rows.retain(|row| match row.rowid() {
+            Some(rowid) => rowid >= from && rowid <= to,
+            None => true,
+        });
+        self.rows_count = self.rows.len();
+        self.missing_rowids = self.missing_rowids.take().and_then(|mut missing| {
+            missing.retain(|rowid| (from..=to).contains(rowid));
+            (!missing.is_empty()).then_some(missing)
+        });
+        if let Some(diff) = &mut self.wal {
+            diff.retain_rowids(from, to);
+        }
+    }
+
+    /// Drops the rows (live, WAL and recovered) whose values do not satisfy the condition.
+    /// Sequences of modifications are kept whole if one of their rows does
+    pub fn retain_matching(&mut self, predicate: &Predicate) {
+        let unknown: Vec<String> = predicate
+            .columns()
+            .into_iter()
+            .filter(|c| {
+                !self
+                    .columns
+                    .iter()
+                    .any(|column| column.eq_ignore_ascii_case(c))
+            })
+            .filter(|c| !["rowid", "_rowid_", "oid"].contains(&c.to_lowercase().as_str()))
+            .collect();
+        if !unknown.is_empty() {
+            warn!(
+                "{}: columns {:?} not found, no row kept",
+                self.name, unknown
+            );
+            self.retain_statuses(&[]);
+            return;
+        }
+
+        let affinities: Vec<Option<Affinity>> = self
+            .columns
+            .iter()
+            .map(|column| {
+                self.schema
+                    .columns
+                    .iter()
+                    .find(|c| &c.name == column)
+                    .map(|c| c.affinity.clone())
+            })
+            .collect();
+        let matches =
+            |rowid: Option<i64>, values: &[String], classes: &[Option<StorageClass>]| -> bool {
+                predicate.matches(&self.columns, &affinities, rowid, values, classes)
+            };
+
+        let rows: Vec<LeafCell> = self
+            .rows
+            .iter()
+            .filter(|row| matches(row.rowid(), &row.data(), row.storage_classes()))
+            .cloned()
+            .collect();
+        let wal: Option<Diff> = self.wal.clone().map(|mut diff| {
+            diff.retain_matching(matches);
+            diff
+        });
+        self.rows = rows;
+        self.rows_count = self.rows.len();
+        self.wal = wal;
+    }
+
+    /// Drops the rows whose status is not among the given ones
+    pub fn retain_statuses(&mut self, statuses: &[RowStatus]) {
+        if !statuses.contains(&self.rows_status()) {
+            self.rows.clear();
+            self.rows_count = 0;
+        }
+        if let Some(diff) = &mut self.wal {
+            if !statuses.contains(&RowStatus::Inserted) {
+                diff.insertions.clear();
+            }
+            if !statuses.contains(&RowStatus::Deleted) {
+                diff.deletions.clear();
+            }
+            if !statuses.contains(&RowStatus::Modified) {
+                diff.modifications.clear();
+            }
+        }
+    }
+
+    /// Lists the rowids missing between the first and the last one of the table,
+    /// stopping at MAX_MISSING_ROWIDS of them
     pub fn find_missing_rowids(&mut self) -> () {
-        if self.rows.len() == 0 {
+        if self.rows.is_empty() {
             return;
         }
 
         let first_id = self.rows.first().unwrap().rowid().unwrap();
         let last_id = self.rows.last().unwrap().rowid().unwrap();
 
-        if last_id - first_id != (self.rows.len() - 1) as u32 {
-            let mut missing_ids: Vec<u32> = vec![];
-            let mut i: u32 = first_id;
-            for row in self.rows.iter() {
-                let cur_rowid: u32 = row.rowid().unwrap();
-                if cur_rowid != i {
-                    for j in i..cur_rowid {
-                        missing_ids.push(j);
-                    }
-                    i = cur_rowid;
+        if last_id.checked_sub(first_id) == Some((self.rows.len() - 1) as i64) {
+            return;
+        }
+
+        let mut missing_ids: Vec<i64> = vec![];
+        let mut next: Option<i64> = Some(first_id);
+        for row in self.rows.iter() {
+            let cur_rowid: i64 = row.rowid().unwrap();
+            let mut j: i64 = match next {
+                Some(n) => n,
+                None => break,
+            };
+            while j < cur_rowid {
+                if missing_ids.len() == MAX_MISSING_ROWIDS {
+                    warn!(
+                        "{}: more than {} missing rowids, only the first ones are listed",
+                        self.name, MAX_MISSING_ROWIDS
+                    );
+                    self.missing_rowids = Some(missing_ids);
+                    return;
                 }
-                i += 1;
+                missing_ids.push(j);
+                j += 1;
             }
+            next = cur_rowid.checked_add(1);
+        }
 
-            self.missing_rowids = Some(missing_ids);
+        self.missing_rowids = Some(missing_ids);
+    }
+
+    /// Finds the timestamp columns, so that their values get an ISO-8601 companion
+    pub fn decode_timestamps(&mut self, config: &TimestampConfig) {
+        let timestamps: BTreeMap<String, Epoch> = timestamp_columns(self, Some(config))
+            .into_iter()
+            .map(|(i, epoch)| (self.columns[i].to_string(), epoch))
+            .collect();
+        debug!("{}: timestamp columns {:?}", self.name, timestamps);
+        self.timestamps = Some(timestamps);
+    }
+
+    /// Finds the blob columns, so that their values get a companion field
+    /// with their kind, their hash and (if `decode` is set) their decoded content
+    pub fn inspect_blobs(&mut self, decode: bool) {
+        let blob_columns: BTreeSet<String> = self.find_blob_columns();
+        debug!("{}: blob columns {:?}", self.name, blob_columns);
+        self.blob_columns = Some(blob_columns);
+        self.decode_blobs = decode;
+    }
+
+    /// Writes the blobs of the blob columns to `dir`, each one named by its SHA-256,
+    /// unless already in `written`
+    pub fn extract_blobs(
+        &self,
+        dir: &Path,
+        written: &mut BTreeSet<String>,
+    ) -> Result<(), &'static str> {
+        let positions: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| self.is_blob_column(column))
+            .map(|(i, _)| i)
+            .collect();
+        if positions.is_empty() {
+            return Ok(());
         }
+        for row in self.iter_keyed_rows() {
+            for i in positions.iter() {
+                let bytes: Vec<u8> = match row.values.get(*i).and_then(|v| blob_bytes(v)) {
+                    Some(b) => b,
+                    None => continue,
+                };
+                let file_name: String = inspect(&bytes, false).file_name();
+                if written.contains(&file_name) {
+                    continue;
+                }
+                std::fs::write(dir.join(&file_name), &bytes)
+                    .map_err(|_| "Cannot write blob file")?;
+                written.insert(file_name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds where each row has been read from (file, page, offset, WAL frame and,
+    /// if `raw_cells` is set, the bytes of the cell) to the output
+    pub fn add_provenance(&mut self, source: &str, raw_cells: bool) {
+        self.provenance_source = Some(source.to_string());
+        self.raw_cells = raw_cells;
+    }
+
+    /// Status of the rows read from the b-tree of the table
+    fn rows_status(&self) -> RowStatus {
+        match (&self.dropped, self.carved) {
+            (_, true) => RowStatus::Carved,
+            (Some(_), false) => RowStatus::Dropped,
+            (None, false) => RowStatus::Live,
+        }
+    }
+
+    /// Returns live rows followed by rows inserted, deleted and modified in the WAL file
+    pub fn keyed_rows(&self) -> Vec<KeyedRow<'_>> {
+        self.iter_keyed_rows().collect()
+    }
+
+    /// Same as `keyed_rows`, one row at a time
+    pub fn iter_keyed_rows(&self) -> impl Iterator<Item = KeyedRow<'_>> + '_ {
+        let status: RowStatus = self.rows_status();
+        let (insertions, deletions, modifications): (&[LeafCell], &[LeafCell], &[ModsSequence]) =
+            match &self.wal {
+                Some(diff) => (&diff.insertions, &diff.deletions, &diff.modifications),
+                None => (&[], &[], &[]),
+            };
+
+        self.rows
+            .iter()
+            .map(move |row| KeyedRow::from_cell(self, row, status))
+            .chain(
+                insertions
+                    .iter()
+                    .map(|cell| KeyedRow::from_cell(self, cell, RowStatus::Inserted)),
+            )
+            .chain(
+                deletions
+                    .iter()
+                    .map(|cell| KeyedRow::from_cell(self, cell, RowStatus::Deleted)),
+            )
+            .chain(modifications.iter().flat_map(move |mods_seq| {
+                mods_seq
+                    .sequence
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, row)| KeyedRow {
+                        table: self,
+                        rowid: Some(mods_seq.rowid),
+                        status: RowStatus::Modified,
+                        sequence: Some(i),
+                        values: row,
+                        storage_classes: mods_seq
+                            .storage_classes
+                            .get(i)
+                            .map_or(&[], |c| c.as_slice()),
+                        defaulted_columns: mods_seq
+                            .defaulted_columns
+                            .get(i)
+                            .and_then(|d| d.as_deref()),
+                        extra_values: mods_seq.extra_values.get(i).and_then(|e| e.as_deref()),
+                        provenance: mods_seq.locations.get(i).and_then(|l| l.as_ref()),
+                    })
+            }))
     }
 
     /*pub fn has_page(&self, page_num: u32) -> bool {
@@ -389,95 +1267,531 @@ impl Table {
         self.deleted_rows = deleted_rows;
     }*/
 
-    pub fn to_csv(&self) -> String {
-        let mut columns = self.columns.clone();
+    /// Column names, preceded by the rowid and followed by the WAL flags if WAL was parsed.
+    /// Timestamp and blob columns are followed by their companion columns
+    pub fn csv_header(&self) -> Vec<String> {
+        let mut header: Vec<String> = vec![String::from("__ROWID__")];
+        for column in self.columns.iter() {
+            header.push(column.to_string());
+            if self.timestamp_format(column).is_some() {
+                header.push(iso8601_field(column));
+            }
+            if self.is_blob_column(column) {
+                header.push(format!("{}_blob_kind", column));
+                header.push(format!("{}_blob_sha256", column));
+                if self.decode_blobs {
+                    header.push(format!("{}_blob_decoded", column));
+                }
+            }
+        }
         if self.wal.is_some() {
-            let mut wal_cols = vec![
+            header.extend([
                 String::from("__INSERTED__"),
                 String::from("__DELETED__"),
                 String::from("__MODIFIED__"),
-            ];
-            columns.append(&mut wal_cols);
-        } else {
-            columns = self.columns.clone();
+            ]);
         }
+        if self.provenance_source.is_some() {
+            header.extend(
+                [
+                    "__FILE__",
+                    "__PAGE__",
+                    "__OFFSET__",
+                    "__WAL_FRAME__",
+                    "__WAL_FRAME_OFFSET__",
+                ]
+                .map(String::from),
+            );
+            if self.raw_cells {
+                header.push(String::from("__RAW__"));
+            }
+        }
+
+        header
+    }
 
-        // Column names
-        let mut csv_string = String::from("__ROWID__;");
-        columns
+    /// Rows of the main file and of the WAL, each one as wide as the header
+    pub fn csv_records(&self) -> Vec<Vec<String>> {
+        let mut records: Vec<Vec<String>> = self
+            .rows
             .iter()
-            .for_each(|col| csv_string.push_str(format!("{col};").as_str()));
-        let _ = csv_string.remove(csv_string.len() - 1); // remove last ','
-        csv_string.push_str("\n");
-
-        // rows from main file
-        self.rows.iter().for_each(|row| {
-            csv_string.push_str(row.to_csv().as_str());
-            if self.wal.is_some() {
-                csv_string.push_str("0;0;0\n");
-            } else {
-                let _ = csv_string.remove(csv_string.len() - 1); // remove last ','
-                csv_string.push_str("\n");
+            .map(|row| {
+                let mut record: Vec<String> = row.to_csv_record();
+                record.resize(self.columns.len() + 1, String::from("NULL"));
+                if self.wal.is_some() {
+                    record.extend(["0", "0", "0"].map(String::from));
+                }
+                record
+            })
+            .collect();
+
+        if let Some(diff) = &self.wal {
+            diff.to_csv_records().into_iter().for_each(|mut record| {
+                // Keep the WAL flags as the last columns
+                let flags: Vec<String> = record.split_off(record.len() - 3);
+                record.resize(self.columns.len() + 1, String::from("NULL"));
+                record.extend(flags);
+                records.push(record);
+            });
+        }
+
+        if let Some(source) = &self.provenance_source {
+            let mut provenances: Vec<Option<Provenance>> =
+                self.rows.iter().map(|r| r.provenance().cloned()).collect();
+            if let Some(diff) = &self.wal {
+                provenances.extend(diff.provenances());
             }
-        });
+            let width: usize = 5 + self.raw_cells as usize;
+            for (record, provenance) in records.iter_mut().zip(provenances.iter()) {
+                match provenance {
+                    Some(p) => record
+                        .extend(ProvenanceRecord::new(p, source, self.raw_cells).to_csv_values()),
+                    None => record.resize(record.len() + width, String::new()),
+                }
+            }
+        }
 
-        // rows from wal file
-        if self.wal.is_some() {
-            csv_string.push_str(self.wal.as_ref().unwrap().to_csv().as_str());
+        let has_companions: bool = self.timestamps.as_ref().is_some_and(|t| !t.is_empty())
+            || self.blob_columns.as_ref().is_some_and(|b| !b.is_empty());
+        match has_companions {
+            true => records
+                .into_iter()
+                .map(|record| self.add_companion_values(record))
+                .collect(),
+            false => records,
         }
+    }
+
+    fn timestamp_format(&self, column: &str) -> Option<&Epoch> {
+        self.timestamps.as_ref().and_then(|t| t.get(column))
+    }
 
-        //println!("{csv_string}");
-        csv_string
+    fn is_blob_column(&self, column: &str) -> bool {
+        self.blob_columns
+            .as_ref()
+            .is_some_and(|b| b.contains(column))
+    }
+
+    /// Kind, hash and (if requested) decoded content of a blob value
+    fn blob_info(&self, value: &str) -> Option<BlobInfo> {
+        blob_bytes(value).map(|bytes| inspect(&bytes, self.decode_blobs))
+    }
+
+    /// Inserts the companion values (ISO-8601 timestamp, blob kind, hash and content)
+    /// after the value of each timestamp and blob column of a CSV record
+    fn add_companion_values(&self, record: Vec<String>) -> Vec<String> {
+        let mut extended: Vec<String> = Vec::with_capacity(record.len());
+        for (i, value) in record.into_iter().enumerate() {
+            // The first value is the rowid
+            let column: Option<&String> = match i {
+                0 => None,
+                i => self.columns.get(i - 1),
+            };
+            let mut companions: Vec<String> = vec![];
+            if let Some(epoch) = column.and_then(|c| self.timestamp_format(c)) {
+                companions.push(epoch.decode(&value).unwrap_or_default());
+            }
+            if column.is_some_and(|c| self.is_blob_column(c)) {
+                let info: Option<BlobInfo> = self.blob_info(&value);
+                companions.push(match &info {
+                    Some(i) => i.kind.name().to_string(),
+                    None => String::new(),
+                });
+                companions.push(
+                    info.as_ref()
+                        .map(|i| i.sha256.to_string())
+                        .unwrap_or_default(),
+                );
+                if self.decode_blobs {
+                    companions.push(
+                        info.and_then(|i| i.decoded)
+                            .map(|d| d.to_string())
+                            .unwrap_or_default(),
+                    );
+                }
+            }
+            extended.push(value);
+            extended.extend(companions);
+        }
+
+        extended
+    }
+
+    /// Columns with values stored as blobs, or declared as BLOB
+    fn find_blob_columns(&self) -> BTreeSet<String> {
+        let mut positions: BTreeSet<usize> = BTreeSet::new();
+        let mut cells: Vec<&LeafCell> = self.rows.iter().collect();
+        if let Some(diff) = &self.wal {
+            cells.extend(diff.insertions.iter());
+            cells.extend(diff.deletions.iter());
+        }
+        for cell in cells.into_iter() {
+            positions.extend(cell.blob_positions().iter());
+        }
+
+        self.columns
+            .iter()
+            .enumerate()
+            .filter(|(i, column)| {
+                positions.contains(i)
+                    || self.schema.columns.iter().any(|c| {
+                        &&c.name == column && c.declared_type.to_uppercase().contains("BLOB")
+                    })
+            })
+            .map(|(_, column)| column.to_string())
+            .collect()
     }
 }
 
 type Trigger = String;
 
+#[derive(Serialize)]
+struct KeyedDataBase<'a> {
+    tables: Vec<KeyedTable<'a>>,
+    virtual_tables: &'a Vec<VirtualTable>,
+    triggers: &'a Option<Vec<Trigger>>,
+}
+
+/// A line of the NDJSON output, tagged by its record type
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonLine<'a> {
+    Table {
+        name: &'a str,
+        columns: &'a [String],
+        rows_count: usize,
+        missing_rowids: &'a Option<Vec<i64>>,
+        shadow_of: &'a Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dropped: &'a Option<SchemaEntry>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamps: &'a Option<BTreeMap<String, Epoch>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        blob_columns: &'a Option<BTreeSet<String>>,
+    },
+    /// The values are nested, so that columns named `type` or `table` do not clash with the tag
+    Row {
+        table: &'a str,
+        values: KeyedRow<'a>,
+    },
+    VirtualTable {
+        name: &'a str,
+        module: &'a str,
+        arguments: &'a [String],
+        columns: &'a [String],
+        shadow_tables: &'a [String],
+        content_table: &'a Option<String>,
+    },
+    Fts5Entry {
+        table: &'a str,
+        #[serde(flatten)]
+        entry: &'a Fts5Entry,
+    },
+    Trigger {
+        sql: &'a str,
+    },
+}
+
+/// Writes JSON Lines one record at a time (table, row, virtual table, FTS5 entry, trigger),
+/// so that the output is never held in memory as a whole
+pub struct NdjsonWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> NdjsonWriter<W> {
+    pub fn new(writer: W) -> NdjsonWriter<W> {
+        NdjsonWriter { writer }
+    }
+
+    fn write_line(&mut self, line: &JsonLine) -> Result<(), &'static str> {
+        serde_json::to_writer(&mut self.writer, line)
+            .map_err(|_| "Error serializing NDJSON line")?;
+        self.writer
+            .write_all(b"\n")
+            .map_err(|_| "Error writing NDJSON line")
+    }
+
+    /// Writes the table followed by its rows
+    pub fn write_table(&mut self, table: &Table) -> Result<(), &'static str> {
+        self.write_line(&JsonLine::Table {
+            name: &table.name,
+            columns: &table.columns,
+            rows_count: table.rows_count,
+            missing_rowids: &table.missing_rowids,
+            shadow_of: &table.shadow_of,
+            dropped: &table.dropped,
+            timestamps: &table.timestamps,
+            blob_columns: &table.blob_columns,
+        })?;
+        for row in table.iter_keyed_rows() {
+            self.write_line(&JsonLine::Row {
+                table: &table.name,
+                values: row,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Writes the virtual table followed by the entries of its FTS5 index
+    pub fn write_virtual_table(&mut self, vtable: &VirtualTable) -> Result<(), &'static str> {
+        self.write_line(&JsonLine::VirtualTable {
+            name: &vtable.name,
+            module: &vtable.module,
+            arguments: &vtable.arguments,
+            columns: &vtable.columns,
+            shadow_tables: &vtable.shadow_tables,
+            content_table: &vtable.content_table,
+        })?;
+        if let Some(index) = &vtable.fts5_index {
+            for entry in index.entries.iter() {
+                self.write_line(&JsonLine::Fts5Entry {
+                    table: &vtable.name,
+                    entry,
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_trigger(&mut self, trigger: &str) -> Result<(), &'static str> {
+        self.write_line(&JsonLine::Trigger { sql: trigger })
+    }
+
+    pub fn flush(&mut self) -> Result<(), &'static str> {
+        self.writer
+            .flush()
+            .map_err(|_| "Error flushing NDJSON file")
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct DataBase {
     tables: Vec<Table>,
+    virtual_tables: Vec<VirtualTable>,
     //indexes: Vec<Index>,
     triggers: Option<Vec<Trigger>>,
 }
 
+/// What is read from the database besides the rows of its tables
+#[derive(Clone, Copy, Default)]
+pub struct ReadOptions {
+    /// Look for the rowids missing from each table
+    pub missing_ids: bool,
+    pub triggers: bool,
+    pub indices: bool,
+    /// Decode FTS5 indexes, to recover terms of deleted rows
+    pub fts5_index: bool,
+}
+
 impl DataBase {
     pub fn new(
         db_file: MainFile,
         wal_file: Option<WALFile>,
-        add_missing_ids: bool,
-        get_triggers: bool,
-        get_indices: bool,
+        read: ReadOptions,
+        filter: &RowFilter,
     ) -> DataBase {
         let mut tables: Vec<Table> = vec![];
-        let table_info: HashMap<String, TableInfo> = db_file.get_tables_info();
+        let (virtual_tables, triggers): (Vec<VirtualTable>, Option<Vec<Trigger>>) =
+            match DataBase::walk(&db_file, &wal_file, read, filter, |table| {
+                tables.push(table);
+                Ok(())
+            }) {
+                Ok(result) => result,
+                Err(e) => {
+                    warn!("{}", e);
+                    (vec![], None)
+                }
+            };
+
+        DataBase {
+            tables,
+            virtual_tables,
+            triggers,
+        }
+    }
+
+    /// Reads the selected tables (live, rebuilt from virtual tables and dropped ones) one at
+    /// a time and hands each one, filtered, to `visit`: only the tables needed to rebuild the
+    /// selected virtual tables are held in memory together.
+    /// Returns the virtual tables and the triggers.
+    pub fn walk<F>(
+        db_file: &MainFile,
+        wal_file: &Option<WALFile>,
+        read: ReadOptions,
+        filter: &RowFilter,
+        mut visit: F,
+    ) -> Result<(Vec<VirtualTable>, Option<Vec<Trigger>>), &'static str>
+    where
+        F: FnMut(Table) -> Result<(), &'static str>,
+    {
+        let mut table_info: HashMap<String, TableInfo> = db_file.get_tables_info();
+        if let Some(f) = wal_file {
+            apply_wal_schema(db_file, f, &mut table_info);
+        }
+        if let Some(predicate) = &filter.predicate {
+            info!("Keeping rows where {}", predicate.source);
+        }
+
+        /* Schemas */
+        let mut schemas: Vec<(&String, &TableInfo, TableSchema)> = vec![];
+        let mut virtual_schemas: Vec<TableSchema> = vec![];
+        for (table_name, info) in table_info.iter() {
+            let schema: TableSchema = match TableSchema::parse(&info.sql) {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("{}: {}", table_name, e);
+                    continue;
+                }
+            };
+            // Virtual tables have no b-tree: their content is in shadow tables
+            match schema.module {
+                Some(_) if filter.selects_table(table_name) => virtual_schemas.push(schema),
+                Some(_) => debug!("Table {} not selected", table_name),
+                None => schemas.push((table_name, info, schema)),
+            }
+        }
+
+        // Shadow and content tables of the selected virtual tables are needed to rebuild them
+        let shadow_prefixes: Vec<String> = virtual_schemas
+            .iter()
+            .map(|schema| format!("{}_", schema.name.to_lowercase()))
+            .collect();
+        let content_tables: Vec<String> =
+            virtual_schemas.iter().filter_map(content_table).collect();
+        let is_shadow = |name: &str| -> bool {
+            shadow_prefixes
+                .iter()
+                .any(|prefix| name.to_lowercase().starts_with(prefix))
+        };
+        let is_needed = |name: &str| -> bool {
+            is_shadow(name) || content_tables.iter().any(|t| t.eq_ignore_ascii_case(name))
+        };
 
         /* Tables */
         info!("Creating tables...");
-        table_info.iter().for_each(|(table_name, info)| {
+        let mut needed: Vec<Table> = vec![];
+        for (table_name, info, schema) in schemas.into_iter() {
+            if !filter.selects_table(table_name) && !is_needed(table_name) {
+                debug!("Table {} not selected", table_name);
+                continue;
+            }
             debug!("Table: {}", table_name);
-            let mut table: Table =
-                match Table::new(&db_file, &wal_file, table_name.to_string(), info) {
-                    Ok(t) => t,
-                    Err(e) => {
-                        warn!("{}", e);
-                        return;
-                    }
-                };
-            if add_missing_ids {
+
+            // Tables needed by virtual tables are read whole
+            let rowids: Option<(i64, i64)> = match is_needed(table_name) {
+                true => None,
+                false => filter.rowids,
+            };
+            let mut table: Table = match Table::new(
+                db_file,
+                wal_file,
+                table_name.to_string(),
+                info,
+                schema,
+                rowids,
+            ) {
+                Ok(t) => t,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                }
+            };
+            // Rowids of shadow tables are sparse by design (e.g.: FTS5 segment ids)
+            if read.missing_ids && !is_shadow(table_name) {
                 debug!("Looking for missing row ids...");
                 table.find_missing_rowids();
             }
-            tables.push(table);
-        });
+            match is_needed(table_name) {
+                true => needed.push(table),
+                false => {
+                    filter.apply(&mut table);
+                    visit(table)?;
+                }
+            }
+        }
+
+        /* Virtual tables */
+        let mut virtual_tables: Vec<VirtualTable> = vec![];
+        let mut rebuilt: Vec<Table> = vec![];
+        for schema in virtual_schemas.into_iter() {
+            info!("Rebuilding virtual table {}...", schema.name);
+            let mut virtual_table: VirtualTable = VirtualTable::new(schema, &mut needed);
+            if read.fts5_index {
+                virtual_table.decode_fts5_index(&needed);
+            }
+            if let Some(table) = virtual_table.rebuild(&needed) {
+                rebuilt.push(table);
+            }
+            virtual_tables.push(virtual_table);
+        }
+        for mut table in needed.into_iter().chain(rebuilt) {
+            if !filter.selects_table(&table.name) {
+                continue;
+            }
+            filter.apply(&mut table);
+            visit(table)?;
+        }
+
+        /* Dropped tables */
+        let mut names: Vec<String> = table_info.keys().cloned().collect();
+        for dropped in db_file.dropped_tables().iter() {
+            let mut schema: TableSchema = match TableSchema::parse(&dropped.entry.sql) {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("{}: {}", dropped.entry.name, e);
+                    continue;
+                }
+            };
+            if schema.without_rowid || schema.module.is_some() {
+                continue;
+            }
+            // Older definitions of live tables get a different name
+            while names.iter().any(|n| n.eq_ignore_ascii_case(&schema.name)) {
+                schema.name = format!("{}_dropped", schema.name);
+            }
+            names.push(schema.name.to_string());
+            if !filter.selects_table(&schema.name) {
+                continue;
+            }
+
+            let mut table: Table = Table::from_rows(schema, dropped.rows.clone());
+            info!("Dropped table {}: {} rows", table.name, table.rows_count);
+            table.dropped = Some(dropped.entry.clone());
+            filter.apply(&mut table);
+            visit(table)?;
+        }
+
+        /* Records of the other freelist leaf pages, grouped by inferred schema */
+        if !db_file.free_leaf_cells().is_empty() {
+            info!(
+                "Inferring the schema of {} records of freelist pages...",
+                db_file.free_leaf_cells().len()
+            );
+            let known: Vec<TableSchema> = table_info
+                .values()
+                .map(|info| &info.sql)
+                .chain(db_file.dropped_tables().iter().map(|t| &t.entry.sql))
+                .filter_map(|sql| TableSchema::parse(sql).ok())
+                .collect();
+            let (carved, _): (Vec<Table>, Vec<InferredSchema>) =
+                infer_tables(db_file.free_leaf_cells().to_vec(), &known);
+            for mut table in carved.into_iter() {
+                if !filter.selects_table(&table.name) {
+                    continue;
+                }
+                filter.apply(&mut table);
+                visit(table)?;
+            }
+        }
 
         /* Indices */
-        if get_indices {
+        if read.indices {
             // TODO
             warn!("TODO: Index extraction not implemented yet.");
         }
 
         /* Triggers */
-        let triggers: Option<Vec<String>> = match get_triggers {
+        let triggers: Option<Vec<Trigger>> = match read.triggers {
             true => {
                 info!("Getting triggers...");
                 Some(db_file.get_triggers())
@@ -485,17 +1799,98 @@ impl DataBase {
             false => None,
         };
 
-        DataBase { tables, triggers }
+        Ok((virtual_tables, triggers))
     }
 
-    pub fn tables(&self) -> Vec<Table> {
-        self.tables.clone()
+    /// Creates a database holding only the given tables (e.g.: carved ones)
+    pub fn from_tables(tables: Vec<Table>) -> DataBase {
+        DataBase {
+            tables,
+            virtual_tables: vec![],
+            triggers: None,
+        }
+    }
+
+    /// Finds the timestamp columns of each table, so that their values get an ISO-8601 companion
+    pub fn decode_timestamps(&mut self, config: &TimestampConfig) {
+        info!("Looking for timestamp columns...");
+        for table in self.tables.iter_mut() {
+            table.decode_timestamps(config);
+        }
+    }
+
+    /// Finds the blob columns of each table, so that their values get a companion field
+    /// with their kind, their hash and (if `decode` is set) their decoded content
+    pub fn inspect_blobs(&mut self, decode: bool) {
+        info!("Looking for blob columns...");
+        for table in self.tables.iter_mut() {
+            table.inspect_blobs(decode);
+        }
+    }
+
+    /// Writes the blobs of the blob columns to `dir`, each one named by its SHA-256,
+    /// and returns the paths of the files written
+    pub fn extract_blobs(&self, dir: &Path) -> Result<Vec<PathBuf>, &'static str> {
+        std::fs::create_dir_all(dir).map_err(|_| "Cannot create blobs directory")?;
+
+        let mut written: BTreeSet<String> = BTreeSet::new();
+        for table in self.tables.iter() {
+            table.extract_blobs(dir, &mut written)?;
+        }
+
+        Ok(written.iter().map(|name| dir.join(name)).collect())
+    }
+
+    /// Adds where each row has been read from (file, page, offset, WAL frame and,
+    /// if `raw_cells` is set, the bytes of the cell) to the output
+    pub fn add_provenance(&mut self, source: &str, raw_cells: bool) {
+        for table in self.tables.iter_mut() {
+            table.add_provenance(source, raw_cells);
+        }
+    }
+
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+
+    pub fn triggers(&self) -> Option<Vec<String>> {
+        self.triggers.clone()
+    }
+
+    pub fn virtual_tables(&self) -> Vec<VirtualTable> {
+        self.virtual_tables.clone()
     }
 
     pub fn to_json(&self) -> String {
         serde_json::to_string(&self).unwrap()
     }
 
+    /// Same as `to_json`, but each row is an object keyed by column name
+    pub fn to_keyed_json(&self) -> String {
+        let tables: Vec<KeyedTable> = self
+            .tables
+            .iter()
+            .map(|table| KeyedTable {
+                name: &table.name,
+                columns: &table.columns,
+                rows_count: table.rows_count,
+                rows: table.keyed_rows(),
+                missing_rowids: &table.missing_rowids,
+                shadow_of: &table.shadow_of,
+                dropped: &table.dropped,
+                timestamps: &table.timestamps,
+                blob_columns: &table.blob_columns,
+            })
+            .collect();
+
+        serde_json::to_string(&KeyedDataBase {
+            tables,
+            virtual_tables: &self.virtual_tables,
+            triggers: &self.triggers,
+        })
+        .unwrap()
+    }
+
     /*pub fn look_for_missing_ids(&self) -> () {
         trace!("LOOKING FOR MISSING IDS");
         for table in self.tables.iter() {
@@ -506,3 +1901,258 @@ impl DataBase {
         }
     }*/
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{sqlite3, test_dir};
+
+    fn table(sql: &str, rows: Vec<(i64, Vec<&str>)>) -> Table {
+        Table::from_rows(
+            TableSchema::parse(sql).unwrap(),
+            rows.into_iter()
+                .map(|(rowid, values)| {
+                    LeafCell::from_values(
+                        Some(rowid),
+                        values.iter().map(|v| v.to_string()).collect(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn missing_rowids_between_rows() {
+        let mut t: Table = table(
+            "CREATE TABLE t (a)",
+            vec![
+                (1, vec!["x"]),
+                (2, vec!["x"]),
+                (5, vec!["x"]),
+                (7, vec!["x"]),
+            ],
+        );
+        t.find_missing_rowids();
+        assert_eq!(t.missing_rowids, Some(vec![3, 4, 6]));
+
+        let mut contiguous: Table =
+            table("CREATE TABLE t (a)", vec![(1, vec!["x"]), (2, vec!["x"])]);
+        contiguous.find_missing_rowids();
+        assert_eq!(contiguous.missing_rowids, None);
+    }
+
+    #[test]
+    fn missing_rowids_of_sparse_large_rowids() {
+        // Rowids of FTS5 %_data tables: segment id << 37
+        let mut t: Table = table(
+            "CREATE TABLE t_data (id INTEGER PRIMARY KEY, block BLOB)",
+            vec![
+                (1, vec!["1", "x"]),
+                (10, vec!["10", "x"]),
+                (1 << 37, vec!["", "x"]),
+                (2 << 37, vec!["", "x"]),
+            ],
+        );
+        t.find_missing_rowids();
+        let missing: Vec<i64> = t.missing_rowids.unwrap();
+        assert_eq!(missing.len(), MAX_MISSING_ROWIDS);
+        assert_eq!(&missing[..9], &[2, 3, 4, 5, 6, 7, 8, 9, 11]);
+
+        // The span of the rowids overflows an i64
+        let mut extreme: Table = table(
+            "CREATE TABLE t (a)",
+            vec![
+                (i64::MIN, vec!["x"]),
+                (i64::MAX - 1, vec!["x"]),
+                (i64::MAX, vec!["x"]),
+            ],
+        );
+        extreme.find_missing_rowids();
+        assert_eq!(extreme.missing_rowids.unwrap().len(), MAX_MISSING_ROWIDS);
+    }
+
+    #[test]
+    fn modification_of_added_columns() {
+        let schema: TableSchema =
+            TableSchema::parse("CREATE TABLE t (a, b TEXT DEFAULT 'x', c)").unwrap();
+        let cell = |values: &[&str]| {
+            LeafCell::from_values(Some(1), values.iter().map(|v| v.to_string()).collect())
+        };
+
+        // Written before ADD COLUMN b and c, then updated
+        let before: LeafCell = cell(&["1"]);
+        assert!(is_modified(&before, &cell(&["1", "y"]), &schema));
+        assert!(is_modified(&before, &cell(&["1", "x", "2"]), &schema));
+        assert!(is_modified(
+            &cell(&["1", "x", "NULL"]),
+            &cell(&["1", "x", "NULL", "3"]),
+            &schema
+        ));
+        // Only the missing values are written
+        assert!(!is_modified(&before, &cell(&["1", "x"]), &schema));
+        assert!(!is_modified(&before, &cell(&["1", "x", "NULL"]), &schema));
+    }
+
+    #[test]
+    fn rowid_alias_after_generated_columns() {
+        // The virtual column g is not stored: the records hold only id and b
+        let t: Table = table(
+            "CREATE TABLE dead (g AS (b || '!') VIRTUAL, id INTEGER PRIMARY KEY, b TEXT)",
+            vec![(5, vec!["NULL", "x"]), (6, vec!["NULL", "NULL"])],
+        );
+        assert_eq!(t.rows[0].values(), ["NULL", "5", "x"]);
+        assert_eq!(t.rows[1].values(), ["NULL", "6", "NULL"]);
+    }
+
+    #[test]
+    fn provenance_and_raw_cells() {
+        let mut t: Table = table("CREATE TABLE t (a)", vec![(1, vec!["x"])]);
+        t.rows[0].set_location(Some(2), 4093, false, &[0x03, 0x01, 0x02, 0x0F, 0x78]);
+        t.add_provenance("test.db", false);
+        let json: serde_json::Value = serde_json::to_value(t.keyed_rows()).unwrap();
+        assert_eq!(
+            json[0]["__PROVENANCE__"],
+            serde_json::json!({"file": "test.db", "page": 2, "offset": 4093})
+        );
+
+        t.add_provenance("test.db", true);
+        let json: serde_json::Value = serde_json::to_value(t.keyed_rows()).unwrap();
+        assert_eq!(json[0]["__PROVENANCE__"]["raw"], "0301020f78");
+        assert_eq!(
+            t.csv_header()[t.csv_header().len() - 6..],
+            [
+                "__FILE__",
+                "__PAGE__",
+                "__OFFSET__",
+                "__WAL_FRAME__",
+                "__WAL_FRAME_OFFSET__",
+                "__RAW__"
+            ]
+        );
+        assert_eq!(
+            t.csv_records()[0][2..],
+            ["test.db", "2", "4093", "", "", "0301020f78"]
+        );
+    }
+
+    #[test]
+    fn ndjson_row_values_are_nested() {
+        let t: Table = table(
+            "CREATE TABLE t (type, \"table\")",
+            vec![(1, vec!["x", "y"]), (2, vec!["z", "NULL"])],
+        );
+        let mut writer: NdjsonWriter<Vec<u8>> = NdjsonWriter::new(vec![]);
+        writer.write_table(&t).unwrap();
+        writer.write_trigger("CREATE TRIGGER r ...").unwrap();
+        writer.flush().unwrap();
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(writer.writer)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["type"], "table");
+        assert_eq!(lines[0]["name"], "t");
+        assert_eq!(lines[0]["rows_count"], 2);
+        assert_eq!(lines[1]["type"], "row");
+        assert_eq!(lines[1]["table"], "t");
+        assert_eq!(lines[1]["values"]["__ROWID__"], 1);
+        assert_eq!(lines[1]["values"]["type"], "x");
+        assert_eq!(lines[1]["values"]["table"], "y");
+        assert_eq!(lines[2]["values"]["type"], "z");
+        assert_eq!(lines[3]["type"], "trigger");
+    }
+
+    #[test]
+    fn walk_visits_selected_tables() {
+        let dir: PathBuf = test_dir("walk");
+        let db: PathBuf = dir.join("test.db");
+        sqlite3(
+            &db,
+            "CREATE TABLE a (v); INSERT INTO a VALUES (1), (2), (3);
+            CREATE TABLE b (v); INSERT INTO b VALUES (4);
+            CREATE VIRTUAL TABLE docs USING fts5(body);
+            INSERT INTO docs VALUES ('hello world'), ('goodbye');
+            CREATE TRIGGER r AFTER INSERT ON b BEGIN DELETE FROM a; END;",
+        );
+        let bytearray: Vec<u8> = std::fs::read(&db).unwrap();
+        let main_file: MainFile = MainFile::new(&bytearray).unwrap();
+        let read: ReadOptions = ReadOptions {
+            triggers: true,
+            ..ReadOptions::default()
+        };
+        let filter: RowFilter = RowFilter {
+            tables: vec![String::from("a"), String::from("docs")],
+            predicate: Some(Predicate::parse("rowid > 1").unwrap()),
+            ..RowFilter::default()
+        };
+
+        let mut visited: Vec<(String, usize)> = vec![];
+        let (virtual_tables, triggers) = DataBase::walk(&main_file, &None, read, &filter, |t| {
+            visited.push((t.name.to_string(), t.rows_count));
+            Ok(())
+        })
+        .unwrap();
+        // The shadow tables of docs are read to rebuild it, but not visited
+        assert_eq!(
+            visited,
+            vec![(String::from("a"), 2), (String::from("docs"), 1)]
+        );
+        assert_eq!(virtual_tables.len(), 1);
+        assert_eq!(virtual_tables[0].name, "docs");
+        assert_eq!(triggers.unwrap().len(), 1);
+
+        // Errors of the visitor stop the walk
+        let result = DataBase::walk(&main_file, &None, read, &filter, |_| Err("Cannot write"));
+        assert_eq!(result.err(), Some("Cannot write"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn schema_altered_in_the_wal() {
+        let dir: PathBuf = test_dir("wal_schema");
+        let db: PathBuf = dir.join("test.db");
+        sqlite3(
+            &db,
+            concat!(
+                "PRAGMA journal_mode=WAL;\n",
+                "CREATE TABLE t (a); INSERT INTO t VALUES (1);\n",
+                "PRAGMA wal_checkpoint(TRUNCATE);\n",
+                // The WAL is kept when the shell exits
+                ".dbconfig no_ckpt_on_close on\n",
+                "ALTER TABLE t ADD COLUMN extra DEFAULT 'def';\n",
+                "INSERT INTO t VALUES (2, 'x');\n",
+            ),
+        );
+        let bytearray: Vec<u8> = std::fs::read(&db).unwrap();
+        let main_file: MainFile = MainFile::new(&bytearray).unwrap();
+        let wal_bytearray: Vec<u8> = std::fs::read(dir.join("test.db-wal")).unwrap();
+        let wal_file: Option<WALFile> =
+            Some(WALFile::new(&wal_bytearray, wal_bytearray.len() as u64));
+
+        let mut tables: Vec<Table> = vec![];
+        DataBase::walk(
+            &main_file,
+            &wal_file,
+            ReadOptions::default(),
+            &RowFilter::default(),
+            |t| {
+                tables.push(t);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(tables[0].columns, vec!["a", "extra"]);
+        let rows: Vec<serde_json::Value> =
+            serde_json::from_str(&serde_json::to_string(&tables[0].keyed_rows()).unwrap()).unwrap();
+        assert_eq!(rows[0]["extra"], "def");
+        let inserted: &serde_json::Value = &rows[rows.len() - 1];
+        assert_eq!(inserted["__STATUS__"], "inserted");
+        assert_eq!(inserted["extra"], "x");
+        assert!(inserted.get("__EXTRA__").is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
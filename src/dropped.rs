@@ -0,0 +1,570 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::carver::{parse_record_header, RecordShape};
+use crate::constants::{
+    FILE_HEADER_LEN, INTERIOR_BTREE_HEADER_LEN, INTERIOR_TABLE_BTREE_PAGE, LEAF_BTREE_HEADER_LEN,
+    LEAF_TABLE_BTREE_PAGE,
+};
+use crate::structs::{InteriorTableCell, LeafCell, PageHeader};
+use crate::utils::{read_encoded_string, read_varint, STRING_ENCODING};
+
+/// Next trunk page and number of leaf pages
+const TRUNK_HEADER_LEN: usize = 8;
+
+/// Values of the `type` column of sqlite_master
+const SCHEMA_KINDS: [&str; 4] = ["table", "index", "view", "trigger"];
+
+/// A row of sqlite_master recovered from free space
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SchemaEntry {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: String,
+    pub table_name: String,
+    pub root_page: u32,
+    pub sql: String,
+    /// Page where the entry has been found (1 or a freelist page)
+    pub page: u32,
+    /// Absolute offset of the record in the file
+    pub offset: usize,
+    /// No live object has the same name (otherwise this is an older definition of it)
+    pub dropped: bool,
+}
+
+/// Rows of a dropped table, read from the pages of its b-tree still in the freelist
+#[derive(Clone)]
+pub struct DroppedTable {
+    pub entry: SchemaEntry,
+    pub rows: Vec<LeafCell>,
+    /// Freelist pages of its b-tree
+    pub pages: Vec<u32>,
+}
+
+/// Text as stored in the database, in its encoding
+fn encoded(text: &str) -> Vec<u8> {
+    STRING_ENCODING.with(|encoding| match *encoding.borrow() {
+        2 => text.encode_utf16().flat_map(|c| c.to_le_bytes()).collect(),
+        3 => text.encode_utf16().flat_map(|c| c.to_be_bytes()).collect(),
+        _ => text.as_bytes().to_vec(),
+    })
+}
+
+/// Values of SCHEMA_KINDS and the start of the `sql` column, encoded once per scan
+struct SchemaKeywords {
+    kinds: Vec<Vec<u8>>,
+    create: Vec<u8>,
+}
+
+impl SchemaKeywords {
+    fn new() -> SchemaKeywords {
+        SchemaKeywords {
+            kinds: SCHEMA_KINDS.iter().map(|k| encoded(k)).collect(),
+            create: encoded("CREATE"),
+        }
+    }
+}
+
+/// Decodes a sqlite_master record whose serial types start at `offset`. The length of the header
+/// is not read, since the first bytes of a deleted cell are overwritten by the freeblock header:
+/// without `with_kind`, the serial type of `type` is considered overwritten as well and the
+/// length of the value is found from the content
+fn read_schema_record(
+    bytearray: &[u8],
+    offset: usize,
+    with_kind: bool,
+    keywords: &SchemaKeywords,
+) -> Option<(String, String, String, u32, String)> {
+    let count: usize = if with_kind { 5 } else { 4 };
+    let mut serial_types: Vec<i64> = vec![];
+    let mut i: usize = offset;
+    while serial_types.len() < count && i < bytearray.len() {
+        let (serial_type, size): (i64, usize) = read_varint(&bytearray[i..]);
+        serial_types.push(serial_type);
+        i += size;
+    }
+    if serial_types.len() < count {
+        return None;
+    }
+    if !with_kind {
+        let kind: &Vec<u8> = keywords
+            .kinds
+            .iter()
+            .find(|k| bytearray.get(i..i + k.len()) == Some(k.as_slice()))?;
+        serial_types.insert(0, 13 + 2 * kind.len() as i64);
+    }
+
+    // type, name and tbl_name are text, rootpage an integer, sql text or NULL
+    let is_text = |t: i64| t >= 13 && t % 2 == 1;
+    if !is_text(serial_types[0])
+        || !is_text(serial_types[1])
+        || !is_text(serial_types[2])
+        || !matches!(serial_types[3], 0..=4 | 8 | 9)
+        || !(is_text(serial_types[4]) || serial_types[4] == 0)
+    {
+        return None;
+    }
+
+    let mut values: Vec<&[u8]> = vec![];
+    for serial_type in serial_types.iter() {
+        let len: usize = match serial_type {
+            0 | 8 | 9 => 0,
+            1..=4 => *serial_type as usize,
+            t => (*t as usize - 13) / 2,
+        };
+        if i.checked_add(len)? > bytearray.len() {
+            return None;
+        }
+        values.push(&bytearray[i..i + len]);
+        i += len;
+    }
+
+    // Raw bytes are checked first, not to decode garbage
+    if !keywords.kinds.iter().any(|k| k.as_slice() == values[0]) {
+        return None;
+    }
+    let create: &[u8] = &keywords.create;
+    if serial_types[4] != 0
+        && !values[4]
+            .get(..create.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(create))
+    {
+        return None;
+    }
+    let root_page: u32 = match serial_types[3] {
+        9 => 1,
+        0 | 8 => 0,
+        _ => values[3].iter().fold(0, |n, b| (n << 8) | *b as u32),
+    };
+
+    Some((
+        read_encoded_string(values[0]),
+        read_encoded_string(values[1]),
+        read_encoded_string(values[2]),
+        root_page,
+        match serial_types[4] {
+            0 => String::from("NULL"),
+            _ => read_encoded_string(values[4]),
+        },
+    ))
+}
+
+/// Regions of page 1 not used by its cells (absolute offsets): the unallocated space between
+/// the cell pointer array and the cell content area, and the freeblocks
+fn unused_regions(bytearray: &[u8], page_size: usize) -> Vec<(usize, usize)> {
+    let page_end: usize = page_size.min(bytearray.len());
+    if FILE_HEADER_LEN + INTERIOR_BTREE_HEADER_LEN > page_end {
+        return vec![];
+    }
+    let header: PageHeader = PageHeader::new(bytearray, FILE_HEADER_LEN);
+    let header_len: usize = match header.page_type() {
+        LEAF_TABLE_BTREE_PAGE => LEAF_BTREE_HEADER_LEN,
+        INTERIOR_TABLE_BTREE_PAGE => INTERIOR_BTREE_HEADER_LEN,
+        _ => return vec![],
+    };
+    let array_end: usize = FILE_HEADER_LEN + header_len + 2 * header.cell_count() as usize;
+    let content_offset: usize = match header.cell_content_offset() {
+        0 => 65536,
+        c => c as usize,
+    }
+    .min(page_end);
+
+    let mut regions: Vec<(usize, usize)> = vec![];
+    if array_end < content_offset {
+        regions.push((array_end, content_offset));
+    }
+    // Freeblocks are chained in increasing order of offset
+    let mut freeblock: usize = header.first_freeblock_offset() as usize;
+    while freeblock >= content_offset && freeblock + 4 <= page_end {
+        let next: usize =
+            u16::from_be_bytes([bytearray[freeblock], bytearray[freeblock + 1]]) as usize;
+        let size: usize =
+            u16::from_be_bytes([bytearray[freeblock + 2], bytearray[freeblock + 3]]) as usize;
+        regions.push((freeblock, (freeblock + size).min(page_end)));
+        if next <= freeblock {
+            break;
+        }
+        freeblock = next;
+    }
+
+    regions
+}
+
+/// Looks for sqlite_master records in the unused regions of page 1 and in the freelist pages,
+/// keeping the ones which are not live rows of sqlite_master (the same entry found twice is
+/// kept once)
+pub fn recover_schema_entries(
+    bytearray: &[u8],
+    page_size: usize,
+    free_pages: &[u32],
+    live_rows: &[Vec<String>],
+) -> Vec<SchemaEntry> {
+    let mut entries: Vec<SchemaEntry> = vec![];
+    let keywords: SchemaKeywords = SchemaKeywords::new();
+
+    // Freelist pages are unused as a whole
+    let regions: Vec<(u32, usize, usize)> = unused_regions(bytearray, page_size)
+        .into_iter()
+        .map(|(start, end)| (1, start, end))
+        .chain(free_pages.iter().map(|&page| {
+            let start: usize = (page as usize - 1) * page_size;
+            (page, start, (start + page_size).min(bytearray.len()))
+        }))
+        .collect();
+    for (page, start, end) in regions.into_iter() {
+        for offset in start..end {
+            let record: Option<(String, String, String, u32, String)> =
+                read_schema_record(&bytearray[..end], offset, true, &keywords)
+                    .or_else(|| read_schema_record(&bytearray[..end], offset, false, &keywords));
+            let (kind, name, table_name, root_page, sql) = match record {
+                Some(r) => r,
+                None => continue,
+            };
+            let is_live: bool = live_rows.iter().any(|row| {
+                row.len() >= 5
+                    && row[0] == kind
+                    && row[1] == name
+                    && row[2] == table_name
+                    && row[3] == root_page.to_string()
+                    && row[4] == sql
+            });
+            let is_known: bool = entries.iter().any(|e| {
+                e.kind == kind && e.name == name && e.root_page == root_page && e.sql == sql
+            });
+            if is_live || is_known {
+                continue;
+            }
+
+            debug!(
+                "Deleted schema entry at 0x{:02x?}: {} {}",
+                offset, kind, name
+            );
+            entries.push(SchemaEntry {
+                dropped: !live_rows.iter().any(|row| row.get(1) == Some(&name)),
+                kind,
+                name,
+                table_name,
+                root_page,
+                sql,
+                page,
+                offset,
+            });
+        }
+    }
+
+    entries
+}
+
+/// Reads the cell of a table leaf page in the freelist, if its record header is valid
+fn read_leaf_cell(
+    bytearray: &[u8],
+    page: u32,
+    cell_address: usize,
+    page_end: usize,
+    page_size: usize,
+) -> Option<(LeafCell, usize)> {
+    let shape: RecordShape = parse_record_header(bytearray, cell_address, page_end)?;
+    let mut cell: LeafCell =
+        LeafCell::new_in_page(bytearray, cell_address, LEAF_TABLE_BTREE_PAGE, page_size);
+    cell.set_location(
+        Some(page),
+        cell_address,
+        false,
+        &bytearray[cell_address..cell_address + shape.cell_len],
+    );
+    Some((cell, shape.cell_len))
+}
+
+/// Reads the cells left in a freelist trunk page which was a table leaf page: its header and the
+/// first cell pointers are overwritten by the trunk header and its array of leaf pages, so records
+/// are looked for at each offset after the array and the cell pointers left
+fn read_trunk_cells(
+    bytearray: &[u8],
+    page: u32,
+    page_offset: usize,
+    page_size: usize,
+) -> Vec<LeafCell> {
+    let mut cells: Vec<LeafCell> = vec![];
+    let page_end: usize = page_offset + page_size;
+    let count: usize = u32::from_be_bytes([
+        bytearray[page_offset + 4],
+        bytearray[page_offset + 5],
+        bytearray[page_offset + 6],
+        bytearray[page_offset + 7],
+    ]) as usize;
+    if count > (page_size - TRUNK_HEADER_LEN) / 4 {
+        return cells;
+    }
+
+    let mut cell_address: usize = page_offset + TRUNK_HEADER_LEN + 4 * count;
+    // Cell pointers point forward, inside of the page
+    while cell_address + 2 <= page_end {
+        let pointer: usize =
+            u16::from_be_bytes([bytearray[cell_address], bytearray[cell_address + 1]]) as usize;
+        if pointer <= cell_address - page_offset || pointer >= page_size {
+            break;
+        }
+        cell_address += 2;
+    }
+    while cell_address + 4 <= page_end {
+        match read_leaf_cell(bytearray, page, cell_address, page_end, page_size) {
+            Some((cell, cell_len)) => {
+                cells.push(cell);
+                cell_address += cell_len;
+            }
+            None => cell_address += 1,
+        }
+    }
+    debug!("Trunk page {}: {} cells", page, cells.len());
+
+    cells
+}
+
+/// Reads the rows of the table b-tree rooted at a freelist page: pages no longer in the freelist
+/// (reused) and cells with overflow pages (which may have been reused) are skipped, pages turned
+/// into freelist trunk pages are carved.
+/// Returns the rows and the pages of the b-tree read
+pub fn recover_rows(
+    bytearray: &[u8],
+    page_size: usize,
+    free_pages: &[u32],
+    trunk_pages: &[u32],
+    root_page: u32,
+) -> (Vec<LeafCell>, Vec<u32>) {
+    let mut rows: Vec<LeafCell> = vec![];
+    let mut to_visit: Vec<u32> = vec![root_page];
+    let mut visited: Vec<u32> = vec![];
+    let mut skipped_cells: usize = 0;
+
+    while let Some(page) = to_visit.pop() {
+        if visited.contains(&page) || !free_pages.contains(&page) {
+            continue;
+        }
+        visited.push(page);
+
+        let page_offset: usize = (page as usize - 1) * page_size;
+        let page_end: usize = page_offset + page_size;
+        if page_end > bytearray.len() {
+            continue;
+        }
+        if trunk_pages.contains(&page) {
+            rows.extend(read_trunk_cells(bytearray, page, page_offset, page_size));
+            continue;
+        }
+        let header: PageHeader = PageHeader::new(bytearray, page_offset);
+        let header_len: usize = match header.page_type() {
+            LEAF_TABLE_BTREE_PAGE => LEAF_BTREE_HEADER_LEN,
+            INTERIOR_TABLE_BTREE_PAGE => INTERIOR_BTREE_HEADER_LEN,
+            _ => continue,
+        };
+        let cell_count: usize = header.cell_count() as usize;
+        if header_len + 2 * cell_count > page_size {
+            continue;
+        }
+
+        let mut children: Vec<u32> = vec![];
+        for i in 0..cell_count {
+            let pointer: usize = page_offset + header_len + 2 * i;
+            let cell_address: usize = page_offset
+                + u16::from_be_bytes([bytearray[pointer], bytearray[pointer + 1]]) as usize;
+            if cell_address < pointer || cell_address + 4 > page_end {
+                skipped_cells += 1;
+                continue;
+            }
+
+            match header.page_type() {
+                LEAF_TABLE_BTREE_PAGE => {
+                    match read_leaf_cell(bytearray, page, cell_address, page_end, page_size) {
+                        Some((cell, _)) => rows.push(cell),
+                        None => skipped_cells += 1,
+                    }
+                }
+                _ => children.push(InteriorTableCell::new(bytearray, cell_address).left_pointer()),
+            }
+        }
+        // Visited in order, from the leftmost child
+        if let Some(p) = header.rightmost_ptr() {
+            to_visit.push(p);
+        }
+        to_visit.extend(children.into_iter().rev());
+    }
+
+    if skipped_cells > 0 {
+        warn!(
+            "Page {}: {} cells of the dropped table skipped",
+            root_page, skipped_cells
+        );
+    }
+    info!(
+        "{} rows recovered from b-tree rooted at page {}",
+        rows.len(),
+        root_page
+    );
+
+    (rows, visited)
+}
+
+/// Reads the cells of the table leaf pages (and of the trunk pages) in the freelist which do not
+/// belong to the b-tree of a dropped table (`claimed`), e.g.: pages of tables whose sqlite_master
+/// entry has been overwritten
+pub fn recover_free_leaf_cells(
+    bytearray: &[u8],
+    page_size: usize,
+    free_pages: &[u32],
+    trunk_pages: &[u32],
+    claimed: &[u32],
+) -> Vec<LeafCell> {
+    let mut cells: Vec<LeafCell> = vec![];
+    for &page in free_pages.iter().filter(|p| !claimed.contains(p)) {
+        let page_offset: usize = (page as usize - 1) * page_size;
+        let page_end: usize = page_offset + page_size;
+        if page_end > bytearray.len() {
+            continue;
+        }
+        if trunk_pages.contains(&page) {
+            cells.extend(read_trunk_cells(bytearray, page, page_offset, page_size));
+            continue;
+        }
+        let header: PageHeader = PageHeader::new(bytearray, page_offset);
+        let cell_count: usize = header.cell_count() as usize;
+        if header.page_type() != LEAF_TABLE_BTREE_PAGE
+            || LEAF_BTREE_HEADER_LEN + 2 * cell_count > page_size
+        {
+            continue;
+        }
+
+        for i in 0..cell_count {
+            let pointer: usize = page_offset + LEAF_BTREE_HEADER_LEN + 2 * i;
+            let cell_address: usize = page_offset
+                + u16::from_be_bytes([bytearray[pointer], bytearray[pointer + 1]]) as usize;
+            if cell_address < pointer || cell_address + 4 > page_end {
+                continue;
+            }
+            if let Some((cell, _)) =
+                read_leaf_cell(bytearray, page, cell_address, page_end, page_size)
+            {
+                cells.push(cell);
+            }
+        }
+    }
+    debug!("{} cells in freelist leaf pages", cells.len());
+
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{sqlite3, test_dir};
+    use std::fs::{read, remove_dir_all};
+    use std::path::PathBuf;
+
+    /// Bytes of a database, its page size, its freelist pages and the trunk ones
+    type Database = (Vec<u8>, usize, Vec<u32>, Vec<u32>);
+
+    /// Database created by the sqlite3 shell
+    fn database(name: &str, sql: &str) -> Database {
+        let dir: PathBuf = test_dir(name);
+        let path: PathBuf = dir.join("dropped.db");
+        sqlite3(&path, &format!("PRAGMA secure_delete=0;{}", sql));
+        let bytearray: Vec<u8> = read(&path).unwrap();
+        remove_dir_all(&dir).unwrap();
+
+        let be32 =
+            |offset: usize| u32::from_be_bytes(bytearray[offset..offset + 4].try_into().unwrap());
+        let page_size: usize = u16::from_be_bytes([bytearray[16], bytearray[17]]) as usize;
+        let mut free_pages: Vec<u32> = vec![];
+        let mut trunk_pages: Vec<u32> = vec![];
+        let mut trunk: u32 = be32(32);
+        while trunk != 0 {
+            let offset: usize = (trunk as usize - 1) * page_size;
+            trunk_pages.push(trunk);
+            free_pages.push(trunk);
+            free_pages.extend((0..be32(offset + 4) as usize).map(|i| be32(offset + 8 + 4 * i)));
+            trunk = be32(offset);
+        }
+
+        (bytearray, page_size, free_pages, trunk_pages)
+    }
+
+    fn live_row(name: &str, root_page: u32, sql: &str) -> Vec<String> {
+        ["table", name, name, &root_page.to_string(), sql]
+            .iter()
+            .map(|v| v.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn dropped_single_page_table() {
+        let (bytearray, page_size, free_pages, trunk_pages) = database(
+            "dropped_single",
+            "CREATE TABLE keep (a);
+            INSERT INTO keep VALUES (1);
+            CREATE TABLE gone (a TEXT, b INTEGER);
+            INSERT INTO gone VALUES ('alpha', 1), ('beta', 2);
+            DROP TABLE gone;",
+        );
+        // The only page of the table is now the trunk page of the freelist
+        assert_eq!(trunk_pages, vec![3]);
+
+        let entries: Vec<SchemaEntry> = recover_schema_entries(
+            &bytearray,
+            page_size,
+            &free_pages,
+            &[live_row("keep", 2, "CREATE TABLE keep (a)")],
+        );
+        let entry: &SchemaEntry = entries.iter().find(|e| e.name == "gone").unwrap();
+        assert!(entry.dropped);
+        assert_eq!(entry.root_page, 3);
+
+        let (rows, pages): (Vec<LeafCell>, Vec<u32>) =
+            recover_rows(&bytearray, page_size, &free_pages, &trunk_pages, 3);
+        assert_eq!(pages, vec![3]);
+        let mut values: Vec<(Option<i64>, Vec<String>)> =
+            rows.iter().map(|r| (r.rowid(), r.data())).collect();
+        values.sort();
+        assert_eq!(
+            values,
+            vec![
+                (Some(1), vec![String::from("alpha"), String::from("1")]),
+                (Some(2), vec![String::from("beta"), String::from("2")]),
+            ]
+        );
+        assert_eq!(rows[0].provenance().unwrap().page, Some(3));
+
+        // Unclaimed, the trunk page is carved with the other freelist pages
+        let cells: Vec<LeafCell> =
+            recover_free_leaf_cells(&bytearray, page_size, &free_pages, &trunk_pages, &[]);
+        assert_eq!(cells.len(), 2);
+        assert!(
+            recover_free_leaf_cells(&bytearray, page_size, &free_pages, &trunk_pages, &pages)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn dropped_multi_page_table() {
+        let (bytearray, page_size, free_pages, trunk_pages) = database(
+            "dropped_multi",
+            "PRAGMA page_size=1024;
+            CREATE TABLE keep (a);
+            INSERT INTO keep VALUES (1);
+            CREATE TABLE gone (a TEXT, b INTEGER);
+            WITH RECURSIVE c(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM c WHERE i < 500)
+            INSERT INTO gone SELECT 'row number ' || i, i FROM c;
+            DROP TABLE gone;",
+        );
+        assert!(free_pages.len() > 2);
+
+        let (rows, pages): (Vec<LeafCell>, Vec<u32>) =
+            recover_rows(&bytearray, page_size, &free_pages, &trunk_pages, 3);
+        assert!(trunk_pages.iter().all(|p| pages.contains(p)));
+        let mut rowids: Vec<i64> = rows.iter().filter_map(|r| r.rowid()).collect();
+        rowids.sort();
+        assert_eq!(rowids, (1..=500).collect::<Vec<i64>>());
+        assert!(rows.iter().all(|r| {
+            let rowid: String = r.rowid().unwrap().to_string();
+            r.data() == vec![format!("row number {}", rowid), rowid]
+        }));
+    }
+}
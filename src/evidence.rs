@@ -0,0 +1,269 @@
+use log::{info, warn};
+use md5::Md5;
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::utils::to_hex;
+
+/// Files written by SQLite next to the main database
+const COMPANION_SUFFIXES: [&str; 3] = ["-wal", "-journal", "-shm"];
+
+/// Size of the chunks files are hashed in
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Size and digests of a file
+#[derive(Serialize, Clone, PartialEq)]
+pub struct FileHashes {
+    pub path: String,
+    pub size: u64,
+    pub md5: String,
+    pub sha1: String,
+    pub sha256: String,
+}
+
+impl FileHashes {
+    /// Reads the file once, computing the three digests
+    pub fn new(path: &Path) -> Result<FileHashes, &'static str> {
+        let reader: BufReader<File> =
+            BufReader::new(File::open(path).map_err(|_| "Cannot open file to hash")?);
+        FileHashes::copy(reader, std::io::sink(), path)
+    }
+
+    /// Copies the bytes read to `writer` (e.g.: the file at `path`), computing the three digests
+    pub fn copy<R: Read, W: Write>(
+        mut reader: R,
+        mut writer: W,
+        path: &Path,
+    ) -> Result<FileHashes, &'static str> {
+        let mut md5: Md5 = Md5::new();
+        let mut sha1: Sha1 = Sha1::new();
+        let mut sha256: Sha256 = Sha256::new();
+        let mut buffer: Vec<u8> = vec![0; HASH_BUFFER_SIZE];
+        let mut size: u64 = 0;
+        loop {
+            let read: usize = reader
+                .read(&mut buffer)
+                .map_err(|_| "Cannot read file to hash")?;
+            if read == 0 {
+                break;
+            }
+            writer
+                .write_all(&buffer[..read])
+                .map_err(|_| "Cannot write file")?;
+            md5.update(&buffer[..read]);
+            sha1.update(&buffer[..read]);
+            sha256.update(&buffer[..read]);
+            size += read as u64;
+        }
+        writer.flush().map_err(|_| "Cannot write file")?;
+
+        Ok(FileHashes {
+            path: path.display().to_string(),
+            size,
+            md5: to_hex(&md5.finalize()),
+            sha1: to_hex(&sha1.finalize()),
+            sha256: to_hex(&sha256.finalize()),
+        })
+    }
+}
+
+/// WAL, journal and shm files found next to the main database
+pub fn companion_paths(db_filepath: &str) -> Vec<PathBuf> {
+    COMPANION_SUFFIXES
+        .iter()
+        .map(|suffix| PathBuf::from(format!("{}{}", db_filepath, suffix)))
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// Hashes the main database and the WAL, journal and shm files found next to it
+pub fn hash_inputs(db_filepath: &str) -> Vec<FileHashes> {
+    let mut paths: Vec<PathBuf> = vec![PathBuf::from(db_filepath)];
+    paths.extend(companion_paths(db_filepath));
+
+    let mut hashes: Vec<FileHashes> = vec![];
+    for path in paths.iter() {
+        match FileHashes::new(path) {
+            Ok(h) => {
+                info!("{} - MD5 {} - SHA-256 {}", h.path, h.md5, h.sha256);
+                hashes.push(h);
+            }
+            Err(e) => warn!("{}: {}", path.display(), e),
+        }
+    }
+
+    hashes
+}
+
+/// Audit trail of a run: what has been read, how, and what has been written
+#[derive(Serialize)]
+pub struct RunManifest {
+    pub tool: String,
+    pub version: String,
+    pub command_line: Vec<String>,
+    pub started_at: String,
+    pub finished_at: String,
+    /// Hashes of the input files before processing
+    pub inputs_before: Vec<FileHashes>,
+    /// Hashes of the input files after processing
+    pub inputs_after: Vec<FileHashes>,
+    /// The input files have not been modified while processing them
+    pub inputs_unchanged: bool,
+    pub outputs: Vec<FileHashes>,
+}
+
+pub fn now() -> String {
+    OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+/// Run being audited, started when created
+pub struct Run {
+    started_at: String,
+    db_filepath: String,
+    inputs_before: Vec<FileHashes>,
+}
+
+impl Run {
+    /// Records the start time and hashes the input files
+    pub fn start(db_filepath: &str) -> Run {
+        Run {
+            started_at: now(),
+            db_filepath: db_filepath.to_string(),
+            inputs_before: hash_inputs(db_filepath),
+        }
+    }
+
+    /// Hashes the input files again and the files written during the run (`outputs`),
+    /// then writes the manifest to `manifest_path`
+    pub fn finish(
+        self,
+        outputs: &[PathBuf],
+        manifest_path: &Path,
+    ) -> Result<RunManifest, &'static str> {
+        let inputs_after: Vec<FileHashes> = hash_inputs(&self.db_filepath);
+        let inputs_unchanged: bool = inputs_after == self.inputs_before;
+        if !inputs_unchanged {
+            warn!("Input files changed while processing them");
+        }
+
+        let mut written: Vec<&PathBuf> = outputs.iter().collect();
+        written.sort();
+        written.dedup();
+        let mut hashes: Vec<FileHashes> = vec![];
+        for path in written.into_iter() {
+            match FileHashes::new(path) {
+                Ok(h) => hashes.push(h),
+                Err(e) => warn!("{}: {}", path.display(), e),
+            }
+        }
+
+        let manifest: RunManifest = RunManifest {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            command_line: std::env::args().collect(),
+            started_at: self.started_at,
+            finished_at: now(),
+            inputs_before: self.inputs_before,
+            inputs_after,
+            inputs_unchanged,
+            outputs: hashes,
+        };
+
+        let file: File = File::create(manifest_path).map_err(|_| "Cannot create run manifest")?;
+        serde_json::to_writer_pretty(file, &manifest).map_err(|_| "Cannot write run manifest")?;
+
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_dir;
+    use std::fs::{read, read_to_string, remove_dir_all, write};
+
+    #[test]
+    fn hashes_of_file() {
+        let dir: PathBuf = test_dir("hashes");
+        let path: PathBuf = dir.join("abc");
+        write(&path, b"abc").unwrap();
+        let hashes: FileHashes = FileHashes::new(&path).unwrap();
+        assert_eq!(hashes.size, 3);
+        assert_eq!(hashes.md5, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hashes.sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(
+            hashes.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        // Copies spanning several buffers are hashed as a whole
+        let data: Vec<u8> = (0..HASH_BUFFER_SIZE + 3).map(|i| i as u8).collect();
+        let copy: PathBuf = dir.join("copy");
+        let copied: FileHashes =
+            FileHashes::copy(&data[..], File::create(&copy).unwrap(), &copy).unwrap();
+        assert_eq!(copied.size, data.len() as u64);
+        assert_eq!(read(&copy).unwrap(), data);
+        assert_eq!(copied.sha256, to_hex(&Sha256::digest(&data)));
+        assert!(copied == FileHashes::new(&copy).unwrap());
+
+        assert!(FileHashes::new(&dir.join("missing")).is_err());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn run_manifest() {
+        let dir: PathBuf = test_dir("manifest");
+        let db: PathBuf = dir.join("test.db");
+        write(&db, b"main").unwrap();
+        write(dir.join("test.db-wal"), b"wal").unwrap();
+        write(dir.join("test.db-shm"), b"shm").unwrap();
+        let db_filepath: &str = db.to_str().unwrap();
+        assert_eq!(
+            companion_paths(db_filepath),
+            vec![dir.join("test.db-wal"), dir.join("test.db-shm")]
+        );
+
+        let run: Run = Run::start(db_filepath);
+        let b: PathBuf = dir.join("b.json");
+        let a: PathBuf = dir.join("a.csv");
+        write(&b, b"{}").unwrap();
+        write(&a, b"x").unwrap();
+        // Outputs are listed once, sorted; missing ones are skipped
+        let outputs: Vec<PathBuf> = vec![b.clone(), a.clone(), b.clone(), dir.join("missing")];
+        let manifest_path: PathBuf = dir.join("manifest.json");
+        let manifest: RunManifest = run.finish(&outputs, &manifest_path).unwrap();
+        assert_eq!(manifest.inputs_before.len(), 3);
+        assert!(manifest.inputs_unchanged);
+        let written: Vec<String> = manifest
+            .outputs
+            .iter()
+            .map(|h| h.path.to_string())
+            .collect();
+        assert_eq!(written, [a.display().to_string(), b.display().to_string()]);
+        let json: serde_json::Value =
+            serde_json::from_str(&read_to_string(&manifest_path).unwrap()).unwrap();
+        assert_eq!(json["tool"], env!("CARGO_PKG_NAME"));
+        assert_eq!(json["outputs"][1]["size"], 2);
+        assert_eq!(
+            json["inputs_after"][1]["sha256"],
+            manifest.inputs_before[1].sha256
+        );
+
+        // Inputs modified while processing them
+        let run: Run = Run::start(db_filepath);
+        write(dir.join("test.db-wal"), b"changed").unwrap();
+        let manifest: RunManifest = run.finish(&[], &manifest_path).unwrap();
+        assert!(!manifest.inputs_unchanged);
+        assert!(manifest.outputs.is_empty());
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::db::{DataBase, KeyedRow, RowStatus, Table};
+use crate::fts5::Fts5Entry;
+use crate::mainfile::FileHeader;
+
+/// Rows shown in each page of the table views
+const ROWS_PER_PAGE: usize = 50;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.5em; } h2 { border-bottom: 1px solid #ccc; margin-top: 2em; }
+pre { background: #f5f5f5; padding: 1em; overflow-x: auto; white-space: pre-wrap; }
+table { border-collapse: collapse; font-size: 0.85em; margin: 0.5em 0; }
+th, td { border: 1px solid #ddd; padding: 2px 6px; text-align: left; vertical-align: top; max-width: 40em; overflow-wrap: anywhere; }
+th { background: #eee; position: sticky; top: 0; }
+tr.inserted { background: #e3f6e3; } tr.deleted { background: #fbe3e3; } tr.modified { background: #fff5d6; }
+.badge { font-size: 0.8em; padding: 0 4px; border-radius: 3px; background: #ddd; }
+.pager button { margin: 0 2px; }
+summary { cursor: pointer; font-weight: bold; margin: 0.5em 0; }
+";
+
+/// Pagination of the table views: shows one page of rows at a time
+const SCRIPT: &str = "
+document.querySelectorAll('table.paged').forEach(function (table) {
+  var rows = Array.prototype.slice.call(table.tBodies[0].rows);
+  var size = parseInt(table.dataset.pageSize, 10);
+  var pages = Math.max(1, Math.ceil(rows.length / size));
+  var pager = document.createElement('div');
+  pager.className = 'pager';
+  var label = document.createElement('span');
+  function show(page) {
+    rows.forEach(function (row, i) { row.style.display = (i >= page * size && i < (page + 1) * size) ? '' : 'none'; });
+    label.textContent = ' Page ' + (page + 1) + ' of ' + pages + ' ';
+    pager.dataset.page = page;
+  }
+  [['<<', function () { return 0; }], ['<', function (p) { return Math.max(0, p - 1); }],
+   ['>', function (p) { return Math.min(pages - 1, p + 1); }], ['>>', function () { return pages - 1; }]]
+    .forEach(function (b, i) {
+      var button = document.createElement('button');
+      button.textContent = b[0];
+      button.onclick = function () { show(b[1](parseInt(pager.dataset.page, 10))); };
+      pager.appendChild(button);
+      if (i == 1) { pager.appendChild(label); }
+    });
+  table.parentNode.insertBefore(pager, table);
+  show(0);
+});
+";
+
+fn escape(text: &str) -> String {
+    let mut escaped: String = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn status_class(status: RowStatus) -> &'static str {
+    match status {
+        RowStatus::Live => "live",
+        RowStatus::Inserted => "inserted",
+        RowStatus::Deleted => "deleted",
+        RowStatus::Modified => "modified",
+        RowStatus::Dropped => "dropped",
+        RowStatus::Carved => "carved",
+    }
+}
+
+/// Writes a single HTML file, with inline style and script, describing the database:
+/// file header, schema, anomalies, recovered records and the rows of each table
+pub fn write_report<W: Write>(
+    out: &mut W,
+    source: &str,
+    header: &FileHeader,
+    db: &DataBase,
+) -> std::io::Result<()> {
+    let tables: &[Table] = db.tables();
+
+    writeln!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>rustbish - {}</title>\n<style>{}</style>\n</head>\n<body>",
+        escape(source),
+        STYLE
+    )?;
+    writeln!(out, "<h1>rustbish report: {}</h1>", escape(source))?;
+
+    /* Summary */
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for table in tables.iter() {
+        for row in table.keyed_rows().iter() {
+            *counts.entry(status_class(row.status)).or_default() += 1;
+        }
+    }
+    writeln!(out, "<h2>Summary</h2>\n<table>")?;
+    writeln!(out, "<tr><th>Tables</th><td>{}</td></tr>", tables.len())?;
+    for (status, count) in counts.iter() {
+        writeln!(out, "<tr><th>Rows {}</th><td>{}</td></tr>", status, count)?;
+    }
+    writeln!(out, "</table>")?;
+
+    /* File header */
+    writeln!(
+        out,
+        "<h2>File header</h2>\n<pre>{}</pre>",
+        escape(&format!("{:?}", header))
+    )?;
+
+    /* Schema */
+    writeln!(out, "<h2>Schema</h2>")?;
+    for table in tables.iter() {
+        writeln!(
+            out,
+            "<details><summary>{}</summary><pre>{}</pre></details>",
+            escape(&table.name),
+            escape(&table.schema.sql)
+        )?;
+    }
+    if let Some(triggers) = db.triggers() {
+        for trigger in triggers.iter() {
+            writeln!(out, "<pre>{}</pre>", escape(trigger))?;
+        }
+    }
+
+    /* Anomalies */
+    let mut anomalies: Vec<String> = vec![];
+    for table in tables.iter() {
+        match &table.missing_rowids {
+            Some(missing) if !missing.is_empty() => anomalies.push(format!(
+                "{}: {} missing rowids ({})",
+                table.name,
+                missing.len(),
+                missing
+                    .iter()
+                    .take(20)
+                    .map(|r| r.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )),
+            _ => (),
+        }
+        let rows: Vec<KeyedRow> = table.keyed_rows();
+        let extra: usize = rows.iter().filter(|r| r.extra_values.is_some()).count();
+        if extra > 0 {
+            anomalies.push(format!(
+                "{}: {} records with values exceeding the columns",
+                table.name, extra
+            ));
+        }
+        let defaulted: usize = rows
+            .iter()
+            .filter(|r| r.defaulted_columns.is_some())
+            .count();
+        if defaulted > 0 {
+            anomalies.push(format!(
+                "{}: {} records shorter than the schema (columns added later)",
+                table.name, defaulted
+            ));
+        }
+    }
+    for vtable in db.virtual_tables().iter() {
+        match &vtable.fts5_index {
+            Some(index) if !index.orphan_rowids.is_empty() => anomalies.push(format!(
+                "{}: full-text index references {} rowids missing from the content",
+                vtable.name,
+                index.orphan_rowids.len()
+            )),
+            _ => (),
+        }
+    }
+    writeln!(out, "<h2>Anomalies</h2>")?;
+    match anomalies.is_empty() {
+        true => writeln!(out, "<p>None found.</p>")?,
+        false => {
+            writeln!(out, "<ul>")?;
+            for anomaly in anomalies.iter() {
+                writeln!(out, "<li>{}</li>", escape(anomaly))?;
+            }
+            writeln!(out, "</ul>")?;
+        }
+    }
+
+    /* Recovered records */
+    writeln!(out, "<h2>Recovered records</h2>")?;
+    for table in tables.iter() {
+        for (status, title) in [
+            (RowStatus::Deleted, "deleted rows"),
+            (RowStatus::Dropped, "rows of a dropped table"),
+            (RowStatus::Carved, "carved records"),
+        ] {
+            let rows: Vec<KeyedRow> = table
+                .keyed_rows()
+                .into_iter()
+                .filter(|r| r.status == status)
+                .collect();
+            if !rows.is_empty() {
+                writeln!(out, "<h3>{}: {}</h3>", escape(&table.name), title)?;
+                write_rows(out, table, &rows)?;
+            }
+        }
+    }
+    for vtable in db.virtual_tables().iter() {
+        match &vtable.fts5_index {
+            Some(index) if !index.orphan_rowids.is_empty() => {
+                writeln!(
+                    out,
+                    "<h3>{}: text of deleted rows, rebuilt from the full-text index</h3>\n<table><tr><th>rowid</th><th>column</th><th>text</th></tr>",
+                    escape(&vtable.name)
+                )?;
+                let mut texts: BTreeMap<(i64, i64), Vec<&Fts5Entry>> = BTreeMap::new();
+                for entry in index.entries.iter().filter(|e| e.orphan) {
+                    texts
+                        .entry((entry.rowid, entry.column))
+                        .or_default()
+                        .push(entry);
+                }
+                for ((rowid, column), mut entries) in texts.into_iter() {
+                    entries.sort_by_key(|e| e.position);
+                    let column_name: String = match vtable.columns.get(column as usize) {
+                        Some(c) => c.to_string(),
+                        None => column.to_string(),
+                    };
+                    writeln!(
+                        out,
+                        "<tr class=\"deleted\"><td>{}</td><td>{}</td><td>{}</td></tr>",
+                        rowid,
+                        escape(&column_name),
+                        escape(
+                            &entries
+                                .iter()
+                                .map(|e| e.term.as_str())
+                                .collect::<Vec<&str>>()
+                                .join(" ")
+                        )
+                    )?;
+                }
+                writeln!(out, "</table>")?;
+            }
+            _ => (),
+        }
+    }
+
+    /* Tables */
+    writeln!(out, "<h2>Tables</h2>")?;
+    for table in tables.iter() {
+        let rows: Vec<KeyedRow> = table.keyed_rows();
+        writeln!(
+            out,
+            "<details><summary>{} <span class=\"badge\">{} rows</span>{}</summary>",
+            escape(&table.name),
+            rows.len(),
+            match &table.shadow_of {
+                Some(t) => format!(" <span class=\"badge\">shadow of {}</span>", escape(t)),
+                None => String::new(),
+            }
+        )?;
+        write_rows(out, table, &rows)?;
+        writeln!(out, "</details>")?;
+    }
+
+    writeln!(out, "<script>{}</script>\n</body>\n</html>", SCRIPT)?;
+
+    Ok(())
+}
+
+/// Table of rows, highlighted by status; modified rows show their position in the sequence
+fn write_rows<W: Write>(out: &mut W, table: &Table, rows: &[KeyedRow]) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "<table class=\"paged\" data-page-size=\"{}\"><thead><tr><th>rowid</th><th>status</th>",
+        ROWS_PER_PAGE
+    )?;
+    for column in table.columns.iter() {
+        write!(out, "<th>{}</th>", escape(column))?;
+    }
+    writeln!(out, "</tr></thead><tbody>")?;
+
+    for row in rows.iter() {
+        let status: String = match row.sequence {
+            Some(i) => format!("{} #{}", status_class(row.status), i + 1),
+            None => status_class(row.status).to_string(),
+        };
+        write!(
+            out,
+            "<tr class=\"{}\"><td>{}</td><td>{}</td>",
+            status_class(row.status),
+            match row.rowid {
+                Some(r) => r.to_string(),
+                None => String::new(),
+            },
+            status
+        )?;
+        for value in row.values.iter() {
+            write!(out, "<td>{}</td>", escape(value))?;
+        }
+        writeln!(out, "</tr>")?;
+    }
+    writeln!(out, "</tbody></table>")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ReadOptions, RowFilter};
+    use crate::mainfile::MainFile;
+    use crate::testing::{sqlite3, test_dir};
+    use std::path::PathBuf;
+
+    #[test]
+    fn escape_markup() {
+        assert_eq!(
+            escape("<a href=\"x\">Tom & 'Jerry'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn report_of_database() {
+        let dir: PathBuf = test_dir("html");
+        let db: PathBuf = dir.join("test.db");
+        sqlite3(
+            &db,
+            "CREATE TABLE notes (body);
+            INSERT INTO notes VALUES ('<script>alert(1)</script>'), ('plain');
+            ALTER TABLE notes ADD COLUMN tag DEFAULT 'none';
+            INSERT INTO notes VALUES ('tagged', 'work');
+            CREATE TRIGGER t AFTER DELETE ON notes BEGIN SELECT 1; END;",
+        );
+        let bytearray: Vec<u8> = std::fs::read(&db).unwrap();
+        let header: FileHeader = FileHeader::new(&bytearray).unwrap();
+        let read: ReadOptions = ReadOptions {
+            triggers: true,
+            ..ReadOptions::default()
+        };
+        let database: DataBase = DataBase::new(
+            MainFile::new(&bytearray).unwrap(),
+            None,
+            read,
+            &RowFilter::default(),
+        );
+
+        let mut out: Vec<u8> = vec![];
+        write_report(&mut out, "test.db", &header, &database).unwrap();
+        let html: String = String::from_utf8(out).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.trim_end().ends_with("</html>"));
+        assert!(html.contains("<tr><th>Tables</th><td>1</td></tr>"));
+        assert!(html.contains("<tr><th>Rows live</th><td>3</td></tr>"));
+        // Values are escaped, so that the report runs only its own script
+        assert!(html.contains("<td>&lt;script&gt;alert(1)&lt;/script&gt;</td>"));
+        assert!(!html.contains("<script>alert"));
+        assert_eq!(html.matches("<script>").count(), 1);
+        assert!(html.contains("notes: 2 records shorter than the schema (columns added later)"));
+        assert!(html.contains("CREATE TRIGGER t AFTER DELETE ON notes"));
+        assert!(html.contains(&format!(
+            "<table class=\"paged\" data-page-size=\"{}\">",
+            ROWS_PER_PAGE
+        )));
+        assert!(html.contains("<th>body</th><th>tag</th></tr></thead>"));
+        assert!(html.contains(
+            "<tr class=\"live\"><td>3</td><td>live</td><td>tagged</td><td>work</td></tr>"
+        ));
+    }
+}
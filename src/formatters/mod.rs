@@ -1,50 +1,1056 @@
-use log::info;
+use csv::{QuoteStyle, WriterBuilder};
+use log::{debug, error, info, warn};
+use std::collections::{BTreeSet, HashSet};
 use std::fs::File;
-use std::io::Write;
-use std::path::Path;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-use crate::db::DataBase;
-use crate::{mainfile::MainFile, wal::WALFile};
+mod html;
+mod sqlite;
+use sqlite::{SqliteWriter, Value};
+
+use crate::args::DumpArgs;
+use crate::blobs::BlobOptions;
+use crate::db::{DataBase, KeyedRow, NdjsonWriter, ReadOptions, RowFilter, RowStatus, Table};
+use crate::fts5::Fts5Index;
+use crate::mainfile::{FileHeader, MainFile};
+use crate::schema::ColumnDef;
+use crate::timestamps::{build_timeline, load_config, to_iso8601, TimelineEvent, TimestampConfig};
+use crate::utils::sanitize_file_name;
+use crate::wal::WALFile;
+
+/// Options of the CSV writer
+pub struct CsvOptions {
+    pub delimiter: u8,
+    /// Quote every field, not only the ones containing delimiters, quotes or newlines
+    pub quote_all: bool,
+    /// Start files with the UTF-8 BOM, for Excel
+    pub bom: bool,
+}
+
+/// Options of the provenance of rows
+pub struct ProvenanceOptions {
+    /// Path of the main file, as given
+    pub source: String,
+    /// Also write the bytes of each cell, in hex
+    pub raw_cells: bool,
+}
+
+/// Options of a run writing extracted rows: what is read, which rows are kept,
+/// what is added to them and how CSV files are written
+pub struct RunOptions {
+    /// Path of the main file, as given
+    pub source: String,
+    pub read: ReadOptions,
+    pub filter: RowFilter,
+    /// JSON rows are objects keyed by column name
+    pub keyed_rows: bool,
+    /// Formats of timestamp columns (others are detected)
+    pub timestamps: Option<TimestampConfig>,
+    /// Add the ISO-8601 value of timestamp columns to the rows
+    pub decode_timestamps: bool,
+    pub blobs: Option<BlobOptions>,
+    pub provenance: Option<ProvenanceOptions>,
+    pub csv: CsvOptions,
+}
+
+impl RunOptions {
+    /// Checks and collects the options of the dump, carve and diff commands,
+    /// keeping only the rows with the given statuses, if any
+    pub fn new(
+        args: &DumpArgs,
+        statuses: Option<Vec<RowStatus>>,
+    ) -> Result<RunOptions, &'static str> {
+        if !args.delimiter.is_ascii() {
+            return Err("CSV delimiter must be an ASCII character");
+        }
+        let timestamps: Option<TimestampConfig> = match &args.timestamps {
+            Some(path) => Some(load_config(path)?),
+            None if args.decode_timestamps => Some(TimestampConfig::default()),
+            None => None,
+        };
+        let output_dir: PathBuf = Path::new(".").join(&args.output_dir);
+
+        Ok(RunOptions {
+            source: args.filepath.to_string(),
+            read: ReadOptions {
+                missing_ids: args.missingids,
+                triggers: args.triggers,
+                indices: args.indices,
+                fts5_index: args.fts5_index,
+            },
+            filter: RowFilter {
+                statuses,
+                tables: args.tables.clone(),
+                exclude_tables: args.exclude_tables.clone(),
+                columns: args.columns.iter().cloned().collect(),
+                rowids: args.rowid_range,
+                predicate: args.predicate.clone(),
+            },
+            keyed_rows: args.keyed_rows,
+            timestamps,
+            decode_timestamps: args.decode_timestamps,
+            blobs: match args.decode_blobs || args.extract_blobs {
+                true => Some(BlobOptions {
+                    decode: args.decode_blobs,
+                    extract_dir: match args.extract_blobs {
+                        true => Some(output_dir.join("blobs")),
+                        false => None,
+                    },
+                }),
+                false => None,
+            },
+            provenance: match args.provenance || args.raw_cells {
+                true => Some(ProvenanceOptions {
+                    source: args.filepath.to_string(),
+                    raw_cells: args.raw_cells,
+                }),
+                false => None,
+            },
+            csv: CsvOptions {
+                delimiter: args.delimiter as u8,
+                quote_all: args.quote_all,
+                bom: args.bom,
+            },
+        })
+    }
+
+    /// Formats of the timestamp columns whose ISO-8601 value is added to the rows, if requested
+    fn decoded_timestamps(&self) -> Option<&TimestampConfig> {
+        match self.decode_timestamps {
+            true => self.timestamps.as_ref(),
+            false => None,
+        }
+    }
+}
+
+/// Adds the requested companion values (timestamps, blobs) and provenance to the rows.
+/// Returns the paths of the blobs extracted
+fn extend_rows(db: &mut DataBase, options: &RunOptions) -> Vec<PathBuf> {
+    if let Some(config) = options.decoded_timestamps() {
+        db.decode_timestamps(config);
+    }
+    let written: Vec<PathBuf> = match &options.blobs {
+        Some(blobs) => inspect_blobs(db, blobs),
+        None => vec![],
+    };
+    if let Some(provenance) = &options.provenance {
+        db.add_provenance(&provenance.source, provenance.raw_cells);
+    }
+    written
+}
+
+/// Writes an RFC 4180 CSV file
+fn write_csv(
+    mut outfile: File,
+    header: Vec<String>,
+    records: Vec<Vec<String>>,
+    options: &CsvOptions,
+) -> Result<(), &'static str> {
+    if options.bom {
+        outfile
+            .write_all("\u{feff}".as_bytes())
+            .map_err(|_| "Error writing CSV BOM")?;
+    }
+
+    let mut writer: csv::Writer<File> = WriterBuilder::new()
+        .delimiter(options.delimiter)
+        .quote_style(match options.quote_all {
+            true => QuoteStyle::Always,
+            false => QuoteStyle::Necessary,
+        })
+        .from_writer(outfile);
+
+    writer
+        .write_record(&header)
+        .map_err(|_| "Error writing CSV header")?;
+    for record in records.iter() {
+        writer
+            .write_record(record)
+            .map_err(|_| "Error writing CSV record")?;
+    }
+    writer.flush().map_err(|_| "Error flushing CSV file")?;
+
+    Ok(())
+}
 
 pub fn csv_run(
     main_db_file: MainFile,
     wal_file: Option<WALFile>,
-    missing_ids: bool,
-    triggers: bool,
-    indices: bool,
-) -> () {
+    output_dir: &Path,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>, &'static str> {
     info!("Write CSV files");
 
-    let db: DataBase = DataBase::new(main_db_file, wal_file, missing_ids, triggers, indices);
+    let mut db: DataBase = DataBase::new(main_db_file, wal_file, options.read, &options.filter);
+    let mut written: Vec<PathBuf> = extend_rows(&mut db, options);
 
-    db.tables().iter().for_each(|table| {
-        let mut outfile: File = File::create(
-            Path::new(".")
-                .join("output")
-                .join(format!("{}.csv", &table.name)),
-        )
-        .unwrap();
-        write!(outfile, "{}", table.to_csv()).unwrap();
-    });
+    written.extend(write_csv_tables(
+        &db,
+        output_dir,
+        &options.source,
+        &options.csv,
+    )?);
+    Ok(written)
+}
+
+/// Writes a CSV file for each table and FTS5 index of the database, and a manifest listing them.
+/// Returns the paths of the files written
+fn write_csv_tables(
+    db: &DataBase,
+    output_dir: &Path,
+    source: &str,
+    options: &CsvOptions,
+) -> Result<Vec<PathBuf>, &'static str> {
+    let mut written: Vec<PathBuf> = vec![];
+    let mut file_names: HashSet<String> = HashSet::new();
+    let mut manifest: Vec<Vec<String>> = vec![];
+    // Reserved before tables, so that the manifest has always the same name
+    let manifest_name: String = unique_file_name("manifest", "", &mut file_names);
+
+    for table in db.tables().iter() {
+        let file_name: String = unique_file_name(&table.name, "", &mut file_names);
+        let wal_rows: usize = match &table.wal {
+            Some(diff) => diff.rows().len(),
+            None => 0,
+        };
+        let outfile: File = create_file(&output_dir.join(&file_name))?;
+        write_csv(outfile, table.csv_header(), table.csv_records(), options)?;
+        written.push(output_dir.join(&file_name));
+        manifest.push(vec![
+            file_name,
+            table.name.to_string(),
+            source.to_string(),
+            table.rows.len().to_string(),
+            wal_rows.to_string(),
+        ]);
+    }
+
+    for vtable in db.virtual_tables().iter() {
+        let index: &Fts5Index = match &vtable.fts5_index {
+            Some(index) => index,
+            None => continue,
+        };
+        let file_name: String = unique_file_name(&vtable.name, "_fts5_index", &mut file_names);
+        let outfile: File = create_file(&output_dir.join(&file_name))?;
+        write_csv(outfile, index.csv_header(), index.csv_records(), options)?;
+        written.push(output_dir.join(&file_name));
+        manifest.push(vec![
+            file_name,
+            vtable.name.to_string(),
+            source.to_string(),
+            index.entries.len().to_string(),
+            String::from("0"),
+        ]);
+    }
+
+    /* Manifest */
+    let header: Vec<String> = ["file", "table", "source", "rows", "wal_rows"]
+        .map(String::from)
+        .to_vec();
+    let outfile: File = create_file(&output_dir.join(&manifest_name))?;
+    write_csv(outfile, header, manifest, options)?;
+    info!("Created CSV manifest: {}", manifest_name);
+    written.push(output_dir.join(&manifest_name));
+
+    Ok(written)
+}
+
+/// Creates the file, logging why it cannot be created
+fn create_file(path: &Path) -> Result<File, &'static str> {
+    File::create(path).map_err(|e| {
+        error!("{}: {}", path.display(), e);
+        "Cannot create output file"
+    })
+}
+
+/// Writes the records of carved pages as keyed JSON, with their offsets
+pub fn carved_json_run(
+    tables: Vec<Table>,
+    outpath: &Path,
+    source: &str,
+) -> Result<Vec<PathBuf>, &'static str> {
+    let mut db: DataBase = DataBase::from_tables(tables);
+    db.add_provenance(source, false);
+
+    let mut outfile: File = create_file(outpath)?;
+    write!(outfile, "{}", db.to_keyed_json()).map_err(|_| "Error writing JSON file")?;
+    Ok(vec![outpath.to_path_buf()])
+}
+
+/// Writes the records of carved pages as CSV files, one for each group, with their offsets
+pub fn carved_csv_run(
+    tables: Vec<Table>,
+    output_dir: &Path,
+    source: &str,
+    options: CsvOptions,
+) -> Result<Vec<PathBuf>, &'static str> {
+    let mut db: DataBase = DataBase::from_tables(tables);
+    db.add_provenance(source, false);
+
+    write_csv_tables(&db, output_dir, source, &options)
+}
+
+/// Adds the companion values of blobs to the rows and extracts the blobs, if requested.
+/// Returns the paths of the blobs written
+fn inspect_blobs(db: &mut DataBase, options: &BlobOptions) -> Vec<PathBuf> {
+    db.inspect_blobs(options.decode);
+    match &options.extract_dir {
+        Some(dir) => match db.extract_blobs(dir) {
+            Ok(written) => {
+                info!("Extracted {} blobs to {}", written.len(), dir.display());
+                written
+            }
+            Err(e) => {
+                warn!("{}", e);
+                vec![]
+            }
+        },
+        None => vec![],
+    }
+}
+
+/// File name of a table: the name is sanitized and names differing only in case get a numeric suffix
+fn unique_file_name(table_name: &str, suffix: &str, used: &mut HashSet<String>) -> String {
+    let stem: String = sanitize_file_name(table_name);
+
+    let mut file_name: String = format!("{}{}.csv", stem, suffix);
+    let mut counter: usize = 2;
+    while !used.insert(file_name.to_lowercase()) {
+        file_name = format!("{}{}_{}.csv", stem, suffix, counter);
+        counter += 1;
+    }
+    if file_name != format!("{}{}.csv", table_name, suffix) {
+        debug!("{}: written to {}", table_name, file_name);
+    }
+
+    file_name
 }
 
 pub fn json_run(
     main_db_file: MainFile,
     wal_file: Option<WALFile>,
-    mut outfile: File,
-    missing_ids: bool,
-    triggers: bool,
-    indices: bool,
-) -> () {
+    outpath: &Path,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>, &'static str> {
     info!("Write JSON file");
 
-    let db: DataBase = DataBase::new(
-        main_db_file,
-        wal_file,
-        missing_ids,
-        triggers,
-        indices, //args.indices
+    let mut db: DataBase = DataBase::new(main_db_file, wal_file, options.read, &options.filter);
+    let mut written: Vec<PathBuf> = extend_rows(&mut db, options);
+
+    let mut outfile: File = create_file(outpath)?;
+    // Companion values of timestamps and blobs, and provenance, are added to keyed rows only
+    let json: String = match options.keyed_rows
+        || options.decode_timestamps
+        || options.blobs.is_some()
+        || options.provenance.is_some()
+    {
+        true => db.to_keyed_json(),
+        false => db.to_json(),
+    };
+    write!(outfile, "{}", json).map_err(|_| "Error writing JSON file")?;
+    written.push(outpath.to_path_buf());
+    Ok(written)
+}
+
+/// Writes the database as JSON Lines, one record per line
+pub fn ndjson_run(
+    main_db_file: MainFile,
+    wal_file: Option<WALFile>,
+    outpath: &Path,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>, &'static str> {
+    info!("Write NDJSON file");
+
+    // Each table is written as soon as it has been read, then dropped
+    let outfile: File = create_file(outpath)?;
+    let mut writer: NdjsonWriter<BufWriter<File>> = NdjsonWriter::new(BufWriter::new(outfile));
+    let extract_dir: Option<&PathBuf> = options.blobs.as_ref().and_then(|o| o.extract_dir.as_ref());
+    let timestamps: Option<&TimestampConfig> = options.decoded_timestamps();
+    if let Some(dir) = extract_dir {
+        match std::fs::create_dir_all(dir) {
+            Ok(_) => (),
+            Err(_) => warn!("Cannot create blobs directory"),
+        }
+    }
+    let mut blobs_written: BTreeSet<String> = BTreeSet::new();
+    DataBase::walk(
+        &main_db_file,
+        &wal_file,
+        options.read,
+        &options.filter,
+        |mut table| {
+            if let Some(config) = timestamps {
+                table.decode_timestamps(config);
+            }
+            if let Some(blobs) = &options.blobs {
+                table.inspect_blobs(blobs.decode);
+                if let Some(dir) = extract_dir {
+                    table.extract_blobs(dir, &mut blobs_written)?;
+                }
+            }
+            if let Some(provenance) = &options.provenance {
+                table.add_provenance(&provenance.source, provenance.raw_cells);
+            }
+            writer.write_table(&table)
+        },
+    )
+    .and_then(|(virtual_tables, triggers)| {
+        for vtable in virtual_tables.iter() {
+            writer.write_virtual_table(vtable)?;
+        }
+        for trigger in triggers.unwrap_or_default().iter() {
+            writer.write_trigger(trigger)?;
+        }
+        writer.flush()
+    })?;
+    let mut written: Vec<PathBuf> = vec![outpath.to_path_buf()];
+    if let Some(dir) = extract_dir {
+        info!(
+            "Extracted {} blobs to {}",
+            blobs_written.len(),
+            dir.display()
+        );
+        written.extend(blobs_written.iter().map(|name| dir.join(name)));
+    }
+    Ok(written)
+}
+
+/// Writes a self-contained HTML report
+pub fn html_run(
+    main_db_file: MainFile,
+    wal_file: Option<WALFile>,
+    outpath: &Path,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>, &'static str> {
+    info!("Write HTML report");
+
+    let header: FileHeader = main_db_file.header();
+    let db: DataBase = DataBase::new(main_db_file, wal_file, options.read, &options.filter);
+
+    let mut writer: BufWriter<File> = BufWriter::new(create_file(outpath)?);
+    html::write_report(&mut writer, &options.source, &header, &db)
+        .and_then(|_| writer.flush())
+        .map_err(|_| "Error writing HTML report")?;
+    Ok(vec![outpath.to_path_buf()])
+}
+
+/// Writes the timeline of the timestamps found in the tables, in bodyfile, TLN and CSV forms
+pub fn timeline_run(
+    main_db_file: MainFile,
+    wal_file: Option<WALFile>,
+    output_dir: &Path,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>, &'static str> {
+    info!("Write timeline files");
+
+    let db: DataBase = DataBase::new(main_db_file, wal_file, options.read, &options.filter);
+    let events: Vec<TimelineEvent> = build_timeline(&db, options.timestamps.as_ref());
+    info!("{} timeline events", events.len());
+
+    let source: &str = &options.source;
+    let stem: String = Path::new(source)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let source_name: String = Path::new(source)
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    // Descriptions cannot contain field separators nor newlines
+    let clean = |text: &str| -> String {
+        text.chars()
+            .map(|c| match c {
+                '|' => '/',
+                c if c.is_control() => ' ',
+                c => c,
+            })
+            .collect()
+    };
+    let describe = |event: &TimelineEvent| -> String {
+        clean(&format!(
+            "{}:{}.{} rowid={} [{}] {}",
+            source_name,
+            event.table,
+            event.column,
+            match event.rowid {
+                Some(r) => r.to_string(),
+                None => String::from("-"),
+            },
+            event.status.label(),
+            event.summary
+        ))
+    };
+
+    /* Bodyfile (TSK 3.x): MD5|name|inode|mode|UID|GID|size|atime|mtime|ctime|crtime */
+    let mut bodyfile: String = String::new();
+    /* TLN: Time|Source|Host|User|Description */
+    let mut tln: String = String::new();
+    let mut records: Vec<Vec<String>> = vec![];
+    for event in events.iter() {
+        let seconds: i128 = event.nanos.div_euclid(1_000_000_000);
+        let description: String = describe(event);
+        bodyfile.push_str(&format!("0|{}|0|0|0|0|0|0|{}|0|0\n", description, seconds));
+        tln.push_str(&format!(
+            "{}|SQLite|{}||{}\n",
+            seconds,
+            clean(&source_name),
+            description
+        ));
+        records.push(vec![
+            to_iso8601(event.nanos).unwrap_or_default(),
+            seconds.to_string(),
+            source.to_string(),
+            event.table.to_string(),
+            event.column.to_string(),
+            match event.rowid {
+                Some(r) => r.to_string(),
+                None => String::new(),
+            },
+            event.status.label().to_string(),
+            event.epoch.name().to_string(),
+            event.raw_value.to_string(),
+            event.summary.to_string(),
+        ]);
+    }
+
+    let mut written: Vec<PathBuf> = vec![];
+    for (extension, content) in [("bodyfile", bodyfile), ("tln", tln)] {
+        let file_name: String = format!("{}.{}", stem, extension);
+        let path: PathBuf = output_dir.join(&file_name);
+        File::create(&path)
+            .and_then(|mut f| f.write_all(content.as_bytes()))
+            .map_err(|e| {
+                error!("{}: {}", path.display(), e);
+                "Cannot write timeline file"
+            })?;
+        written.push(path);
+    }
+
+    let header: Vec<String> = [
+        "datetime_utc",
+        "unix_time",
+        "source",
+        "table",
+        "column",
+        "rowid",
+        "status",
+        "format",
+        "raw_value",
+        "summary",
+    ]
+    .map(String::from)
+    .to_vec();
+    let file_name: String = format!("{}_timeline.csv", stem);
+    let path: PathBuf = output_dir.join(&file_name);
+    let outfile: File = create_file(&path).map_err(|_| "Cannot write timeline file")?;
+    write_csv(outfile, header, records, &options.csv).map_err(|e| {
+        error!("{}: {}", path.display(), e);
+        "Cannot write timeline file"
+    })?;
+    written.push(path);
+
+    Ok(written)
+}
+
+/// Names of the provenance columns added to each table of the rebuilt database
+const PROVENANCE_COLUMNS: [(&str, &str); 5] = [
+    ("__rowid", "INTEGER"),
+    ("__source", "TEXT"),
+    ("__page", "INTEGER"),
+    ("__offset", "INTEGER"),
+    ("__status", "TEXT"),
+];
+
+/// Table of the rebuilt database describing the recovered tables
+const TABLES_TABLE: &str = "__tables";
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Definition of each column of the table, if found in its schema
+fn column_defs(table: &Table) -> Vec<Option<&ColumnDef>> {
+    table
+        .columns
+        .iter()
+        .map(|column| table.schema.columns.iter().find(|c| &c.name == column))
+        .collect()
+}
+
+/// Value at the given position of a row, with the storage class of its record
+fn row_value(row: &KeyedRow, position: usize, column: Option<&ColumnDef>) -> Value {
+    match row.values.get(position) {
+        Some(v) => Value::from_stored(v, row.storage_class(position), column),
+        None => Value::Null,
+    }
+}
+
+/// Columns of the table with their declared types, without constraints
+fn plain_column_definitions(table: &Table, column_defs: &[Option<&ColumnDef>]) -> Vec<String> {
+    table
+        .columns
+        .iter()
+        .zip(column_defs.iter())
+        .map(|(column, def)| match def {
+            Some(d) if !d.declared_type.is_empty() => {
+                format!("{} {}", quote_identifier(column), d.declared_type)
+            }
+            _ => quote_identifier(column),
+        })
+        .collect()
+}
+
+/// Writes a new SQLite database with the recovered tables.
+/// Each table keeps its columns and declared types (constraints are dropped, since
+/// deleted and modified versions of the same row are stored side by side)
+/// plus the provenance columns: original rowid, source file, page, offset and status.
+pub fn sqlite_run(
+    main_db_file: MainFile,
+    wal_file: Option<WALFile>,
+    outpath: &Path,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>, &'static str> {
+    info!("Write SQLite file");
+    let source: &str = &options.source;
+
+    let db: DataBase = DataBase::new(main_db_file, wal_file, options.read, &options.filter);
+
+    let mut writer: SqliteWriter = SqliteWriter::new();
+    let mut table_names: HashSet<String> = HashSet::from([TABLES_TABLE.to_string()]);
+    let mut tables_rows: Vec<Vec<Value>> = vec![];
+
+    for table in db.tables().iter() {
+        // Names starting with sqlite_ are reserved for internal tables
+        let mut name: String = match table.name.to_lowercase().starts_with("sqlite_") {
+            true => format!("__{}", table.name),
+            false => table.name.to_string(),
+        };
+        let stem: String = name.to_string();
+        let mut counter: usize = 2;
+        while !table_names.insert(name.to_lowercase()) {
+            name = format!("{}_{}", stem, counter);
+            counter += 1;
+        }
+
+        let column_defs: Vec<Option<&ColumnDef>> = column_defs(table);
+
+        let mut definitions: Vec<String> = plain_column_definitions(table, &column_defs);
+        let mut provenance_names: Vec<String> = vec![];
+        for (column, declared_type) in PROVENANCE_COLUMNS.iter() {
+            let mut column: String = column.to_string();
+            while table
+                .columns
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(&column))
+            {
+                column = format!("_{}", column);
+            }
+            definitions.push(format!("{} {}", quote_identifier(&column), declared_type));
+            provenance_names.push(column);
+        }
+        if provenance_names
+            .iter()
+            .zip(PROVENANCE_COLUMNS.iter())
+            .any(|(n, (p, _))| n != p)
+        {
+            warn!(
+                "{}: provenance columns renamed to {:?}",
+                table.name, provenance_names
+            );
+        }
+        let sql: String = format!(
+            "CREATE TABLE {} ({})",
+            quote_identifier(&name),
+            definitions.join(", ")
+        );
+
+        let rows: Vec<Vec<Value>> = table
+            .keyed_rows()
+            .iter()
+            .map(|row: &KeyedRow| {
+                let mut values: Vec<Value> = table
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, _)| row_value(row, i, column_defs[i]))
+                    .collect();
+                values.push(match row.rowid {
+                    Some(r) => Value::Integer(r),
+                    None => Value::Null,
+                });
+                values.push(Value::Text(match &row.provenance {
+                    Some(p) if p.from_wal => format!("{}-wal", source),
+                    _ => source.to_string(),
+                }));
+                values.push(match row.provenance.and_then(|p| p.page) {
+                    Some(page) => Value::Integer(page as i64),
+                    None => Value::Null,
+                });
+                values.push(match &row.provenance {
+                    Some(p) => Value::Integer(p.offset as i64),
+                    None => Value::Null,
+                });
+                values.push(Value::Text(row.status.label().to_string()));
+                values
+            })
+            .collect();
+        debug!("{}: {} rows", name, rows.len());
+
+        tables_rows.push(vec![
+            Value::Text(name.to_string()),
+            Value::Text(table.name.to_string()),
+            Value::Text(source.to_string()),
+            match &table.shadow_of {
+                Some(t) => Value::Text(t.to_string()),
+                None => Value::Null,
+            },
+            Value::Text(table.schema.sql.to_string()),
+        ]);
+        writer.add_table(&name, &sql, rows);
+    }
+
+    writer.add_table(
+        TABLES_TABLE,
+        &format!(
+            "CREATE TABLE {} (name TEXT, original_name TEXT, source TEXT, shadow_of TEXT, sql TEXT)",
+            TABLES_TABLE
+        ),
+        tables_rows,
     );
 
-    write!(outfile, "{}", db.to_json()).unwrap();
+    let mut outfile: File = create_file(outpath)?;
+    outfile
+        .write_all(&writer.into_bytes())
+        .map_err(|_| "Error writing SQLite file")?;
+    Ok(vec![outpath.to_path_buf()])
+}
+
+/// Writes an SQL dump: the original `CREATE` queries followed by the `INSERT` statements
+/// of live rows and, in commented sections, of the rows inserted in the WAL.
+/// Rows deleted and modified in the WAL go to their own tables (`<table>__deleted` and
+/// `<table>__modified`, without constraints, with the original rowid and the position in the
+/// sequence of modifications; `__` prefixed for internal tables), so that replaying the dump
+/// does not change the live rows. Tables are written in order of name, sqlite_sequence last.
+/// Dropped and carved tables are created from their columns, with the name they are extracted with.
+pub fn sql_run(
+    main_db_file: MainFile,
+    wal_file: Option<WALFile>,
+    outpath: &Path,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>, &'static str> {
+    info!("Write SQL file");
+
+    let db: DataBase = DataBase::new(main_db_file, wal_file, options.read, &options.filter);
+
+    // Tables in order of name, sqlite_sequence last: it exists once AUTOINCREMENT tables are created
+    let mut tables: Vec<&Table> = db.tables().iter().collect();
+    tables.sort_by_key(|t| {
+        (
+            t.name.eq_ignore_ascii_case("sqlite_sequence"),
+            t.name.to_lowercase(),
+        )
+    });
+
+    let mut dump: String = String::from("PRAGMA foreign_keys=OFF;\nBEGIN TRANSACTION;\n");
+    for table in tables.into_iter() {
+        dump.push_str(&format!("\n-- Table: {}\n", table.name));
+        if let Some(t) = &table.shadow_of {
+            dump.push_str(&format!("-- Shadow table of {}: filled by its module\n", t));
+            continue;
+        }
+
+        let column_defs: Vec<Option<&ColumnDef>> = column_defs(table);
+        // Dropped and carved tables may have been renamed (e.g.: `<table>_dropped`), so they are
+        // created with their name and columns, without constraints
+        let recovered: bool = table.dropped.is_some() || table.carved;
+        let is_internal: bool = table.name.to_lowercase().starts_with("sqlite_");
+        if is_internal {
+            if !table.name.eq_ignore_ascii_case("sqlite_sequence") {
+                dump.push_str("-- Internal table: skipped\n");
+                continue;
+            }
+            dump.push_str("DELETE FROM sqlite_sequence;\n");
+        } else if recovered {
+            dump.push_str(&format!(
+                "CREATE TABLE {} ({});\n",
+                quote_identifier(&table.name),
+                plain_column_definitions(table, &column_defs).join(", ")
+            ));
+        } else {
+            dump.push_str(&format!("{};\n", table.schema.sql.trim_end_matches(';')));
+        }
+
+        // Generated columns cannot be inserted (they are plain columns of recovered tables)
+        let insertable: Vec<usize> = (0..table.columns.len())
+            .filter(|&i| match column_defs[i] {
+                Some(def) => recovered || def.generated.is_none(),
+                None => true,
+            })
+            .collect();
+        // The rowid is written explicitly, unless it is already a column
+        let explicit_rowid: bool = !table.schema.without_rowid
+            && (table.schema.rowid_alias.is_none() || recovered)
+            && !is_internal
+            && !table
+                .columns
+                .iter()
+                .any(|c| ["rowid", "_rowid_", "oid"].contains(&c.to_lowercase().as_str()));
+
+        let mut columns: Vec<String> = vec![];
+        if explicit_rowid {
+            columns.push(String::from("rowid"));
+        }
+        insertable
+            .iter()
+            .for_each(|&i| columns.push(quote_identifier(&table.columns[i])));
+        let columns: String = columns.join(", ");
+
+        let rows: Vec<KeyedRow> = table.keyed_rows();
+        let mut section: Option<RowStatus> = None;
+        for row in rows.iter() {
+            let comment: &str = match row.status {
+                RowStatus::Live => "",
+                RowStatus::Inserted => "-- Rows inserted in WAL\n",
+                RowStatus::Dropped => "-- Rows of a dropped table\n",
+                RowStatus::Carved => "-- Carved records\n",
+                RowStatus::Deleted | RowStatus::Modified => continue,
+            };
+            if section != Some(row.status) {
+                section = Some(row.status);
+                dump.push_str(comment);
+            }
+
+            let mut values: Vec<String> = vec![];
+            if explicit_rowid {
+                values.push(match row.rowid {
+                    Some(r) => r.to_string(),
+                    None => String::from("NULL"),
+                });
+            }
+            insertable
+                .iter()
+                .for_each(|&i| values.push(row_value(row, i, column_defs[i]).to_sql_literal()));
+
+            dump.push_str(&format!(
+                "INSERT INTO {} ({}) VALUES ({});\n",
+                quote_identifier(&table.name),
+                columns,
+                values.join(", ")
+            ));
+        }
+
+        /* Deleted and modified rows, in tables of their own */
+        for (status, suffix, comment) in [
+            (RowStatus::Deleted, "__deleted", "-- Rows deleted in WAL\n"),
+            (
+                RowStatus::Modified,
+                "__modified",
+                "-- Rows modified in WAL (in order)\n",
+            ),
+        ] {
+            let historic: Vec<&KeyedRow> = rows.iter().filter(|r| r.status == status).collect();
+            if historic.is_empty() {
+                continue;
+            }
+            dump.push_str(comment);
+
+            // Names starting with sqlite_ are reserved for internal tables
+            let name: String = match is_internal {
+                true => format!("__{}{}", table.name, suffix),
+                false => format!("{}{}", table.name, suffix),
+            };
+            let mut extra_columns: Vec<&str> = vec!["__rowid"];
+            if status == RowStatus::Modified {
+                extra_columns.push("__sequence");
+            }
+            let mut definitions: Vec<String> = plain_column_definitions(table, &column_defs);
+            let mut names: Vec<String> =
+                table.columns.iter().map(|c| quote_identifier(c)).collect();
+            for column in extra_columns.iter() {
+                definitions.push(format!("{} INTEGER", quote_identifier(column)));
+                names.push(quote_identifier(column));
+            }
+            dump.push_str(&format!(
+                "CREATE TABLE {} ({});\n",
+                quote_identifier(&name),
+                definitions.join(", ")
+            ));
+
+            for row in historic.into_iter() {
+                let mut values: Vec<String> = (0..table.columns.len())
+                    .map(|i| row_value(row, i, column_defs[i]).to_sql_literal())
+                    .collect();
+                values.push(match row.rowid {
+                    Some(r) => r.to_string(),
+                    None => String::from("NULL"),
+                });
+                if status == RowStatus::Modified {
+                    values.push(match row.sequence {
+                        Some(s) => s.to_string(),
+                        None => String::from("NULL"),
+                    });
+                }
+                dump.push_str(&format!(
+                    "INSERT INTO {} ({}) VALUES ({});\n",
+                    quote_identifier(&name),
+                    names.join(", "),
+                    values.join(", ")
+                ));
+            }
+        }
+    }
+    dump.push_str("\nCOMMIT;\n");
+
+    let mut outfile: File = create_file(outpath)?;
+    write!(outfile, "{}", dump).map_err(|_| "Error writing SQL file")?;
+    Ok(vec![outpath.to_path_buf()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{Args, Command};
+    use crate::schema::TableSchema;
+    use crate::structs::LeafCell;
+    use crate::testing::{sqlite3, test_dir};
+    use clap::Parser;
+    use std::fs::{read, read_to_string, remove_dir_all};
+
+    /// Writes the SQL dump of a database
+    fn dump_sql(source: &Path, outpath: &Path) -> String {
+        let args: DumpArgs = match Args::try_parse_from([
+            "rustbish",
+            "dump",
+            source.to_str().unwrap(),
+            "--format",
+            "sql",
+        ]) {
+            Ok(Args {
+                command: Command::Dump(args),
+                ..
+            }) => args,
+            _ => panic!("Invalid dump options"),
+        };
+        let options: RunOptions = RunOptions::new(&args, None).unwrap();
+        let bytearray: Vec<u8> = read(source).unwrap();
+        let main_file: MainFile = MainFile::new(&bytearray).unwrap();
+        sql_run(main_file, None, outpath, &options).unwrap();
+        read_to_string(outpath).unwrap()
+    }
+
+    #[test]
+    fn sql_dump_replays_autoincrement_tables() {
+        let dir: PathBuf = test_dir("sql");
+        let source: PathBuf = dir.join("source.db");
+        // The AUTOINCREMENT table sorts after sqlite_sequence
+        sqlite3(
+            &source,
+            "CREATE TABLE zz (id INTEGER PRIMARY KEY AUTOINCREMENT, v);
+            INSERT INTO zz (v) VALUES (x'01000102'), ('0123'), (-5), (-300), (-70000), (-3000000000);
+            CREATE TABLE a (b TEXT);
+            INSERT INTO a VALUES ('NULL');",
+        );
+
+        let dump: String = dump_sql(&source, &dir.join("dump.sql"));
+        assert_eq!(dump, dump_sql(&source, &dir.join("again.sql")));
+        let position = |text: &str| dump.find(text).unwrap();
+        assert!(position("CREATE TABLE a ") < position("CREATE TABLE zz "));
+        assert!(position("CREATE TABLE zz ") < position("DELETE FROM sqlite_sequence;"));
+
+        let replayed: String = sqlite3(
+            &dir.join("replayed.db"),
+            &format!(
+                "{}SELECT quote(v) FROM zz ORDER BY id;
+                SELECT name, seq FROM sqlite_sequence;
+                SELECT quote(b) FROM a;",
+                dump
+            ),
+        );
+        remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            replayed,
+            "X'01000102'\n'0123'\n-5\n-300\n-70000\n-3000000000\nzz|6\n'NULL'\n"
+        );
+    }
+
+    #[test]
+    fn unique_file_names() {
+        let mut used: HashSet<String> = HashSet::new();
+        assert_eq!(unique_file_name("manifest", "", &mut used), "manifest.csv");
+        assert_eq!(
+            unique_file_name("Manifest", "", &mut used),
+            "Manifest_2.csv"
+        );
+        assert_eq!(unique_file_name("t", "", &mut used), "t.csv");
+        assert_eq!(unique_file_name("T", "", &mut used), "T_2.csv");
+        assert_eq!(unique_file_name("t", "", &mut used), "t_3.csv");
+        assert_eq!(unique_file_name("a/b", "", &mut used), "a_b.csv");
+        assert_eq!(unique_file_name("a_b", "", &mut used), "a_b_2.csv");
+        assert_eq!(unique_file_name("../x", "", &mut used), "_.._x.csv");
+        assert_eq!(
+            unique_file_name("a/b", "_fts5_index", &mut used),
+            "a_b_fts5_index.csv"
+        );
+    }
+
+    #[test]
+    fn csv_files_and_manifest() {
+        let dir: PathBuf = test_dir("csv");
+        let table = |name: &str, rows: usize| -> Table {
+            Table::from_rows(
+                TableSchema::parse(&format!("CREATE TABLE \"{}\" (v)", name)).unwrap(),
+                (1..=rows)
+                    .map(|i| LeafCell::from_values(Some(i as i64), vec![i.to_string()]))
+                    .collect(),
+            )
+        };
+        let db: DataBase = DataBase::from_tables(vec![
+            table("a/b", 1),
+            table("A_B", 2),
+            table("manifest", 3),
+            table("con", 1),
+        ]);
+        let options: CsvOptions = CsvOptions {
+            delimiter: b',',
+            quote_all: false,
+            bom: false,
+        };
+
+        let written: Vec<PathBuf> = write_csv_tables(&db, &dir, "test.db", &options).unwrap();
+        let names: Vec<String> = written
+            .iter()
+            .map(|p| {
+                assert_eq!(p.parent(), Some(dir.as_path()));
+                p.file_name().unwrap().to_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "a_b.csv",
+                "A_B_2.csv",
+                "manifest_2.csv",
+                "_con.csv",
+                "manifest.csv"
+            ]
+        );
+        assert_eq!(
+            read_to_string(dir.join("manifest.csv")).unwrap(),
+            "file,table,source,rows,wal_rows\n\
+            a_b.csv,a/b,test.db,1,0\n\
+            A_B_2.csv,A_B,test.db,2,0\n\
+            manifest_2.csv,manifest,test.db,3,0\n\
+            _con.csv,con,test.db,1,0\n"
+        );
+        assert_eq!(
+            read_to_string(dir.join("A_B_2.csv")).unwrap(),
+            "__ROWID__,v\n1,1\n2,2\n"
+        );
+
+        // Tables cannot be written to a missing directory
+        assert!(write_csv_tables(&db, &dir.join("missing"), "test.db", &options).is_err());
+        remove_dir_all(&dir).unwrap();
+    }
 }
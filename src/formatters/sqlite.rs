@@ -0,0 +1,561 @@
+use base64::{engine::general_purpose, Engine as _};
+
+use crate::constants::{
+    FILE_HEADER_LEN, INTERIOR_BTREE_HEADER_LEN, INTERIOR_TABLE_BTREE_PAGE, LEAF_BTREE_HEADER_LEN,
+    LEAF_TABLE_BTREE_PAGE, SQLITE_MAGIC,
+};
+use crate::schema::{Affinity, ColumnDef};
+use crate::structs::StorageClass;
+use crate::utils::write_varint;
+
+/// Page size of the generated database
+const OUT_PAGE_SIZE: usize = 4096;
+
+/// SQLite version number written in the header (3.45.0)
+const SQLITE_VERSION_NUMBER: u32 = 3045000;
+
+/// Value of a record, with its storage class
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    /// Converts a parsed value to the storage class it had in its record, if known
+    /// (values stored as text stay text, whatever they look like).
+    /// Otherwise (e.g.: DEFAULT values, rows rebuilt from shadow tables) uses `from_parsed`
+    pub fn from_stored(
+        value: &str,
+        class: Option<StorageClass>,
+        column: Option<&ColumnDef>,
+    ) -> Value {
+        match class {
+            Some(StorageClass::Null) => Value::Null,
+            Some(StorageClass::Integer) => match value.parse::<i64>() {
+                Ok(i) => Value::Integer(i),
+                Err(_) => Value::Text(value.to_string()),
+            },
+            Some(StorageClass::Real) => match value.parse::<f64>() {
+                Ok(f) => Value::Real(f),
+                Err(_) => Value::Text(value.to_string()),
+            },
+            Some(StorageClass::Text) => Value::Text(value.to_string()),
+            Some(StorageClass::Blob) => match general_purpose::STANDARD.decode(value) {
+                Ok(b) => Value::Blob(b),
+                Err(_) => Value::Text(value.to_string()),
+            },
+            None => Value::from_parsed(value, column),
+        }
+    }
+
+    /// Converts a parsed value (always a string) to the storage class suggested by the column:
+    /// numbers are stored as numbers unless the column has TEXT affinity,
+    /// base64 values of BLOB columns are decoded.
+    pub fn from_parsed(value: &str, column: Option<&ColumnDef>) -> Value {
+        if value == "NULL" {
+            return Value::Null;
+        }
+
+        let (affinity, declared_type): (Affinity, String) = match column {
+            Some(c) => (c.affinity.clone(), c.declared_type.to_uppercase()),
+            None => (Affinity::Blob, String::new()),
+        };
+
+        if declared_type.contains("BLOB") {
+            return match general_purpose::STANDARD.decode(value) {
+                Ok(b) => Value::Blob(b),
+                Err(_) => Value::Text(value.to_string()),
+            };
+        }
+        if affinity == Affinity::Text {
+            return Value::Text(value.to_string());
+        }
+
+        match value.parse::<i64>() {
+            Ok(i) => Value::Integer(i),
+            Err(_) => match value.parse::<f64>() {
+                Ok(f) if f.is_finite() => Value::Real(f),
+                _ => Value::Text(value.to_string()),
+            },
+        }
+    }
+
+    fn serial_type(&self) -> i64 {
+        match self {
+            Value::Null => 0,
+            Value::Integer(0) => 8,
+            Value::Integer(1) => 9,
+            Value::Integer(i) => match *i {
+                -0x80..=0x7f => 1,
+                -0x8000..=0x7fff => 2,
+                -0x80_0000..=0x7f_ffff => 3,
+                -0x8000_0000..=0x7fff_ffff => 4,
+                -0x8000_0000_0000..=0x7fff_ffff_ffff => 5,
+                _ => 6,
+            },
+            // SQLite stores NaN as NULL
+            Value::Real(f) if f.is_nan() => 0,
+            Value::Real(_) => 7,
+            Value::Text(t) => 13 + 2 * t.len() as i64,
+            Value::Blob(b) => 12 + 2 * b.len() as i64,
+        }
+    }
+
+    /// SQL literal of the value (text quoted, blobs as X'..')
+    pub fn to_sql_literal(&self) -> String {
+        match self {
+            Value::Null => String::from("NULL"),
+            Value::Integer(i) => i.to_string(),
+            // Infinities as written by the sqlite3 shell, NaN is stored as NULL
+            Value::Real(f) if f.is_nan() => String::from("NULL"),
+            Value::Real(f) if f.is_infinite() => {
+                format!("{}9e999", if *f < 0.0 { "-" } else { "" })
+            }
+            Value::Real(f) => format!("{:?}", f),
+            Value::Text(t) => format!("'{}'", t.replace('\'', "''")),
+            Value::Blob(b) => format!(
+                "X'{}'",
+                b.iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect::<String>()
+            ),
+        }
+    }
+
+    fn body(&self) -> Vec<u8> {
+        match self {
+            Value::Null => vec![],
+            Value::Integer(i) => {
+                let size: usize = match self.serial_type() {
+                    1..=4 => self.serial_type() as usize,
+                    5 => 6,
+                    6 => 8,
+                    _ => 0,
+                };
+                i.to_be_bytes()[8 - size..].to_vec()
+            }
+            Value::Real(f) if f.is_nan() => vec![],
+            Value::Real(f) => f.to_be_bytes().to_vec(),
+            Value::Text(t) => t.as_bytes().to_vec(),
+            Value::Blob(b) => b.to_vec(),
+        }
+    }
+}
+
+/// Encodes a record: header size, serial types, then the values
+fn encode_record(values: &[Value]) -> Vec<u8> {
+    let mut header: Vec<u8> = vec![];
+    let mut body: Vec<u8> = vec![];
+    for value in values.iter() {
+        header.extend(write_varint(value.serial_type()));
+        body.extend(value.body());
+    }
+
+    // The header size includes the varint of the size itself
+    let mut header_size: usize = header.len() + 1;
+    while header.len() + write_varint(header_size as i64).len() != header_size {
+        header_size = header.len() + write_varint(header_size as i64).len();
+    }
+
+    let mut record: Vec<u8> = write_varint(header_size as i64);
+    record.extend(header);
+    record.extend(body);
+
+    record
+}
+
+/// Writer of a new SQLite database, containing table b-trees only.
+/// Pages are built in memory: page 1 holds the file header and the root of sqlite_master.
+pub struct SqliteWriter {
+    pages: Vec<Vec<u8>>,
+    /// Name, query and root page of each table
+    tables: Vec<(String, String, u32)>,
+}
+
+impl SqliteWriter {
+    pub fn new() -> SqliteWriter {
+        SqliteWriter {
+            pages: vec![vec![0; OUT_PAGE_SIZE]],
+            tables: vec![],
+        }
+    }
+
+    /// Adds a table, whose rows get rowids starting from 1
+    pub fn add_table(&mut self, name: &str, sql: &str, rows: Vec<Vec<Value>>) {
+        let mut cells: Vec<(i64, Vec<u8>)> = vec![];
+        for (i, row) in rows.iter().enumerate() {
+            let rowid: i64 = i as i64 + 1;
+            let cell: Vec<u8> = self.leaf_cell(rowid, encode_record(row));
+            cells.push((rowid, cell));
+        }
+
+        let root: u32 = self.build_btree(cells, None);
+        self.tables.push((name.to_string(), sql.to_string(), root));
+    }
+
+    /// Writes sqlite_master and the file header, returning the content of the file
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        let mut cells: Vec<(i64, Vec<u8>)> = vec![];
+        for (i, (name, sql, root)) in self.tables.clone().iter().enumerate() {
+            let rowid: i64 = i as i64 + 1;
+            let record: Vec<u8> = encode_record(&[
+                Value::Text(String::from("table")),
+                Value::Text(name.to_string()),
+                Value::Text(name.to_string()),
+                Value::Integer(*root as i64),
+                Value::Text(sql.to_string()),
+            ]);
+            let cell: Vec<u8> = self.leaf_cell(rowid, record);
+            cells.push((rowid, cell));
+        }
+        self.build_btree(cells, Some(1));
+
+        let page_count: u32 = self.pages.len() as u32;
+        let header: &mut [u8] = &mut self.pages[0][..FILE_HEADER_LEN];
+        header[..SQLITE_MAGIC.len()].copy_from_slice(SQLITE_MAGIC.as_bytes());
+        header[16..18].copy_from_slice(&(OUT_PAGE_SIZE as u16).to_be_bytes());
+        header[18] = 1; // Legacy (rollback journal) write and read versions
+        header[19] = 1;
+        header[21] = 64; // Payload fractions
+        header[22] = 32;
+        header[23] = 32;
+        header[24..28].copy_from_slice(&1u32.to_be_bytes()); // File change counter
+        header[28..32].copy_from_slice(&page_count.to_be_bytes());
+        header[40..44].copy_from_slice(&1u32.to_be_bytes()); // Schema cookie
+        header[44..48].copy_from_slice(&4u32.to_be_bytes()); // Schema format
+        header[56..60].copy_from_slice(&1u32.to_be_bytes()); // UTF-8
+        header[92..96].copy_from_slice(&1u32.to_be_bytes()); // Version-valid-for
+        header[96..100].copy_from_slice(&SQLITE_VERSION_NUMBER.to_be_bytes());
+
+        self.pages.concat()
+    }
+
+    fn allocate(&mut self) -> u32 {
+        self.pages.push(vec![0; OUT_PAGE_SIZE]);
+        self.pages.len() as u32
+    }
+
+    /// Builds a cell of a table leaf page, moving the payload exceeding the page to overflow pages
+    fn leaf_cell(&mut self, rowid: i64, payload: Vec<u8>) -> Vec<u8> {
+        let usable: usize = OUT_PAGE_SIZE;
+        let max_local: usize = usable - 35;
+        let local: usize = match payload.len() <= max_local {
+            true => payload.len(),
+            false => {
+                let min_local: usize = ((usable - 12) * 32 / 255) - 23;
+                let k: usize = min_local + ((payload.len() - min_local) % (usable - 4));
+                match k <= max_local {
+                    true => k,
+                    false => min_local,
+                }
+            }
+        };
+
+        let mut cell: Vec<u8> = write_varint(payload.len() as i64);
+        cell.extend(write_varint(rowid));
+        cell.extend_from_slice(&payload[..local]);
+
+        if local < payload.len() {
+            // Each overflow page starts with the number of the next one
+            let chunks: Vec<&[u8]> = payload[local..].chunks(usable - 4).collect();
+            let overflow_pages: Vec<u32> = chunks.iter().map(|_| self.allocate()).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let next: u32 = overflow_pages.get(i + 1).copied().unwrap_or(0);
+                let page: &mut Vec<u8> = &mut self.pages[overflow_pages[i] as usize - 1];
+                page[..4].copy_from_slice(&next.to_be_bytes());
+                page[4..4 + chunk.len()].copy_from_slice(chunk);
+            }
+            cell.extend(overflow_pages[0].to_be_bytes());
+        }
+
+        cell
+    }
+
+    /// Builds a table b-tree from its leaf cells (sorted by rowid) and returns its root page
+    fn build_btree(&mut self, cells: Vec<(i64, Vec<u8>)>, root: Option<u32>) -> u32 {
+        let root_capacity: usize = match root {
+            Some(1) => OUT_PAGE_SIZE - FILE_HEADER_LEN,
+            _ => OUT_PAGE_SIZE,
+        };
+
+        // Small tables fit in the root page
+        let size: usize =
+            LEAF_BTREE_HEADER_LEN + cells.iter().map(|(_, c)| c.len() + 2).sum::<usize>();
+        if size <= root_capacity {
+            let page: u32 = root.unwrap_or_else(|| self.allocate());
+            let content: Vec<Vec<u8>> = cells.into_iter().map(|(_, c)| c).collect();
+            self.write_page(page, LEAF_TABLE_BTREE_PAGE, &content, None);
+            return page;
+        }
+
+        /* Leaves */
+        let mut children: Vec<(u32, i64)> = vec![];
+        let mut group: Vec<Vec<u8>> = vec![];
+        let mut used: usize = LEAF_BTREE_HEADER_LEN;
+        let mut last_rowid: i64 = 0;
+        for (rowid, cell) in cells.into_iter() {
+            if !group.is_empty() && used + cell.len() + 2 > OUT_PAGE_SIZE {
+                let page: u32 = self.allocate();
+                self.write_page(page, LEAF_TABLE_BTREE_PAGE, &group, None);
+                children.push((page, last_rowid));
+                group.clear();
+                used = LEAF_BTREE_HEADER_LEN;
+            }
+            used += cell.len() + 2;
+            group.push(cell);
+            last_rowid = rowid;
+        }
+        let page: u32 = self.allocate();
+        self.write_page(page, LEAF_TABLE_BTREE_PAGE, &group, None);
+        children.push((page, last_rowid));
+
+        /* Interior pages, up to the root */
+        loop {
+            let mut groups: Vec<Vec<(u32, i64)>> = vec![];
+            let mut group: Vec<(u32, i64)> = vec![];
+            let mut used: usize = INTERIOR_BTREE_HEADER_LEN;
+            for child in children.into_iter() {
+                // The previous last child becomes a cell, the new one is the right-most pointer
+                let cost: usize = match group.last() {
+                    Some(&(_, key)) => 4 + write_varint(key).len() + 2,
+                    None => 0,
+                };
+                if !group.is_empty() && used + cost > OUT_PAGE_SIZE {
+                    groups.push(std::mem::take(&mut group));
+                    used = INTERIOR_BTREE_HEADER_LEN;
+                } else {
+                    used += cost;
+                }
+                group.push(child);
+            }
+            groups.push(group);
+
+            // Interior pages need at least one cell besides the right-most pointer
+            let count: usize = groups.len();
+            if count > 1 && groups[count - 1].len() == 1 {
+                let moved: (u32, i64) = groups[count - 2].pop().unwrap();
+                groups[count - 1].insert(0, moved);
+            }
+
+            if groups.len() == 1 {
+                let size: usize =
+                    INTERIOR_BTREE_HEADER_LEN + 6 * groups[0].len() + 9 * groups[0].len();
+                if size <= root_capacity {
+                    let page: u32 = root.unwrap_or_else(|| self.allocate());
+                    self.write_interior_page(page, &groups[0]);
+                    return page;
+                }
+                // Too large for page 1: split in two
+                let middle: usize = groups[0].len() / 2;
+                let half: Vec<(u32, i64)> = groups[0].split_off(middle);
+                groups.push(half);
+            }
+
+            children = vec![];
+            for group in groups.iter() {
+                let page: u32 = self.allocate();
+                self.write_interior_page(page, group);
+                children.push((page, group.last().unwrap().1));
+            }
+        }
+    }
+
+    fn write_interior_page(&mut self, page: u32, children: &[(u32, i64)]) {
+        let cells: Vec<Vec<u8>> = children[..children.len() - 1]
+            .iter()
+            .map(|(child, key)| {
+                let mut cell: Vec<u8> = child.to_be_bytes().to_vec();
+                cell.extend(write_varint(*key));
+                cell
+            })
+            .collect();
+        let right_most: u32 = children.last().unwrap().0;
+        self.write_page(page, INTERIOR_TABLE_BTREE_PAGE, &cells, Some(right_most));
+    }
+
+    /// Writes the header, the cell pointer array and the cells (from the end of the page)
+    fn write_page(&mut self, page: u32, page_type: u8, cells: &[Vec<u8>], right_most: Option<u32>) {
+        let header_offset: usize = match page {
+            1 => FILE_HEADER_LEN,
+            _ => 0,
+        };
+        let header_len: usize = match right_most {
+            Some(_) => INTERIOR_BTREE_HEADER_LEN,
+            None => LEAF_BTREE_HEADER_LEN,
+        };
+
+        let buffer: &mut Vec<u8> = &mut self.pages[page as usize - 1];
+        let mut content_start: usize = OUT_PAGE_SIZE;
+        let mut pointer: usize = header_offset + header_len;
+        for cell in cells.iter() {
+            content_start -= cell.len();
+            buffer[content_start..content_start + cell.len()].copy_from_slice(cell);
+            buffer[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+            pointer += 2;
+        }
+
+        buffer[header_offset] = page_type;
+        buffer[header_offset + 1..header_offset + 3].copy_from_slice(&0u16.to_be_bytes());
+        buffer[header_offset + 3..header_offset + 5]
+            .copy_from_slice(&(cells.len() as u16).to_be_bytes());
+        buffer[header_offset + 5..header_offset + 7]
+            .copy_from_slice(&(content_start as u16).to_be_bytes());
+        buffer[header_offset + 7] = 0;
+        if let Some(p) = right_most {
+            buffer[header_offset + 8..header_offset + 12].copy_from_slice(&p.to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carver::parse_record_header;
+    use crate::schema::TableSchema;
+    use crate::structs::LeafCell;
+
+    #[test]
+    fn record_serial_types_and_values() {
+        let record: Vec<u8> = encode_record(&[
+            Value::Null,
+            Value::Integer(0),
+            Value::Integer(1),
+            Value::Integer(300),
+            Value::Text(String::from("ab")),
+            Value::Blob(vec![1, 2]),
+            Value::Real(1.5),
+        ]);
+        let mut expected: Vec<u8> = vec![8, 0, 8, 9, 2, 17, 16, 7, 0x01, 0x2C, b'a', b'b', 1, 2];
+        expected.extend(1.5f64.to_be_bytes());
+        assert_eq!(record, expected);
+    }
+
+    #[test]
+    fn nan_is_null() {
+        let record: Vec<u8> = encode_record(&[
+            Value::Real(f64::NAN),
+            Value::Real(f64::INFINITY),
+            Value::Integer(1),
+        ]);
+        let mut expected: Vec<u8> = vec![4, 0, 7, 9];
+        expected.extend(f64::INFINITY.to_be_bytes());
+        assert_eq!(record, expected);
+    }
+
+    #[test]
+    fn integer_sizes() {
+        let sizes: Vec<(i64, i64, usize)> = vec![
+            (-1, 1, 1),
+            (127, 1, 1),
+            (-129, 2, 2),
+            (0x7f_ffff, 3, 3),
+            (-0x8000_0000, 4, 4),
+            (0x7fff_ffff_ffff, 5, 6),
+            (i64::MIN, 6, 8),
+        ];
+        for (value, serial_type, size) in sizes.into_iter() {
+            let record: Vec<u8> = encode_record(&[Value::Integer(value)]);
+            assert_eq!(record[..2], [2, serial_type as u8], "{}", value);
+            assert_eq!(record[2..], value.to_be_bytes()[8 - size..], "{}", value);
+        }
+    }
+
+    #[test]
+    fn header_size_with_two_byte_varint() {
+        // 130 serial types and the 2-byte size itself
+        let record: Vec<u8> = encode_record(&vec![Value::Null; 130]);
+        assert_eq!(record[..2], [0x81, 0x04]);
+        assert_eq!(record.len(), 132);
+
+        let text: String = "x".repeat(200);
+        let record: Vec<u8> = encode_record(&[Value::Text(text.to_string()), Value::Integer(7)]);
+        let mut cell: Vec<u8> = write_varint(record.len() as i64);
+        cell.push(1); // rowid
+        cell.extend(record);
+        let shape = parse_record_header(&cell, 0, cell.len()).unwrap();
+        assert_eq!(shape.serial_types, vec![13 + 400, 1]);
+        assert_eq!(shape.cell_len, cell.len());
+    }
+
+    #[test]
+    fn values_from_parsed_strings() {
+        let schema: TableSchema =
+            TableSchema::parse("CREATE TABLE t (a TEXT, b INTEGER, c BLOB, d)").unwrap();
+        let column = |i: usize| Some(&schema.columns[i]);
+
+        assert_eq!(Value::from_parsed("NULL", column(0)), Value::Null);
+        assert_eq!(
+            Value::from_parsed("12", column(0)),
+            Value::Text(String::from("12"))
+        );
+        assert_eq!(Value::from_parsed("12", column(1)), Value::Integer(12));
+        assert_eq!(Value::from_parsed("1.5", column(1)), Value::Real(1.5));
+        assert_eq!(
+            Value::from_parsed("AQI=", column(2)),
+            Value::Blob(vec![1, 2])
+        );
+        assert_eq!(
+            Value::from_parsed("not base64!", column(2)),
+            Value::Text(String::from("not base64!"))
+        );
+        assert_eq!(Value::from_parsed("-3", column(3)), Value::Integer(-3));
+        assert_eq!(
+            Value::from_parsed("inf", None),
+            Value::Text(String::from("inf"))
+        );
+    }
+
+    #[test]
+    fn values_keep_their_storage_class() {
+        let schema: TableSchema =
+            TableSchema::parse("CREATE TABLE t (u, s TEXT, n, i INTEGER)").unwrap();
+        let values: Vec<Value> = vec![
+            Value::Blob(vec![1, 0, 1, 2]),
+            Value::Text(String::from("0123")),
+            Value::Text(String::from("NULL")),
+            Value::Null,
+        ];
+        let record: Vec<u8> = encode_record(&values);
+        let mut cell: Vec<u8> = write_varint(record.len() as i64);
+        cell.push(1); // rowid
+        cell.extend(record.iter());
+
+        let parsed: LeafCell = LeafCell::new_in_page(&cell, 0, LEAF_TABLE_BTREE_PAGE, 4096);
+        let stored: Vec<Value> = parsed
+            .values()
+            .iter()
+            .zip(parsed.storage_classes().iter())
+            .zip(schema.columns.iter())
+            .map(|((v, class), column)| Value::from_stored(v, *class, Some(column)))
+            .collect();
+        assert_eq!(stored, values);
+        assert_eq!(
+            stored
+                .iter()
+                .map(|v| v.to_sql_literal())
+                .collect::<Vec<String>>(),
+            vec!["X'01000102'", "'0123'", "'NULL'", "NULL"]
+        );
+
+        // Without the storage class, the column decides
+        assert_eq!(
+            Value::from_stored("0123", None, Some(&schema.columns[3])),
+            Value::Integer(123)
+        );
+    }
+
+    #[test]
+    fn sql_literals() {
+        assert_eq!(Value::Null.to_sql_literal(), "NULL");
+        assert_eq!(Value::Real(2.0).to_sql_literal(), "2.0");
+        assert_eq!(
+            Value::Text(String::from("it's")).to_sql_literal(),
+            "'it''s'"
+        );
+        assert_eq!(Value::Blob(vec![0xAB, 1]).to_sql_literal(), "X'AB01'");
+    }
+}
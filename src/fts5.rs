@@ -0,0 +1,514 @@
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::utils::read_varint;
+
+/* Rowids of special records in the %_data table */
+pub const FTS5_AVERAGES_ROWID: i64 = 1;
+pub const FTS5_STRUCTURE_ROWID: i64 = 10;
+
+/* Bits of the rowid of segment pages: | segid (16) | dlidx (1) | height (5) | pgno (31) | */
+const FTS5_DATA_PAGE_B: i64 = 31;
+const FTS5_DATA_HEIGHT_B: i64 = 5;
+const FTS5_DATA_DLI_B: i64 = 1;
+
+/// Marker following the cookie of a structure record in the V2 format
+const FTS5_STRUCTURE_V2: [u8; 4] = [0xFF, 0x00, 0x00, 0x01];
+
+/// First byte of terms of the main index (prefix indexes use '1', '2', ...)
+const FTS5_MAIN_PREFIX: u8 = b'0';
+
+/// Segment listed in the structure record
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Fts5Segment {
+    pub id: i64,
+    pub level: usize,
+    pub first_leaf: i64,
+    pub last_leaf: i64,
+}
+
+/// Occurrence of a term in a column of a row, decoded from the segment leaves
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Fts5Entry {
+    pub term: String,
+    pub rowid: i64,
+    pub column: i64,
+    pub position: i64,
+    /// The rowid no longer exists in the content table
+    pub orphan: bool,
+}
+
+/// Inverted index of an FTS5 table
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Fts5Index {
+    pub segments: Vec<Fts5Segment>,
+    pub entries: Vec<Fts5Entry>,
+    /// Rowids found in the index but not in the content table
+    pub orphan_rowids: Vec<i64>,
+}
+
+/// Position list which continues in the next leaf page
+struct PendingPoslist {
+    term: Vec<u8>,
+    rowid: i64,
+    bytes: Vec<u8>,
+    remaining: usize,
+}
+
+impl Fts5Index {
+    /// Decodes the blocks of the %_data table (pairs of id and blob).
+    /// `content_rowids` are the rowids of the content table, if known, used to find orphan terms.
+    pub fn new(blocks: &[(i64, Vec<u8>)], content_rowids: Option<&HashSet<i64>>) -> Fts5Index {
+        let segments: Vec<Fts5Segment> =
+            match blocks.iter().find(|(id, _)| *id == FTS5_STRUCTURE_ROWID) {
+                Some((_, blob)) => match parse_structure(blob) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        warn!("FTS5 structure record: {}", e);
+                        vec![]
+                    }
+                },
+                None => {
+                    warn!("FTS5 structure record not found");
+                    vec![]
+                }
+            };
+        debug!("FTS5 segments: {:?}", segments);
+
+        // segid -> pgno -> leaf page
+        let mut leaves: BTreeMap<i64, BTreeMap<i64, &Vec<u8>>> = BTreeMap::new();
+        for (id, blob) in blocks.iter() {
+            if *id == FTS5_AVERAGES_ROWID || *id == FTS5_STRUCTURE_ROWID {
+                continue;
+            }
+            let segid: i64 = id >> (FTS5_DATA_PAGE_B + FTS5_DATA_HEIGHT_B + FTS5_DATA_DLI_B);
+            let dlidx: i64 = (id >> (FTS5_DATA_PAGE_B + FTS5_DATA_HEIGHT_B)) & 1;
+            let pgno: i64 = id & ((1 << FTS5_DATA_PAGE_B) - 1);
+            // Doclist-index pages do not contain terms
+            if segid == 0 || dlidx == 1 {
+                continue;
+            }
+            leaves.entry(segid).or_default().insert(pgno, blob);
+        }
+
+        let mut seen: HashSet<Fts5Entry> = HashSet::new();
+        let mut entries: Vec<Fts5Entry> = vec![];
+        for (segid, pages) in leaves.iter() {
+            debug!("FTS5 segment {}: {} leaves", segid, pages.len());
+            for entry in decode_segment(pages.values().map(|p| p.as_slice())) {
+                if seen.insert(entry.clone()) {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        let mut orphan_rowids: Vec<i64> = vec![];
+        match content_rowids {
+            Some(rowids) => {
+                for entry in entries.iter_mut() {
+                    if !rowids.contains(&entry.rowid) {
+                        entry.orphan = true;
+                        if !orphan_rowids.contains(&entry.rowid) {
+                            orphan_rowids.push(entry.rowid);
+                        }
+                    }
+                }
+            }
+            None => debug!("FTS5 content rowids unknown: orphan terms not flagged"),
+        }
+        orphan_rowids.sort();
+
+        Fts5Index {
+            segments,
+            entries,
+            orphan_rowids,
+        }
+    }
+
+    pub fn csv_header(&self) -> Vec<String> {
+        ["term", "rowid", "column", "position", "orphan"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    pub fn csv_records(&self) -> Vec<Vec<String>> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                vec![
+                    entry.term.to_string(),
+                    entry.rowid.to_string(),
+                    entry.column.to_string(),
+                    entry.position.to_string(),
+                    (entry.orphan as u8).to_string(),
+                ]
+            })
+            .collect()
+    }
+}
+
+/// Reads a varint checking the bounds of the buffer
+fn varint_at(bytes: &[u8], offset: usize) -> Option<(i64, usize)> {
+    if offset >= bytes.len() {
+        return None;
+    }
+    Some(read_varint(&bytes[offset..]))
+}
+
+/// Parses the structure record: cookie, levels and segments of each level
+fn parse_structure(blob: &[u8]) -> Result<Vec<Fts5Segment>, &'static str> {
+    let err: &'static str = "Truncated structure record";
+    if blob.len() < 4 {
+        return Err(err);
+    }
+
+    let mut i: usize = 4; // Configuration cookie
+    let is_v2: bool = blob.len() >= 8 && blob[4..8] == FTS5_STRUCTURE_V2;
+    if is_v2 {
+        i += 4;
+    }
+
+    let mut next = || -> Result<i64, &'static str> {
+        let (value, len): (i64, usize) = varint_at(blob, i).ok_or(err)?;
+        i += len;
+        Ok(value)
+    };
+
+    let levels: i64 = next()?;
+    let _segment_count: i64 = next()?;
+    let _write_counter: i64 = next()?;
+
+    let mut segments: Vec<Fts5Segment> = vec![];
+    for level in 0..levels as usize {
+        let _merge: i64 = next()?;
+        let total: i64 = next()?;
+        for _ in 0..total {
+            let id: i64 = next()?;
+            let first_leaf: i64 = next()?;
+            let last_leaf: i64 = next()?;
+            if is_v2 {
+                // Origins, tombstone pages and entries count
+                for _ in 0..5 {
+                    next()?;
+                }
+            }
+            segments.push(Fts5Segment {
+                id,
+                level,
+                first_leaf,
+                last_leaf,
+            });
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Decodes the leaves of a segment, in page order.
+///
+/// Each leaf starts with the offset of its first rowid (if it comes before the first term)
+/// and the offset of the page footer, which holds the delta-encoded offsets of the terms.
+/// The first term of a page is stored in full, the others share a prefix with the previous term.
+/// Each term is followed by its doclist: the first rowid, then rowid deltas,
+/// each one followed by its position list (which may continue in the next page).
+/// Leaves with offsets or sizes out of bounds (e.g.: carved or corrupt blobs) are skipped
+fn decode_segment<'a>(pages: impl Iterator<Item = &'a [u8]>) -> Vec<Fts5Entry> {
+    let mut entries: Vec<Fts5Entry> = vec![];
+    let mut term: Vec<u8> = vec![];
+    let mut rowid: i64 = 0;
+    let mut pending: Option<PendingPoslist> = None;
+
+    for page in pages {
+        if decode_leaf(page, &mut term, &mut rowid, &mut pending, &mut entries).is_none() {
+            debug!("Invalid FTS5 leaf skipped");
+            pending = None;
+        }
+    }
+
+    entries
+}
+
+/// Decodes a leaf of a segment, continuing the term, rowid and position list of the previous one
+fn decode_leaf(
+    page: &[u8],
+    term: &mut Vec<u8>,
+    rowid: &mut i64,
+    pending: &mut Option<PendingPoslist>,
+    entries: &mut Vec<Fts5Entry>,
+) -> Option<()> {
+    if page.len() < 4 {
+        return None;
+    }
+    let first_rowid: usize = u16::from_be_bytes([page[0], page[1]]) as usize;
+    let footer: usize = (u16::from_be_bytes([page[2], page[3]]) as usize).min(page.len());
+
+    let mut term_offsets: Vec<usize> = vec![];
+    let mut i: usize = footer;
+    let mut offset: usize = 0;
+    while let Some((delta, len)) = varint_at(page, i) {
+        offset = offset.checked_add(usize::try_from(delta).ok()?)?;
+        term_offsets.push(offset);
+        i += len;
+    }
+
+    // Varints are never read past the footer
+    let body: &[u8] = &page[..footer];
+    let mut pos: usize = 4;
+
+    // Position list started in the previous page
+    if let Some(mut p) = pending.take() {
+        let available: usize = p.remaining.min(footer.saturating_sub(pos));
+        p.bytes.extend_from_slice(&body[pos..pos + available]);
+        p.remaining -= available;
+        pos += available;
+        if p.remaining > 0 {
+            *pending = Some(p);
+            return Some(());
+        }
+        push_positions(entries, &p.term, p.rowid, &p.bytes);
+    }
+
+    // Bytes from `pos` to `pos + size`, which must end before the footer
+    let slice_end = |pos: usize, size: i64| -> Option<usize> {
+        let end: usize = pos.checked_add(usize::try_from(size).ok()?)?;
+        match end <= footer {
+            true => Some(end),
+            false => None,
+        }
+    };
+
+    while pos < footer {
+        if term_offsets.contains(&pos) {
+            if pos == term_offsets[0] {
+                let (size, len) = varint_at(body, pos)?;
+                pos += len;
+                let end: usize = slice_end(pos, size)?;
+                *term = body[pos..end].to_vec();
+                pos = end;
+            } else {
+                let (prefix, len) = varint_at(body, pos)?;
+                pos += len;
+                let (suffix, len) = varint_at(body, pos)?;
+                pos += len;
+                let end: usize = slice_end(pos, suffix)?;
+                term.truncate(usize::try_from(prefix).ok()?);
+                term.extend_from_slice(&body[pos..end]);
+                pos = end;
+            }
+
+            // First rowid of a doclist is absolute
+            let (value, len) = varint_at(body, pos)?;
+            *rowid = value;
+            pos += len;
+        } else {
+            let (value, len) = varint_at(body, pos)?;
+            // First rowid of a page is absolute, the others are deltas
+            if pos == first_rowid {
+                *rowid = value;
+            } else {
+                *rowid = rowid.checked_add(value)?;
+            }
+            pos += len;
+        }
+
+        // Position list: size * 2 (+ 1 if it is a delete marker), then positions
+        let (header, len) = match varint_at(body, pos) {
+            Some(v) => v,
+            None => break,
+        };
+        pos += len;
+        let size: usize = usize::try_from(header >> 1).ok()?;
+        let available: usize = size.min(footer - pos);
+        let bytes: Vec<u8> = body[pos..pos + available].to_vec();
+        pos += available;
+
+        if available < size {
+            *pending = Some(PendingPoslist {
+                term: term.clone(),
+                rowid: *rowid,
+                bytes,
+                remaining: size - available,
+            });
+            break;
+        }
+        push_positions(entries, term, *rowid, &bytes);
+    }
+
+    Some(())
+}
+
+/// Decodes a position list: varints of (position delta + 2),
+/// where a 0x01 is followed by the number of the next column
+fn push_positions(entries: &mut Vec<Fts5Entry>, term: &[u8], rowid: i64, poslist: &[u8]) {
+    // Terms of prefix indexes duplicate the ones of the main index
+    if term.first() != Some(&FTS5_MAIN_PREFIX) {
+        return;
+    }
+    let term: String = String::from_utf8_lossy(&term[1..]).to_string();
+
+    let mut column: i64 = 0;
+    let mut position: i64 = 0;
+    let mut i: usize = 0;
+    while let Some((mut value, mut len)) = varint_at(poslist, i) {
+        i += len;
+        if value == 1 {
+            (column, len) = match varint_at(poslist, i) {
+                Some(v) => v,
+                None => break,
+            };
+            i += len;
+            position = 0;
+            (value, len) = match varint_at(poslist, i) {
+                Some(v) => v,
+                None => break,
+            };
+            i += len;
+        }
+        if value < 2 {
+            continue;
+        }
+        position = match position.checked_add(value - 2) {
+            Some(p) => p,
+            None => break,
+        };
+
+        entries.push(Fts5Entry {
+            term: term.to_string(),
+            rowid,
+            column,
+            position,
+            orphan: false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Leaf page: first rowid offset, footer offset, content, then term offset deltas
+    fn leaf(first_rowid: u16, content: &[u8], term_deltas: &[u8]) -> Vec<u8> {
+        let mut page: Vec<u8> = vec![];
+        page.extend_from_slice(&first_rowid.to_be_bytes());
+        page.extend_from_slice(&(4 + content.len() as u16).to_be_bytes());
+        page.extend_from_slice(content);
+        page.extend_from_slice(term_deltas);
+        page
+    }
+
+    fn summary(entries: &[Fts5Entry]) -> Vec<(String, i64, i64, i64)> {
+        entries
+            .iter()
+            .map(|e| (e.term.to_string(), e.rowid, e.column, e.position))
+            .collect()
+    }
+
+    #[test]
+    fn terms_with_shared_prefix() {
+        let content: Vec<u8> = [
+            &[4][..],
+            b"0abc",
+            &[5, 4, 2, 5],    // rowid 5, positions 0 and 3
+            &[2, 2, 3],       // rowid 7, position 1
+            &[3, 1, b'd'],    // "0ab" + "d"
+            &[9, 6, 1, 1, 2], // rowid 9, column 1, position 0
+        ]
+        .concat();
+        let page: Vec<u8> = leaf(0, &content, &[4, 12]);
+
+        assert_eq!(
+            summary(&decode_segment([page.as_slice()].into_iter())),
+            vec![
+                ("abc".to_string(), 5, 0, 0),
+                ("abc".to_string(), 5, 0, 3),
+                ("abc".to_string(), 7, 0, 1),
+                ("abd".to_string(), 9, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn position_list_continuing_in_next_leaf() {
+        // Position list of 3 bytes, 2 of which are in the first leaf
+        let first: Vec<u8> = leaf(0, &[[3].as_slice(), b"0xy", &[1, 6, 2, 3]].concat(), &[4]);
+        // Last byte of the position list, then rowid 2 (absolute, as first rowid of the page)
+        let second: Vec<u8> = leaf(5, &[4, 2, 2, 2], &[]);
+
+        assert_eq!(
+            summary(&decode_segment(
+                [first.as_slice(), second.as_slice()].into_iter()
+            )),
+            vec![
+                ("xy".to_string(), 1, 0, 0),
+                ("xy".to_string(), 1, 0, 1),
+                ("xy".to_string(), 1, 0, 3),
+                ("xy".to_string(), 2, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_index_terms_are_ignored() {
+        let page: Vec<u8> = leaf(0, &[[2].as_slice(), b"1a", &[1, 2, 2]].concat(), &[4]);
+        assert!(decode_segment([page.as_slice()].into_iter()).is_empty());
+    }
+
+    #[test]
+    fn corrupt_leaves_are_skipped() {
+        let valid: Vec<u8> = leaf(0, &[[2].as_slice(), b"0a", &[1, 2, 2]].concat(), &[4]);
+        let corrupt: Vec<Vec<u8>> = vec![
+            // Term length varint ending past the footer
+            leaf(0, &[0x81], &[4]),
+            // Term longer than the leaf
+            leaf(0, &[[0x7F].as_slice(), b"0a"].concat(), &[4]),
+            // Negative term offset delta
+            leaf(
+                0,
+                &[2, b'0', b'a'],
+                &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+            ),
+            // Rowid delta overflowing
+            leaf(
+                0,
+                &[
+                    [2].as_slice(),
+                    b"0a",
+                    &[0xBF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 2, 2],
+                    &[1, 2, 2],
+                ]
+                .concat(),
+                &[4],
+            ),
+            // Footer offset past the end of the leaf
+            vec![0, 0, 0xFF, 0xFF, 3],
+            vec![0, 0],
+        ];
+
+        for page in corrupt.iter() {
+            let pages = [page.as_slice(), valid.as_slice()];
+            let entries: Vec<Fts5Entry> = decode_segment(pages.into_iter());
+            assert_eq!(
+                entries.last().map(|e| (e.term.as_str(), e.rowid)),
+                Some(("a", 1))
+            );
+        }
+    }
+
+    #[test]
+    fn structure_record() {
+        // Cookie, 1 level, 1 segment, write counter, then merge, count and segment 1 (leaves 1-3)
+        let blob: Vec<u8> = vec![0, 0, 0, 1, 1, 1, 5, 0, 1, 1, 1, 3];
+        let segments: Vec<Fts5Segment> = parse_structure(&blob).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(
+            (
+                segments[0].id,
+                segments[0].first_leaf,
+                segments[0].last_leaf
+            ),
+            (1, 1, 3)
+        );
+        assert!(parse_structure(&blob[..9]).is_err());
+    }
+}
@@ -0,0 +1,522 @@
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::carver::{parse_record_header, RecordShape};
+use crate::db::Table;
+use crate::schema::{Affinity, ColumnDef, TableSchema};
+use crate::structs::{LeafCell, StorageClass};
+use crate::utils::sanitize_file_name;
+
+/// Weight of the confidence of schemas inferred from the records alone, without a table of
+/// sqlite_master accepting them
+const UNMATCHED_WEIGHT: f64 = 0.5;
+
+impl StorageClass {
+    const VALUES: [StorageClass; 4] = [
+        StorageClass::Integer,
+        StorageClass::Real,
+        StorageClass::Text,
+        StorageClass::Blob,
+    ];
+
+    /// Integers and reals are both stored in numeric columns
+    fn compatible(&self, other: &StorageClass) -> bool {
+        self == other
+            || (self.is_numeric() && other.is_numeric())
+            || *self == StorageClass::Null
+            || *other == StorageClass::Null
+    }
+
+    fn is_numeric(&self) -> bool {
+        *self == StorageClass::Integer || *self == StorageClass::Real
+    }
+
+    fn letter(&self) -> char {
+        match self {
+            StorageClass::Null => 'N',
+            StorageClass::Integer => 'I',
+            StorageClass::Real => 'R',
+            StorageClass::Text => 'T',
+            StorageClass::Blob => 'B',
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            StorageClass::Null => "",
+            StorageClass::Integer => "INTEGER",
+            StorageClass::Real => "REAL",
+            StorageClass::Text => "TEXT",
+            StorageClass::Blob => "BLOB",
+        }
+    }
+}
+
+/// Records with the same number of values, whose non-NULL values have compatible storage classes
+struct Cluster {
+    /// Values of each column, by storage class (same order of `StorageClass::VALUES`)
+    counts: Vec<[usize; 4]>,
+    rows: Vec<LeafCell>,
+}
+
+impl Cluster {
+    /// Storage class of most of the non-NULL values of a column (REAL if integers and reals are mixed)
+    fn column_class(&self, column: usize) -> StorageClass {
+        let counts: &[usize; 4] = &self.counts[column];
+        let (i, count): (usize, &usize) =
+            counts.iter().enumerate().max_by_key(|(_, c)| **c).unwrap();
+        match StorageClass::VALUES[i] {
+            _ if *count == 0 => StorageClass::Null,
+            StorageClass::Integer if counts[1] > 0 => StorageClass::Real,
+            class => class,
+        }
+    }
+
+    fn accepts(&self, classes: &[StorageClass]) -> bool {
+        classes.len() == self.counts.len()
+            && classes
+                .iter()
+                .enumerate()
+                .all(|(i, class)| self.column_class(i).compatible(class))
+    }
+
+    fn add(&mut self, classes: &[StorageClass], row: LeafCell) {
+        for (i, class) in classes.iter().enumerate() {
+            if let Some(position) = StorageClass::VALUES.iter().position(|c| c == class) {
+                self.counts[i][position] += 1;
+            }
+        }
+        self.rows.push(row);
+    }
+
+    /// Share of the non-NULL values of a column compatible with its storage class
+    fn consistency(&self, column: usize) -> (usize, usize) {
+        let class: StorageClass = self.column_class(column);
+        let counts: &[usize; 4] = &self.counts[column];
+        let agreeing: usize = StorageClass::VALUES
+            .iter()
+            .zip(counts.iter())
+            .filter(|(c, _)| class.compatible(c))
+            .map(|(_, n)| n)
+            .sum();
+
+        (agreeing, counts.iter().sum())
+    }
+}
+
+/// A column of an inferred schema
+#[derive(Serialize)]
+pub struct InferredColumn {
+    pub name: String,
+    /// Empty if the column holds only NULLs
+    #[serde(rename = "type")]
+    pub declared_type: String,
+    /// Share of the non-NULL values having the storage class of the type
+    pub consistency: f64,
+}
+
+/// Schema given to a cluster of recovered records
+#[derive(Serialize)]
+pub struct InferredSchema {
+    pub name: String,
+    pub records: usize,
+    /// Storage classes of the columns: (I)NTEGER, (R)EAL, (T)EXT, (B)LOB, (N)ULL only
+    pub signature: String,
+    pub columns: Vec<InferredColumn>,
+    /// Column holding the rowid: the alias of the matched table or, without a match, the first column
+    /// if always NULL (likely an `INTEGER PRIMARY KEY`)
+    pub rowid_alias: Option<String>,
+    pub sql: String,
+    /// Table of sqlite_master whose columns accept the records
+    pub matched_table: Option<String>,
+    /// Other tables accepting them as well
+    pub other_candidates: Vec<String>,
+    /// Columns of the matched table whose affinity agrees with the values of the records
+    /// (BLOB columns and columns always NULL accept anything, so they are not compared)
+    pub compared_columns: usize,
+    /// Share of the non-NULL values having the storage class of their column, times the share of
+    /// the columns compared with the matched table (its number of columns counting as one more),
+    /// divided among the candidate tables. Without a match, the share of columns with a type, halved
+    pub confidence: f64,
+}
+
+/// Columns of a table stored in the record
+fn stored_columns(schema: &TableSchema) -> Vec<&ColumnDef> {
+    schema.columns.iter().filter(|c| c.is_stored()).collect()
+}
+
+/// A table accepts the records if it has as many columns as their values (or more, if the other
+/// ones can be missing from records written before an `ALTER TABLE ... ADD COLUMN`: they have a
+/// DEFAULT or accept NULL), if its rowid alias is NULL in them and if the storage classes of their
+/// values can be stored with the affinity of its columns.
+/// Returns the columns with a specific affinity (not BLOB) agreeing with the records
+fn match_score(cluster: &Cluster, schema: &TableSchema) -> Option<usize> {
+    let columns: Vec<&ColumnDef> = stored_columns(schema);
+    let values: usize = cluster.counts.len();
+    if schema.without_rowid || schema.module.is_some() || columns.len() < values {
+        return None;
+    }
+    if columns[values..]
+        .iter()
+        .any(|c| c.not_null && c.default.is_none())
+    {
+        return None;
+    }
+    /* The alias is counted among the declared columns, the values only hold the stored ones */
+    let alias: Option<usize> = schema.rowid_alias.map(|alias| {
+        schema.columns[..alias]
+            .iter()
+            .filter(|c| c.is_stored())
+            .count()
+    });
+    match alias {
+        Some(alias) if alias >= values || cluster.column_class(alias) != StorageClass::Null => {
+            return None
+        }
+        _ => (),
+    }
+
+    let mut score: usize = 0;
+    for (i, column) in columns.iter().take(values).enumerate() {
+        let class: StorageClass = cluster.column_class(i);
+        let agrees: bool = match column.affinity {
+            Affinity::Blob => continue,
+            _ if class == StorageClass::Null => continue,
+            Affinity::Text => class == StorageClass::Text,
+            Affinity::Integer | Affinity::Real | Affinity::Numeric => class.is_numeric(),
+        };
+        match agrees {
+            true => score += 1,
+            false => return None,
+        }
+    }
+
+    Some(score)
+}
+
+/// Clusters recovered records by number of values and storage classes, giving each cluster a table
+/// with an inferred schema. If a table of `known` accepts the records, its columns are used
+/// (the one with the most columns agreeing with the records is preferred, then the one with
+/// the fewest columns missing from them).
+/// The serial types of the records are read from the bytes of their cells
+pub fn infer_tables(
+    cells: Vec<LeafCell>,
+    known: &[TableSchema],
+) -> (Vec<Table>, Vec<InferredSchema>) {
+    let mut clusters: Vec<Cluster> = vec![];
+    let mut unparsable: usize = 0;
+    for cell in cells.into_iter() {
+        let shape: Option<RecordShape> = match cell.provenance() {
+            Some(p) => parse_record_header(&p.raw, 0, p.raw.len()),
+            None => None,
+        };
+        let classes: Vec<StorageClass> = match shape {
+            Some(s) => s
+                .serial_types
+                .iter()
+                .map(|t| StorageClass::from_serial_type(*t))
+                .collect(),
+            None => {
+                unparsable += 1;
+                continue;
+            }
+        };
+
+        match clusters.iter_mut().find(|c| c.accepts(&classes)) {
+            Some(cluster) => cluster.add(&classes, cell),
+            None => {
+                let mut cluster: Cluster = Cluster {
+                    counts: vec![[0; 4]; classes.len()],
+                    rows: vec![],
+                };
+                cluster.add(&classes, cell);
+                clusters.push(cluster);
+            }
+        }
+    }
+    if unparsable > 0 {
+        warn!("{} records without serial types skipped", unparsable);
+    }
+
+    let mut tables: Vec<Table> = vec![];
+    let mut inferred: Vec<InferredSchema> = vec![];
+    for (n, mut cluster) in clusters.into_iter().enumerate() {
+        let name: String = format!("carved_{}", n + 1);
+        let column_count: usize = cluster.counts.len();
+        let classes: Vec<StorageClass> =
+            (0..column_count).map(|i| cluster.column_class(i)).collect();
+        let (agreeing, values): (usize, usize) = (0..column_count)
+            .map(|i| cluster.consistency(i))
+            .fold((0, 0), |(a, v), (ca, cv)| (a + ca, v + cv));
+        let rowid_alias: Option<usize> = match column_count > 1
+            && classes[0] == StorageClass::Null
+            && cluster.rows.iter().all(|r| r.rowid().is_some())
+        {
+            true => Some(0),
+            false => None,
+        };
+
+        let mut candidates: Vec<(usize, &TableSchema)> = known
+            .iter()
+            .filter_map(|schema| match_score(&cluster, schema).map(|score| (score, schema)))
+            .collect();
+        candidates.sort_by_key(|(score, schema)| {
+            (
+                std::cmp::Reverse(*score),
+                stored_columns(schema).len(),
+                schema.name.to_string(),
+            )
+        });
+
+        let schema: TableSchema = match candidates.first() {
+            Some((_, matched)) => {
+                let mut schema: TableSchema = (*matched).clone();
+                // Names of tables become file names
+                schema.name = sanitize_file_name(&format!("{}_{}", name, matched.name));
+                schema
+            }
+            None => {
+                let definitions: Vec<String> = classes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, class)| match (rowid_alias == Some(i), class) {
+                        (true, _) => format!("c{} INTEGER PRIMARY KEY", i + 1),
+                        (false, StorageClass::Null) => format!("c{}", i + 1),
+                        (false, class) => format!("c{} {}", i + 1, class.type_name()),
+                    })
+                    .collect();
+                match TableSchema::parse(&format!(
+                    "CREATE TABLE {} ({})",
+                    name,
+                    definitions.join(", ")
+                )) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        warn!("{}: {}", name, e);
+                        continue;
+                    }
+                }
+            }
+        };
+
+        /* The rowid alias is set by Table::from_rows, after aligning the records */
+        let rows: Vec<LeafCell> = std::mem::take(&mut cluster.rows);
+
+        let consistency: f64 = match values {
+            0 => 0.0,
+            v => agreeing as f64 / v as f64,
+        };
+        let compared_columns: usize = candidates.first().map_or(0, |(score, _)| *score);
+        let confidence: f64 = match candidates.len() {
+            0 => {
+                let typed: usize = classes.iter().filter(|c| **c != StorageClass::Null).count();
+                consistency * UNMATCHED_WEIGHT * typed as f64 / column_count as f64
+            }
+            n => consistency * (compared_columns + 1) as f64 / (column_count + 1) as f64 / n as f64,
+        };
+        let inferred_schema: InferredSchema = InferredSchema {
+            name: schema.name.to_string(),
+            records: rows.len(),
+            signature: classes.iter().map(|c| c.letter()).collect(),
+            columns: classes
+                .iter()
+                .enumerate()
+                .map(|(i, class)| InferredColumn {
+                    name: schema
+                        .columns
+                        .iter()
+                        .filter(|c| c.is_stored())
+                        .nth(i)
+                        .map_or(format!("c{}", i + 1), |c| c.name.to_string()),
+                    declared_type: class.type_name().to_string(),
+                    consistency: match cluster.consistency(i) {
+                        (_, 0) => 1.0,
+                        (a, v) => a as f64 / v as f64,
+                    },
+                })
+                .collect(),
+            rowid_alias: schema
+                .rowid_alias
+                .and_then(|alias| schema.columns.get(alias))
+                .map(|c| c.name.to_string()),
+            sql: schema.sql.to_string(),
+            matched_table: candidates.first().map(|(_, s)| s.name.to_string()),
+            other_candidates: candidates
+                .iter()
+                .skip(1)
+                .map(|(_, s)| s.name.to_string())
+                .collect(),
+            compared_columns,
+            confidence,
+        };
+        info!(
+            "{}: {} records, {} ({:.2} confidence){}",
+            inferred_schema.name,
+            inferred_schema.records,
+            inferred_schema.signature,
+            inferred_schema.confidence,
+            match &inferred_schema.matched_table {
+                Some(t) => format!(", matching {}", t),
+                None => String::new(),
+            }
+        );
+
+        let mut table: Table = Table::from_rows(schema, rows);
+        table.carved = true;
+        tables.push(table);
+        inferred.push(inferred_schema);
+    }
+
+    (tables, inferred)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::LEAF_TABLE_BTREE_PAGE;
+    use crate::utils::write_varint;
+
+    /// Values of a record: NULL, small integer or text
+    enum V<'a> {
+        N,
+        I(i8),
+        T(&'a str),
+    }
+
+    /// Cell of a table leaf page carved from free space, with its bytes
+    fn carved_cell(rowid: i64, values: &[V]) -> LeafCell {
+        let mut header: Vec<u8> = vec![];
+        let mut content: Vec<u8> = vec![];
+        for value in values.iter() {
+            match value {
+                V::N => header.push(0),
+                V::I(i) => {
+                    header.push(1);
+                    content.push(*i as u8);
+                }
+                V::T(t) => {
+                    header.extend(write_varint(13 + 2 * t.len() as i64));
+                    content.extend(t.as_bytes());
+                }
+            }
+        }
+        let mut payload: Vec<u8> = write_varint(header.len() as i64 + 1);
+        payload.extend(header);
+        payload.extend(content);
+        let mut bytes: Vec<u8> = write_varint(payload.len() as i64);
+        bytes.extend(write_varint(rowid));
+        bytes.extend(payload);
+
+        let mut cell: LeafCell = LeafCell::new_in_page(&bytes, 0, LEAF_TABLE_BTREE_PAGE, 4096);
+        cell.set_location(None, 0, false, &bytes);
+        cell
+    }
+
+    fn schemas(sql: &[&str]) -> Vec<TableSchema> {
+        sql.iter().map(|s| TableSchema::parse(s).unwrap()).collect()
+    }
+
+    #[test]
+    fn records_matching_a_table() {
+        let known: Vec<TableSchema> = schemas(&[
+            "CREATE TABLE msg (id INTEGER PRIMARY KEY, body TEXT, ts INTEGER)",
+            "CREATE TABLE other (a TEXT, b TEXT)",
+        ]);
+        let cells: Vec<LeafCell> = vec![
+            carved_cell(4, &[V::N, V::T("hi"), V::I(5)]),
+            carved_cell(9, &[V::N, V::T("yo"), V::N]),
+        ];
+
+        let (tables, inferred): (Vec<Table>, Vec<InferredSchema>) = infer_tables(cells, &known);
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].name, "carved_1_msg");
+        assert_eq!(tables[0].rows[0].data(), vec!["4", "hi", "5"]);
+        assert_eq!(inferred[0].signature, "NTI");
+        assert_eq!(inferred[0].matched_table.as_deref(), Some("msg"));
+        assert_eq!(inferred[0].rowid_alias.as_deref(), Some("id"));
+        // body and ts are compared, id is always NULL
+        assert_eq!(inferred[0].compared_columns, 2);
+        assert_eq!(inferred[0].confidence, 0.75);
+    }
+
+    #[test]
+    fn records_without_a_table() {
+        let known: Vec<TableSchema> =
+            schemas(&["CREATE TABLE msg (id INTEGER PRIMARY KEY, body TEXT, ts INTEGER)"]);
+        let cells: Vec<LeafCell> = vec![
+            carved_cell(1, &[V::T("a"), V::I(1)]),
+            carved_cell(2, &[V::T("b"), V::I(2)]),
+            carved_cell(3, &[V::N, V::N, V::N, V::N]),
+        ];
+
+        let (tables, inferred): (Vec<Table>, Vec<InferredSchema>) = infer_tables(cells, &known);
+        assert_eq!(tables.len(), 2);
+        assert_eq!(inferred[0].name, "carved_1");
+        assert_eq!(inferred[0].matched_table, None);
+        assert_eq!(
+            inferred[0].sql,
+            "CREATE TABLE carved_1 (c1 TEXT, c2 INTEGER)"
+        );
+        assert_eq!(inferred[0].confidence, 0.5);
+        // NULL values only: the first column is taken for an alias of the rowid
+        assert_eq!(inferred[1].rowid_alias.as_deref(), Some("c1"));
+        assert_eq!(inferred[1].confidence, 0.0);
+    }
+
+    #[test]
+    fn records_written_before_added_columns() {
+        let known: Vec<TableSchema> = schemas(&[
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, added TEXT DEFAULT 'none', n INTEGER NOT NULL DEFAULT 0)",
+            "CREATE TABLE u (id INTEGER PRIMARY KEY, name TEXT, required TEXT NOT NULL)",
+        ]);
+        let (tables, inferred): (Vec<Table>, Vec<InferredSchema>) =
+            infer_tables(vec![carved_cell(3, &[V::N, V::T("a")])], &known);
+        assert_eq!(inferred[0].matched_table.as_deref(), Some("t"));
+        assert!(inferred[0].other_candidates.is_empty());
+        assert_eq!(tables[0].columns, vec!["id", "name", "added", "n"]);
+        assert_eq!(tables[0].rows[0].data(), vec!["3", "a", "none", "0"]);
+        assert_eq!(
+            tables[0].rows[0].defaulted_columns(),
+            Some(&[String::from("added"), String::from("n")][..])
+        );
+
+        // A table with exactly the columns of the records is preferred
+        let known: Vec<TableSchema> = schemas(&[
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, added TEXT DEFAULT 'none')",
+            "CREATE TABLE v (id INTEGER PRIMARY KEY, label TEXT)",
+        ]);
+        let (_, inferred): (Vec<Table>, Vec<InferredSchema>) =
+            infer_tables(vec![carved_cell(3, &[V::N, V::T("a")])], &known);
+        assert_eq!(inferred[0].matched_table.as_deref(), Some("v"));
+        assert_eq!(inferred[0].other_candidates, vec!["t"]);
+        assert_eq!(inferred[0].confidence, 1.0 / 3.0);
+
+        // Records cannot have more values than the columns
+        let (_, inferred): (Vec<Table>, Vec<InferredSchema>) = infer_tables(
+            vec![carved_cell(3, &[V::N, V::T("a"), V::T("b")])],
+            &known[1..],
+        );
+        assert_eq!(inferred[0].matched_table, None);
+    }
+
+    #[test]
+    fn rowid_alias_after_generated_columns() {
+        // g is not stored: the records hold id and body
+        let known: Vec<TableSchema> = schemas(&[
+            "CREATE TABLE msg (g AS (body || '!') VIRTUAL, id INTEGER PRIMARY KEY, body TEXT)",
+        ]);
+        let (tables, inferred): (Vec<Table>, Vec<InferredSchema>) =
+            infer_tables(vec![carved_cell(7, &[V::N, V::T("hi")])], &known);
+        assert_eq!(inferred[0].matched_table.as_deref(), Some("msg"));
+        assert_eq!(inferred[0].rowid_alias.as_deref(), Some("id"));
+        assert_eq!(tables[0].rows[0].data(), vec!["NULL", "7", "hi"]);
+    }
+
+    #[test]
+    fn names_of_matched_tables_are_file_names() {
+        let known: Vec<TableSchema> = schemas(&["CREATE TABLE \"a/b\" (x TEXT)"]);
+        let (tables, _): (Vec<Table>, Vec<InferredSchema>) =
+            infer_tables(vec![carved_cell(1, &[V::T("q")])], &known);
+        assert_eq!(tables[0].name, "carved_1_a_b");
+    }
+}
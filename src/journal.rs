@@ -0,0 +1,72 @@
+/// Magic of rollback journals
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+
+/// Length of the fields of the header (the header itself fills a whole sector)
+const JOURNAL_HEADER_LEN: usize = 28;
+
+/// Representation of the header of a rollback journal
+pub struct JournalHeader {
+    /// Number of pages in the journal (-1: computed from the file size)
+    page_count: i32,
+    nonce: u32,
+    /// Size of the database in pages before the transaction
+    initial_page_count: u32,
+    sector_size: u32,
+    page_size: u32,
+}
+
+impl JournalHeader {
+    /// Parses the first bytes of the journal.
+    /// Committed transactions may leave the header zeroed (journal_mode=PERSIST)
+    pub fn new(bytearray: &[u8]) -> Result<JournalHeader, &'static str> {
+        if bytearray.len() < JOURNAL_HEADER_LEN {
+            return Err("Journal file too short");
+        }
+        if bytearray[..8] != JOURNAL_MAGIC {
+            return match bytearray[..JOURNAL_HEADER_LEN].iter().all(|b| *b == 0) {
+                true => Err("Journal header is zeroed (transaction committed)"),
+                false => Err("NOT A ROLLBACK JOURNAL"),
+            };
+        }
+
+        Ok(JournalHeader {
+            page_count: i32::from_be_bytes([
+                bytearray[8],
+                bytearray[9],
+                bytearray[10],
+                bytearray[11],
+            ]),
+            nonce: u32::from_be_bytes([bytearray[12], bytearray[13], bytearray[14], bytearray[15]]),
+            initial_page_count: u32::from_be_bytes([
+                bytearray[16],
+                bytearray[17],
+                bytearray[18],
+                bytearray[19],
+            ]),
+            sector_size: u32::from_be_bytes([
+                bytearray[20],
+                bytearray[21],
+                bytearray[22],
+                bytearray[23],
+            ]),
+            page_size: u32::from_be_bytes([
+                bytearray[24],
+                bytearray[25],
+                bytearray[26],
+                bytearray[27],
+            ]),
+        })
+    }
+}
+
+impl std::fmt::Debug for JournalHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "JOURNAL HEADER")?;
+        writeln!(f, "\tPAGE COUNT:\t\t\t{}", self.page_count)?;
+        writeln!(f, "\tNONCE:\t\t\t\t0x{:08X}", self.nonce)?;
+        writeln!(f, "\tINITIAL PAGE COUNT:\t{}", self.initial_page_count)?;
+        writeln!(f, "\tSECTOR SIZE:\t\t{}", self.sector_size)?;
+        writeln!(f, "\tPAGE SIZE:\t\t\t{}", self.page_size)?;
+        writeln!(f)
+    }
+}
@@ -3,23 +3,50 @@ use log::{error, info, warn};
 use simple_logger::SimpleLogger;
 use std::fs::{read, File};
 use std::io::Write;
-use std::path::{Path, MAIN_SEPARATOR};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::process::ExitCode;
 use time::macros::format_description;
 
 use clap::Parser;
 
 mod args;
+mod batch;
+mod blobs;
+mod carver;
 mod constants;
 mod db;
+mod dropped;
+mod evidence;
 mod formatters;
+mod fts5;
+mod inference;
+mod journal;
 mod mainfile;
+mod predicate;
+mod scan;
+mod schema;
 mod structs;
+#[cfg(test)]
+mod testing;
+mod timestamps;
 mod utils;
+mod vtab;
 mod wal;
 
-use args::Args;
+use args::{Args, BatchArgs, Command, DumpArgs, Format};
+use batch::BatchIndex;
+use carver::CarvedPage;
+use db::{RowStatus, Table};
+use dropped::SchemaEntry;
+use evidence::{Run, RunManifest};
+use formatters::RunOptions;
+use inference::InferredSchema;
+use journal::JournalHeader;
 use mainfile::{FileHeader, MainFile};
-use wal::WALFile;
+use scan::{EmbeddedDatabase, ExtractedDatabase, ScanManifest};
+use schema::TableSchema;
+use structs::{LeafCell, PageHeader};
+use wal::{FrameSummary, WALFile, WALFileHeader};
 
 fn generate() -> String {
     format!(
@@ -35,7 +62,7 @@ fn generate() -> String {
     )
 }
 
-fn main() -> () {
+fn main() -> ExitCode {
     let args: Args = Args::parse();
 
     println!("{}", generate());
@@ -54,61 +81,522 @@ fn main() -> () {
         .init()
         .unwrap();
 
-    let db_filepath: &String = &args.filepath;
+    let result: Result<(), &'static str> = match args.command {
+        Command::Header { filepath } => print_headers(&filepath),
+        Command::Schema {
+            filepath,
+            dropped_tables,
+        } => print_schema(&filepath, dropped_tables),
+        Command::Dump(dump_args) => dump(dump_args, None),
+        Command::Wal { filepath } => list_wal_frames(&filepath),
+        Command::Carve(mut dump_args) => {
+            // Deleted rows are also found in the WAL and in the pages of dropped tables
+            if Path::new(&format!("{}-wal", dump_args.filepath)).exists() {
+                dump_args.wal = true;
+            }
+            dump_args.dropped_tables = true;
+            dump(
+                dump_args,
+                Some(vec![
+                    RowStatus::Deleted,
+                    RowStatus::Dropped,
+                    RowStatus::Carved,
+                ]),
+            )
+        }
+        Command::Pages { filepath, page } => print_pages(&filepath, page),
+        Command::Diff(mut dump_args) => {
+            match Path::new(&format!("{}-wal", dump_args.filepath)).exists() {
+                true => {
+                    dump_args.wal = true;
+                    dump(
+                        dump_args,
+                        Some(vec![
+                            RowStatus::Inserted,
+                            RowStatus::Deleted,
+                            RowStatus::Modified,
+                        ]),
+                    )
+                }
+                false => Err("WAL file not found"),
+            }
+        }
+        Command::CarvePages {
+            filepath,
+            schema,
+            format,
+            output_dir,
+        } => carve_pages(&filepath, schema, format, &output_dir),
+        Command::Scan {
+            filepath,
+            output_dir,
+        } => scan_file(&filepath, &output_dir),
+        Command::Batch(batch_args) => run_batch(batch_args),
+    };
+
+    // Errors of the commands writing files are reported with the exit status (e.g.: to batch)
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Reads the `CREATE TABLE` statements of sqlite_master
+fn known_schemas(db_filepath: &str) -> Vec<TableSchema> {
+    let bytearray: Vec<u8> = match read_main_file(db_filepath) {
+        Some(b) => b,
+        None => return vec![],
+    };
+    match MainFile::new(&bytearray) {
+        Ok(main_file) => main_file
+            .get_tables_info()
+            .values()
+            .filter_map(|info| TableSchema::parse(&info.sql).ok())
+            .collect(),
+        Err(e) => {
+            error!("{}: {}", db_filepath, e);
+            vec![]
+        }
+    }
+}
+
+/// Writes the records of the table leaf pages carved from a file without database header,
+/// grouped by inferred schema, and the inferred schemas
+fn carve_pages(
+    filepath: &str,
+    schema: Option<String>,
+    format: Format,
+    output_dir: &str,
+) -> Result<(), &'static str> {
+    let known: Vec<TableSchema> = match &schema {
+        Some(db_filepath) => known_schemas(db_filepath),
+        None => vec![],
+    };
+    let run: Run = Run::start(filepath);
+    let bytearray: Vec<u8> = read_main_file(filepath).ok_or("Cannot read the file")?;
+    let pages: Vec<CarvedPage> = carver::carve_pages(&bytearray);
+    info!(
+        "Carved {} table leaf pages ({} records) from {}",
+        pages.len(),
+        pages.iter().map(|p| p.cells.len()).sum::<usize>(),
+        filepath
+    );
+    if pages.is_empty() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(output_dir).map_err(|_| "Cannot create output directory")?;
+    let cells: Vec<LeafCell> = pages.into_iter().flat_map(|p| p.cells).collect();
+    let (tables, inferred): (Vec<Table>, Vec<InferredSchema>) =
+        inference::infer_tables(cells, &known);
+
+    let filename: &str = Path::new(filepath).file_stem().unwrap().to_str().unwrap();
+    let mut outputs: Vec<PathBuf> = vec![];
+    let schemas_filename: String = format!("{}_carved_schemas.json", filename);
+    let schemas_path: PathBuf = Path::new(output_dir).join(&schemas_filename);
+    let outfile: File =
+        File::create(&schemas_path).map_err(|_| "Cannot create inferred schemas")?;
+    serde_json::to_writer_pretty(outfile, &inferred)
+        .map_err(|_| "Cannot write inferred schemas")?;
+    info!(
+        "Created inferred schemas: {}{}{}",
+        output_dir, MAIN_SEPARATOR, schemas_filename
+    );
+    outputs.push(schemas_path);
+    match format {
+        Format::Json => {
+            let json_filename: String = format!("{}_carved.json", filename);
+            outputs.extend(formatters::carved_json_run(
+                tables,
+                &Path::new(output_dir).join(&json_filename),
+                filepath,
+            )?);
+            info!(
+                "Created JSON file to: {}{}{}",
+                output_dir, MAIN_SEPARATOR, json_filename
+            );
+        }
+        Format::Csv => {
+            outputs.extend(formatters::carved_csv_run(
+                tables,
+                Path::new(output_dir),
+                filepath,
+                formatters::CsvOptions {
+                    delimiter: b';',
+                    quote_all: false,
+                    bom: false,
+                },
+            )?);
+            info!("Created CSV files in: {}", output_dir);
+        }
+        _ => return Err("Carved pages can be written only as JSON or CSV"),
+    }
+
+    write_run_manifest(run, &outputs, Path::new(output_dir), filename)?;
+    Ok(())
+}
+
+/// Lists the databases embedded in a file and writes each of them to the output directory
+fn scan_file(filepath: &str, output_dir: &str) -> Result<(), &'static str> {
+    let run: Run = Run::start(filepath);
+    // The file is read in chunks: it can be bigger than the memory (e.g.: a disk image)
+    let mut file: File = match File::open(filepath) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("{}: {}", filepath, e);
+            return Err("Cannot open the file");
+        }
+    };
+    let file_len: u64 = file.metadata().map(|m| m.len()).unwrap_or_default();
+    let databases: Vec<EmbeddedDatabase> = scan::find_databases(&mut file, file_len)?;
+    if databases.is_empty() {
+        warn!("No SQLite database found in {}", filepath);
+        return Ok(());
+    }
+    std::fs::create_dir_all(output_dir).map_err(|_| "Cannot create output directory")?;
 
+    let stem: String = match Path::new(filepath).file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => String::from("scan"),
+    };
+    println!(
+        "{:>12} {:>9} {:>8} {:>12}  FILE",
+        "OFFSET", "PAGE SIZE", "PAGES", "LENGTH"
+    );
+    let mut outputs: Vec<PathBuf> = vec![];
+    let mut extracted: Vec<ExtractedDatabase> = vec![];
+    let found: usize = databases.len();
+    for database in databases.into_iter() {
+        let db_filepath: String = format!(
+            "{}{}{}_0x{:X}.db",
+            output_dir, MAIN_SEPARATOR, stem, database.offset
+        );
+        println!(
+            "{:>12} {:>9} {:>8} {:>12}  {}{}",
+            format!("0x{:X}", database.offset),
+            database.page_size,
+            match database.page_count {
+                0 => String::from("?"),
+                n => n.to_string(),
+            },
+            database.length,
+            db_filepath,
+            if database.truncated {
+                " (truncated)"
+            } else {
+                ""
+            }
+        );
+        match scan::extract_database(&mut file, &database, Path::new(&db_filepath)) {
+            Ok(hashes) => {
+                outputs.push(PathBuf::from(&db_filepath));
+                extracted.push(ExtractedDatabase {
+                    database,
+                    file: hashes,
+                });
+            }
+            Err(e) => error!("{}: {}", db_filepath, e),
+        }
+    }
+    println!("\n{} databases found", found);
+    let failed: usize = found - extracted.len();
+
+    // Offsets and hashes of the extracted databases
+    let manifest: ScanManifest = ScanManifest {
+        source: filepath.to_string(),
+        source_size: file_len,
+        databases: extracted,
+    };
+    let manifest_filename: String = format!("{}_scan.json", stem);
+    let manifest_path: PathBuf = Path::new(output_dir).join(&manifest_filename);
+    let outfile: File = File::create(&manifest_path).map_err(|_| "Cannot create scan manifest")?;
+    serde_json::to_writer_pretty(outfile, &manifest).map_err(|_| "Cannot write scan manifest")?;
+    info!(
+        "Created scan manifest: {}{}{}",
+        output_dir, MAIN_SEPARATOR, manifest_filename
+    );
+    outputs.push(manifest_path);
+
+    write_run_manifest(run, &outputs, Path::new(output_dir), &stem)?;
+    match failed {
+        0 => Ok(()),
+        _ => Err("Some databases could not be extracted"),
+    }
+}
+
+/// Dumps every SQLite file under a directory, printing a summary
+fn run_batch(args: BatchArgs) -> Result<(), &'static str> {
+    let root: &Path = Path::new(&args.directory);
+    if !root.is_dir() {
+        error!("{} is not a directory", args.directory);
+        return Err("Cannot read the directory");
+    }
+    let jobs: usize = match args.jobs {
+        Some(j) => j,
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+
+    let index: BatchIndex =
+        batch::run(root, Path::new(&args.output_dir), jobs, &args.dump_options)?;
+    info!(
+        "{} databases processed, {} failed",
+        index.databases, index.failed
+    );
+    info!(
+        "Index: {}{}{}",
+        args.output_dir,
+        MAIN_SEPARATOR,
+        batch::INDEX_FILE_NAME
+    );
+    match index.failed {
+        0 => Ok(()),
+        _ => Err("Some databases could not be processed"),
+    }
+}
+
+/// Reads the main file, if it exists and is not empty
+fn read_main_file(db_filepath: &str) -> Option<Vec<u8>> {
     if !Path::new(db_filepath).exists() {
         error!("{} not found", db_filepath);
-        return;
+        return None;
     }
 
-    let filename: &str = Path::new(db_filepath)
-        .file_stem()
-        .unwrap()
-        .to_str()
-        .unwrap();
+    let bytearray: Vec<u8> = match read(db_filepath) {
+        Ok(b) => b,
+        Err(e) => {
+            error!("{}: {}", db_filepath, e);
+            return None;
+        }
+    };
+    if bytearray.is_empty() {
+        error!("Given file ({}) is empty", db_filepath);
+        return None;
+    }
 
-    let bytearray: Vec<u8> = read(db_filepath).unwrap();
+    Some(bytearray)
+}
 
-    if bytearray.len() == 0 {
-        error!("Given file ({}) is empty", db_filepath);
+/// Prints the headers of the main file, of the WAL and of the rollback journal
+fn print_headers(db_filepath: &str) -> Result<(), &'static str> {
+    let bytearray: Vec<u8> = read_main_file(db_filepath).ok_or("Cannot read the main file")?;
+    println!("{:?}", FileHeader::new(&bytearray)?);
+
+    let wal_filepath: String = format!("{}-wal", db_filepath);
+    if Path::new(&wal_filepath).exists() {
+        let wal_bytearray: Vec<u8> = read_companion_file(&wal_filepath)?;
+        match WALFileHeader::parse(&wal_bytearray) {
+            Ok(wal_header) => println!("WAL HEADER\n{:?}", wal_header),
+            Err(e) => {
+                error!("{}: {}", wal_filepath, e);
+                return Err(e);
+            }
+        }
+    }
+
+    let journal_filepath: String = format!("{}-journal", db_filepath);
+    if Path::new(&journal_filepath).exists() {
+        // The header of a committed journal is zeroed
+        match JournalHeader::new(&read_companion_file(&journal_filepath)?) {
+            Ok(journal_header) => println!("{:?}", journal_header),
+            Err(e) => warn!("{}: {}", journal_filepath, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a WAL or a rollback journal, logging why it cannot be read
+fn read_companion_file(filepath: &str) -> Result<Vec<u8>, &'static str> {
+    read(filepath).map_err(|e| {
+        error!("{}: {}", filepath, e);
+        "Cannot read the WAL or journal file"
+    })
+}
+
+/// Prints the objects of sqlite_master (and its deleted entries, if requested)
+fn print_schema(db_filepath: &str, dropped_tables: bool) -> Result<(), &'static str> {
+    let bytearray: Vec<u8> = read_main_file(db_filepath).ok_or("Cannot read the main file")?;
+    let main_file: MainFile = MainFile::new(&bytearray)?;
+
+    for row in main_file.get_schema_rows().iter() {
+        match row.as_slice() {
+            [kind, name, table_name, root_page, sql, ..] => {
+                println!(
+                    "{} {} (table: {}, root page: {})",
+                    kind, name, table_name, root_page
+                );
+                if sql != "NULL" {
+                    println!("{}\n", sql);
+                }
+            }
+            _ => warn!("Malformed sqlite_master row: {:?}", row),
+        }
+    }
+
+    if !dropped_tables {
+        return Ok(());
+    }
+    let deleted: Vec<SchemaEntry> = main_file.deleted_schema_entries(&bytearray);
+    if !deleted.is_empty() {
+        println!("DELETED ENTRIES");
+    }
+    for entry in deleted.iter() {
+        println!(
+            "{} {} (table: {}, root page: {}; {} at offset {} of page {})",
+            entry.kind,
+            entry.name,
+            entry.table_name,
+            entry.root_page,
+            if entry.dropped {
+                "dropped"
+            } else {
+                "older definition"
+            },
+            entry.offset,
+            entry.page
+        );
+        if entry.sql != "NULL" {
+            println!("{}\n", entry.sql);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the frames of the WAL and the transactions they belong to
+fn list_wal_frames(db_filepath: &str) -> Result<(), &'static str> {
+    let wal_filepath: String = format!("{}-wal", db_filepath);
+    if !Path::new(&wal_filepath).exists() {
+        return Err("WAL file not found");
+    }
+    let frames: Vec<FrameSummary> = wal::list_frames(&read_companion_file(&wal_filepath)?)?;
 
-        return;
+    println!(
+        "{:>6} {:>10} {:>6} {:>7} {:>5} {:>8} {:>11}",
+        "FRAME", "OFFSET", "PAGE", "COMMIT", "SALT", "CHECKSUM", "TRANSACTION"
+    );
+    for frame in frames.iter() {
+        println!(
+            "{:>6} {:>10} {:>6} {:>7} {:>5} {:>8} {:>11}",
+            frame.number,
+            frame.offset,
+            frame.page_num,
+            match frame.commit_size {
+                Some(n) => n.to_string(),
+                None => String::from("-"),
+            },
+            if frame.salt_match { "ok" } else { "old" },
+            if frame.checksum_match { "ok" } else { "wrong" },
+            match frame.transaction {
+                Some(t) => t.to_string(),
+                None => String::from("-"),
+            }
+        );
     }
 
-    // Only print header of main file
-    if args.fileheader {
-        match FileHeader::new(&bytearray) {
-            Ok(file_header) => println!("{:?}", file_header),
-            Err(e) => error!("{}", e),
-        };
+    let transactions: u32 = frames
+        .iter()
+        .filter_map(|f| f.transaction)
+        .max()
+        .unwrap_or(0);
+    let uncommitted: usize = frames.iter().filter(|f| f.transaction.is_none()).count();
+    println!(
+        "\n{} frames, {} transactions, {} frames not committed or not valid",
+        frames.len(),
+        transactions,
+        uncommitted
+    );
+
+    Ok(())
+}
 
-        return;
+/// Prints a summary of each page of the main file, or the content of a single page
+fn print_pages(db_filepath: &str, page_num: Option<u32>) -> Result<(), &'static str> {
+    let bytearray: Vec<u8> = read_main_file(db_filepath).ok_or("Cannot read the main file")?;
+    let main_file: MainFile = MainFile::new(&bytearray)?;
+
+    match page_num {
+        Some(n) => match main_file.get_page_by_number(n) {
+            Some(page) => println!("{:?}", page),
+            None if main_file.free_page_numbers().contains(&n) => {
+                println!("Page {} is in the freelist", n)
+            }
+            None if main_file.overflow_page_numbers().contains(&n) => {
+                println!("Page {} is an overflow page", n)
+            }
+            None => {
+                error!("Page {} not found", n);
+                return Err("Page not found");
+            }
+        },
+        None => {
+            println!(
+                "{:>6} {:>10} {:<15} {:>6} {:>8} {:>10} {:>10} {:>10}",
+                "PAGE", "OFFSET", "TYPE", "CELLS", "DELETED", "FREEBLOCK", "CONTENT", "FRAGMENTED"
+            );
+            for page in main_file.pages().iter() {
+                let header: PageHeader = page.header();
+                println!(
+                    "{:>6} {:>10} {:<15} {:>6} {:>8} {:>10} {:>10} {:>10}",
+                    page.number(),
+                    page.offset(),
+                    header.page_type_name(),
+                    header.cell_count(),
+                    page.deleted_cells_count(),
+                    header.first_freeblock_offset(),
+                    header.cell_content_offset(),
+                    header.fragmented_free_bytes()
+                );
+            }
+            println!("\nFreelist pages: {:?}", main_file.free_page_numbers());
+            println!("Overflow pages: {:?}", main_file.overflow_page_numbers());
+        }
     }
 
+    Ok(())
+}
+
+/// Extracts the selected tables, columns and rows (only the ones with the given statuses, if any),
+/// in the requested format
+fn dump(args: DumpArgs, statuses: Option<Vec<RowStatus>>) -> Result<(), &'static str> {
+    let options: RunOptions = RunOptions::new(&args, statuses)?;
+
+    let db_filepath: &String = &args.filepath;
+
+    let filename: &str = Path::new(db_filepath)
+        .file_stem()
+        .unwrap()
+        .to_str()
+        .unwrap();
+
+    // Hashes of the input files, before reading them
+    let run: Run = Run::start(db_filepath);
+    let mut outputs: Vec<PathBuf> = vec![];
+
+    let bytearray: Vec<u8> = read_main_file(db_filepath).ok_or("Cannot read the main file")?;
+
     // Creating output dir
+    let output_dir: PathBuf = Path::new(".").join(&args.output_dir);
     if !Path::new(&args.output_dir.clone().to_string()).exists() {
         info!("Create output dir: {}", args.output_dir.to_string());
-        let _ = std::fs::create_dir_all(Path::new(".").join(args.output_dir.to_string()));
+        let _ = std::fs::create_dir_all(&output_dir);
     }
 
     // Main file
     info!("Main DB file: {}", db_filepath);
-    let parsed_main_file: MainFile = match MainFile::new(&bytearray) {
-        Ok(mainfile) => mainfile,
-        Err(e) => {
-            error!("{}", e);
-            return;
-        }
-    };
+    let mut parsed_main_file: MainFile = MainFile::new(&bytearray)?;
+    if args.dropped_tables {
+        parsed_main_file.recover_dropped_tables(&bytearray);
+    }
 
     if args.parsed_files {
-        let mut out_db_file: File = File::create(
-            Path::new(".")
-                .join(args.output_dir.to_string())
-                .join(format!("{}.txt", filename)),
-        )
-        .unwrap();
-        let _ = write!(out_db_file, "{:?}", parsed_main_file);
+        let txt_path: PathBuf = output_dir.join(format!("{}.txt", filename));
+        let mut out_db_file: File =
+            File::create(&txt_path).map_err(|_| "Cannot create TXT file")?;
+        write!(out_db_file, "{:?}", parsed_main_file).map_err(|_| "Error writing TXT file")?;
+        outputs.push(txt_path);
     }
 
     info!("{:=<40}", "");
@@ -121,7 +609,7 @@ fn main() -> () {
             warn!("{} not found", wal_filepath);
         } else {
             info!("WAL file: {}", wal_filepath);
-            let wal_bytearray: Vec<u8> = read(&wal_filepath).unwrap();
+            let wal_bytearray: Vec<u8> = read_companion_file(&wal_filepath)?;
             if wal_bytearray.len() == 0 {
                 warn!("WAL file is empty");
             } else {
@@ -129,53 +617,123 @@ fn main() -> () {
 
                 // Print the txt of extracted data from WAL
                 if args.parsed_files {
-                    let mut out_wal_file: File = File::create(
-                        Path::new(".")
-                            .join(args.output_dir.to_string())
-                            .join(format!("{}-wal.txt", filename)),
-                    )
-                    .unwrap();
-                    let _ = write!(out_wal_file, "{:?}", parsed_wal_file);
+                    let txt_path: PathBuf = output_dir.join(format!("{}-wal.txt", filename));
+                    let mut out_wal_file: File =
+                        File::create(&txt_path).map_err(|_| "Cannot create TXT file")?;
+                    write!(out_wal_file, "{:?}", parsed_wal_file)
+                        .map_err(|_| "Error writing TXT file")?;
+                    outputs.push(txt_path);
                 }
             }
         }
         info!("{:=<45}", "");
     }
 
-    let out_format = args.format;
-    match out_format.as_str() {
-        "JSON" => {
+    match args.format {
+        Format::Json => {
             let json_filename: String = format!("{}.json", filename);
-            let outfile: File = File::create(
-                Path::new(".")
-                    .join(args.output_dir.to_string())
-                    .join(&json_filename),
-            )
-            .unwrap();
-            formatters::json_run(
+            outputs.extend(formatters::json_run(
                 parsed_main_file,
                 parsed_wal_file,
-                outfile,
-                args.missingids,
-                args.triggers,
-                args.indices,
-            );
+                &output_dir.join(&json_filename),
+                &options,
+            )?);
             info!(
                 "Created JSON file to: {}{}{}",
                 args.output_dir, MAIN_SEPARATOR, json_filename
             );
         }
-        "CSV" => {
-            formatters::csv_run(
+        Format::Ndjson => {
+            let ndjson_filename: String = format!("{}.ndjson", filename);
+            outputs.extend(formatters::ndjson_run(
+                parsed_main_file,
+                parsed_wal_file,
+                &output_dir.join(&ndjson_filename),
+                &options,
+            )?);
+            info!(
+                "Created NDJSON file to: {}{}{}",
+                args.output_dir, MAIN_SEPARATOR, ndjson_filename
+            );
+        }
+        Format::Html => {
+            let html_filename: String = format!("{}.html", filename);
+            outputs.extend(formatters::html_run(
+                parsed_main_file,
+                parsed_wal_file,
+                &output_dir.join(&html_filename),
+                &options,
+            )?);
+            info!(
+                "Created HTML report to: {}{}{}",
+                args.output_dir, MAIN_SEPARATOR, html_filename
+            );
+        }
+        Format::Timeline => {
+            outputs.extend(formatters::timeline_run(
+                parsed_main_file,
+                parsed_wal_file,
+                &output_dir,
+                &options,
+            )?);
+            info!("Created timeline files to: {}", args.output_dir);
+        }
+        Format::Csv => {
+            outputs.extend(formatters::csv_run(
+                parsed_main_file,
+                parsed_wal_file,
+                &output_dir,
+                &options,
+            )?);
+            info!("Created CSV files to: {}", args.output_dir);
+        }
+        Format::Sqlite => {
+            let sqlite_filename: String = format!("{}_recovered.db", filename);
+            outputs.extend(formatters::sqlite_run(
                 parsed_main_file,
                 parsed_wal_file,
-                args.missingids,
-                args.triggers,
-                args.indices,
+                &output_dir.join(&sqlite_filename),
+                &options,
+            )?);
+            info!(
+                "Created SQLite file to: {}{}{}",
+                args.output_dir, MAIN_SEPARATOR, sqlite_filename
+            );
+        }
+        Format::Sql => {
+            let sql_filename: String = format!("{}.sql", filename);
+            outputs.extend(formatters::sql_run(
+                parsed_main_file,
+                parsed_wal_file,
+                &output_dir.join(&sql_filename),
+                &options,
+            )?);
+            info!(
+                "Created SQL file to: {}{}{}",
+                args.output_dir, MAIN_SEPARATOR, sql_filename
             );
         }
-        undefined_formatter => warn!("{undefined_formatter} formatter not found."),
     };
 
+    write_run_manifest(run, &outputs, &output_dir, filename)?;
+
     info!("Done. I had a nice trip. See you soon!");
+    Ok(())
+}
+
+/// Writes the run manifest, with the hashes of input files (again) and of output files
+fn write_run_manifest(
+    run: Run,
+    outputs: &[PathBuf],
+    output_dir: &Path,
+    filename: &str,
+) -> Result<(), &'static str> {
+    let manifest_path: PathBuf = output_dir.join(format!("{}_run_manifest.json", filename));
+    let manifest: RunManifest = run.finish(outputs, &manifest_path)?;
+    info!(
+        "Created run manifest ({} output files) to: {}",
+        manifest.outputs.len(),
+        manifest_path.display()
+    );
+    Ok(())
 }
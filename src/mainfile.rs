@@ -3,10 +3,14 @@ use std::collections::HashMap;
 use std::str::from_utf8;
 
 use crate::constants::*;
+use crate::dropped::{
+    recover_free_leaf_cells, recover_rows, recover_schema_entries, DroppedTable, SchemaEntry,
+};
 
-use crate::structs::{Cell, Page, FREEPAGES, OVERFLOW_PAGES, PAGE_SIZE, RESERVED_SPACE};
+use crate::structs::{Cell, LeafCell, Page, FREEPAGES, OVERFLOW_PAGES, PAGE_SIZE, RESERVED_SPACE};
 
 use crate::utils::STRING_ENCODING;
+use crate::wal::WALFile;
 
 #[derive(Clone)]
 pub struct FreeListTrunkPageHeader {
@@ -96,7 +100,7 @@ impl FreeListTrunkPage {
         let cell_array_offset: usize = page_offset + header.count as usize * 4 + 8; // +8 -> | 0x00 * 4 + 4 bytes of 'count' |
 
         let cell_array: Vec<usize> = Self::get_cell_array(bytearray, cell_array_offset);
-        let mut deleted_cells: Vec<Cell> = vec![];
+        let deleted_cells: Vec<Cell> = vec![];
 
         /*for &cell_offset in cell_array.iter() {
             //debug!("cell_offset: 0x{:02x?}", cell_offset);
@@ -432,6 +436,10 @@ pub struct MainFile {
     header: FileHeader,
     pages: Vec<Page>,
     freepages: Vec<FreeListTrunkPage>,
+    /// Tables whose entry has been deleted from sqlite_master, with the rows left in the freelist
+    dropped_tables: Vec<DroppedTable>,
+    /// Cells of the freelist leaf pages not belonging to a dropped table
+    free_leaf_cells: Vec<LeafCell>,
 }
 
 impl MainFile {
@@ -474,7 +482,8 @@ impl MainFile {
             }
 
             let mut n = first_freepage.header.next_page_num;
-            while n != 0 {
+            freepages.push(first_freepage.clone());
+            while n != 0 && !freepages.iter().any(|p| p.number == n - 1) {
                 debug!("NEXT FREE TRUNK PAGE: {}", n);
                 first_freepage = FreeListTrunkPage::new(
                     &bytearray,
@@ -484,10 +493,13 @@ impl MainFile {
                     header.reserved_space as usize,
                 );
                 unsafe {
-                    FREEPAGES.append(first_freepage.header.next_freepages.as_mut());
+                    let freepages_list: &mut Vec<u32> = &mut *std::ptr::addr_of_mut!(FREEPAGES);
+                    freepages_list.push(n);
+                    freepages_list.append(first_freepage.header.next_freepages.as_mut());
                 }
 
                 n = first_freepage.header.next_page_num;
+                freepages.push(first_freepage.clone());
 
                 debug!("N: {}", n);
             }
@@ -560,9 +572,86 @@ impl MainFile {
             header,
             pages,
             freepages,
+            dropped_tables: vec![],
+            free_leaf_cells: vec![],
         })
     }
 
+    pub fn header(&self) -> FileHeader {
+        self.header.clone()
+    }
+
+    /// Parsed pages (free and overflow pages excluded)
+    pub fn pages(&self) -> &[Page] {
+        &self.pages
+    }
+
+    /// Numbers of the pages of the freelist (trunk and leaf pages)
+    pub fn free_page_numbers(&self) -> Vec<u32> {
+        unsafe { (*std::ptr::addr_of!(FREEPAGES)).clone() }
+    }
+
+    /// Numbers of the freelist trunk pages
+    pub fn free_trunk_page_numbers(&self) -> Vec<u32> {
+        self.freepages.iter().map(|p| p.number + 1).collect()
+    }
+
+    /// Numbers of the overflow pages found while parsing cells
+    pub fn overflow_page_numbers(&self) -> Vec<u32> {
+        unsafe { (*std::ptr::addr_of!(OVERFLOW_PAGES)).clone() }
+    }
+
+    /// Rows of sqlite_master deleted from page 1 or left in freelist pages
+    pub fn deleted_schema_entries(&self, bytearray: &[u8]) -> Vec<SchemaEntry> {
+        recover_schema_entries(
+            bytearray,
+            self.header.page_size as usize,
+            &self.free_page_numbers(),
+            &self.get_schema_rows(),
+        )
+    }
+
+    /// Reads the rows of the tables found among the deleted rows of sqlite_master
+    /// whose root page is in the freelist, and the cells of the other freelist leaf pages
+    pub fn recover_dropped_tables(&mut self, bytearray: &[u8]) {
+        info!("Looking for dropped tables...");
+        let free_pages: Vec<u32> = self.free_page_numbers();
+        let trunk_pages: Vec<u32> = self.free_trunk_page_numbers();
+        let page_size: usize = self.header.page_size as usize;
+        self.dropped_tables = self
+            .deleted_schema_entries(bytearray)
+            .into_iter()
+            .filter(|entry| entry.kind == "table" && free_pages.contains(&entry.root_page))
+            .map(|entry| {
+                let (rows, pages): (Vec<LeafCell>, Vec<u32>) = recover_rows(
+                    bytearray,
+                    page_size,
+                    &free_pages,
+                    &trunk_pages,
+                    entry.root_page,
+                );
+                DroppedTable { entry, rows, pages }
+            })
+            .collect();
+
+        let claimed: Vec<u32> = self
+            .dropped_tables
+            .iter()
+            .flat_map(|t| t.pages.iter().cloned())
+            .collect();
+        self.free_leaf_cells =
+            recover_free_leaf_cells(bytearray, page_size, &free_pages, &trunk_pages, &claimed);
+    }
+
+    pub fn dropped_tables(&self) -> &[DroppedTable] {
+        &self.dropped_tables
+    }
+
+    /// Cells of the freelist leaf pages not belonging to a dropped table
+    pub fn free_leaf_cells(&self) -> &[LeafCell] {
+        &self.free_leaf_cells
+    }
+
     pub fn get_page_by_number(&self, number: u32) -> Option<Page> {
         for page in self.pages.iter() {
             if page.number() == number {
@@ -644,6 +733,59 @@ impl MainFile {
         tables_info
     }
 
+    /// Rows of sqlite_master (type, name, tbl_name, rootpage and sql), in b-tree order
+    pub fn get_schema_rows(&self) -> Vec<Vec<String>> {
+        let mut cells: Vec<LeafCell> = vec![];
+        let mut visited: Vec<u32> = vec![];
+        self.collect_leaf_cells(None, 1, &mut cells, &mut visited);
+
+        cells.iter().map(|c| c.data()).collect()
+    }
+
+    /// Rows of sqlite_master as written by the last committed transaction of the WAL which
+    /// modified its first page (None if no such transaction)
+    pub fn get_wal_schema_rows(&self, wal_file: &WALFile) -> Option<Vec<Vec<String>>> {
+        wal_file.committed_page(1)?;
+        let mut cells: Vec<LeafCell> = vec![];
+        let mut visited: Vec<u32> = vec![];
+        self.collect_leaf_cells(Some(wal_file), 1, &mut cells, &mut visited);
+
+        Some(cells.iter().map(|c| c.data()).collect())
+    }
+
+    /// Leaf cells of the table b-tree rooted at `number`, reading the pages committed in the WAL
+    /// (if given) in place of the ones of the main file
+    fn collect_leaf_cells(
+        &self,
+        wal_file: Option<&WALFile>,
+        number: u32,
+        cells: &mut Vec<LeafCell>,
+        visited: &mut Vec<u32>,
+    ) {
+        // Corrupted files may have loops
+        if visited.contains(&number) {
+            return;
+        }
+        visited.push(number);
+
+        let page: Page = match wal_file
+            .and_then(|w| w.committed_page(number))
+            .or_else(|| self.get_page_by_number(number))
+        {
+            Some(p) => p,
+            None => return,
+        };
+        for cell in page.live_cells().iter() {
+            match cell {
+                Cell::LC(c) => cells.push(c.clone()),
+                Cell::ITC(c) => self.collect_leaf_cells(wal_file, c.left_pointer(), cells, visited),
+            }
+        }
+        if let Some(p) = page.header().rightmost_ptr() {
+            self.collect_leaf_cells(wal_file, p, cells, visited);
+        }
+    }
+
     pub fn get_triggers(&self) -> Vec<String> {
         let mut triggers: Vec<String> = vec![];
 
@@ -0,0 +1,662 @@
+use std::cmp::Ordering;
+
+use crate::schema::{tokenize, Affinity, Token, TokenKind};
+use crate::structs::StorageClass;
+use crate::utils::glob_match;
+
+/// Names of the rowid, when not used by a column
+const ROWID_NAMES: [&str; 3] = ["rowid", "_rowid_", "oid"];
+
+/// A typed value, compared like SQLite does
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl Value {
+    /// Types a parsed value by the storage class of its record, if known; otherwise numbers
+    /// are numbers unless the column has TEXT affinity
+    fn from_parsed(
+        value: &str,
+        storage_class: Option<&StorageClass>,
+        affinity: Option<&Affinity>,
+    ) -> Value {
+        match storage_class {
+            Some(StorageClass::Null) => return Value::Null,
+            Some(StorageClass::Text | StorageClass::Blob) => return Value::Text(value.to_string()),
+            Some(StorageClass::Integer | StorageClass::Real) => {
+                if let Some(v) = Value::numeric(value) {
+                    return v;
+                }
+            }
+            None => (),
+        }
+        if value == "NULL" {
+            return Value::Null;
+        }
+        if affinity != Some(&Affinity::Text) {
+            if let Some(v) = Value::numeric(value) {
+                return v;
+            }
+        }
+
+        Value::Text(value.to_string())
+    }
+
+    fn numeric(value: &str) -> Option<Value> {
+        match value.trim().parse::<i64>() {
+            Ok(i) => Some(Value::Integer(i)),
+            Err(_) => match value.trim().parse::<f64>() {
+                Ok(f) if f.is_finite() => Some(Value::Real(f)),
+                _ => None,
+            },
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Integer(i) => Some(*i as f64),
+            Value::Real(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    fn as_text(&self) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Integer(i) => i.to_string(),
+            Value::Real(f) => f.to_string(),
+            Value::Text(t) => t.to_string(),
+        }
+    }
+
+    /// Numbers come before text; NULL is not comparable
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Null, _) | (_, Value::Null) => None,
+            (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (Value::Text(_), _) => Some(Ordering::Greater),
+            (_, Value::Text(_)) => Some(Ordering::Less),
+            (a, b) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Column(String),
+    Literal(Value),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    /// LIKE (case insensitive, `%` and `_`) or GLOB (case sensitive, `*` and `?`)
+    Like {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        glob: bool,
+        negated: bool,
+    },
+    IsNull(Box<Expr>, bool),
+    In(Box<Expr>, Vec<Expr>, bool),
+    Between(Box<Expr>, Box<Expr>, Box<Expr>, bool),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// Values of a row, with the columns and affinities of its table
+struct RowContext<'a> {
+    columns: &'a [String],
+    affinities: &'a [Option<Affinity>],
+    rowid: Option<i64>,
+    values: &'a [String],
+    /// Storage classes of the values, if known
+    storage_classes: &'a [Option<StorageClass>],
+}
+
+impl RowContext<'_> {
+    fn column(&self, name: &str) -> (Value, Option<&Affinity>) {
+        match self
+            .columns
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(name))
+        {
+            Some(i) => {
+                let affinity: Option<&Affinity> = self.affinities.get(i).and_then(|a| a.as_ref());
+                match self.values.get(i) {
+                    Some(v) => {
+                        let storage_class: Option<&StorageClass> =
+                            self.storage_classes.get(i).and_then(|c| c.as_ref());
+                        (Value::from_parsed(v, storage_class, affinity), affinity)
+                    }
+                    None => (Value::Null, affinity),
+                }
+            }
+            None if ROWID_NAMES.contains(&name.to_lowercase().as_str()) => (
+                match self.rowid {
+                    Some(r) => Value::Integer(r),
+                    None => Value::Null,
+                },
+                Some(&Affinity::Integer),
+            ),
+            None => (Value::Null, None),
+        }
+    }
+}
+
+impl Expr {
+    /// Value of an operand, with the affinity of the column it comes from
+    fn value<'a>(&self, row: &'a RowContext) -> (Value, Option<&'a Affinity>) {
+        match self {
+            Expr::Column(name) => row.column(name),
+            Expr::Literal(v) => (v.clone(), None),
+            other => (
+                match other.eval(row) {
+                    Some(b) => Value::Integer(b as i64),
+                    None => Value::Null,
+                },
+                None,
+            ),
+        }
+    }
+
+    /// Compares two operands, applying the affinity of a column to a literal compared with it
+    fn compare(&self, other: &Expr, row: &RowContext) -> Option<Ordering> {
+        let (mut a, a_affinity): (Value, Option<&Affinity>) = self.value(row);
+        let (mut b, b_affinity): (Value, Option<&Affinity>) = other.value(row);
+        match (a_affinity, b_affinity) {
+            (Some(affinity), None) => b = apply_affinity(b, affinity),
+            (None, Some(affinity)) => a = apply_affinity(a, affinity),
+            _ => (),
+        }
+
+        a.compare(&b)
+    }
+
+    /// Three-valued evaluation: `None` is NULL
+    fn eval(&self, row: &RowContext) -> Option<bool> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) => match self.value(row).0 {
+                Value::Null => None,
+                Value::Text(t) => {
+                    Some(Value::numeric(&t).and_then(|v| v.as_f64()).unwrap_or(0.0) != 0.0)
+                }
+                v => Some(v.as_f64() != Some(0.0)),
+            },
+            Expr::Compare(a, op, b) => {
+                let ordering: Ordering = a.compare(b, row)?;
+                Some(match op {
+                    CompareOp::Eq => ordering == Ordering::Equal,
+                    CompareOp::Ne => ordering != Ordering::Equal,
+                    CompareOp::Lt => ordering == Ordering::Less,
+                    CompareOp::Le => ordering != Ordering::Greater,
+                    CompareOp::Gt => ordering == Ordering::Greater,
+                    CompareOp::Ge => ordering != Ordering::Less,
+                })
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                glob,
+                negated,
+            } => {
+                let text: Value = expr.value(row).0;
+                let pattern: Value = pattern.value(row).0;
+                if text == Value::Null || pattern == Value::Null {
+                    return None;
+                }
+                let matched: bool = match glob {
+                    true => glob_match(&pattern.as_text(), &text.as_text(), '*', '?', false),
+                    false => glob_match(&pattern.as_text(), &text.as_text(), '%', '_', true),
+                };
+                Some(matched != *negated)
+            }
+            Expr::IsNull(expr, negated) => Some((expr.value(row).0 == Value::Null) != *negated),
+            Expr::In(expr, list, negated) => {
+                let mut found_null: bool = false;
+                for item in list.iter() {
+                    match expr.compare(item, row) {
+                        Some(Ordering::Equal) => return Some(!negated),
+                        Some(_) => (),
+                        None => found_null = true,
+                    }
+                }
+                match found_null {
+                    true => None,
+                    false => Some(*negated),
+                }
+            }
+            Expr::Between(expr, low, high, negated) => {
+                let above: Option<bool> = expr.compare(low, row).map(|o| o != Ordering::Less);
+                let below: Option<bool> = expr.compare(high, row).map(|o| o != Ordering::Greater);
+                and(above, below).map(|b| b != *negated)
+            }
+            Expr::Not(expr) => expr.eval(row).map(|b| !b),
+            Expr::And(a, b) => and(a.eval(row), b.eval(row)),
+            Expr::Or(a, b) => match (a.eval(row), b.eval(row)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+        }
+    }
+
+    fn columns(&self, columns: &mut Vec<String>) {
+        match self {
+            Expr::Column(name) => columns.push(name.to_string()),
+            Expr::Literal(_) => (),
+            Expr::Compare(a, _, b) | Expr::And(a, b) | Expr::Or(a, b) => {
+                a.columns(columns);
+                b.columns(columns);
+            }
+            Expr::Like { expr, pattern, .. } => {
+                expr.columns(columns);
+                pattern.columns(columns);
+            }
+            Expr::IsNull(expr, _) | Expr::Not(expr) => expr.columns(columns),
+            Expr::In(expr, list, _) => {
+                expr.columns(columns);
+                list.iter().for_each(|item| item.columns(columns));
+            }
+            Expr::Between(expr, low, high, _) => {
+                expr.columns(columns);
+                low.columns(columns);
+                high.columns(columns);
+            }
+        }
+    }
+}
+
+fn and(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+    match (a, b) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }
+}
+
+/// Numeric affinity turns numeric text into numbers, TEXT affinity turns numbers into text
+fn apply_affinity(value: Value, affinity: &Affinity) -> Value {
+    match (affinity, &value) {
+        (Affinity::Text, Value::Integer(_) | Value::Real(_)) => Value::Text(value.as_text()),
+        (Affinity::Integer | Affinity::Real | Affinity::Numeric, Value::Text(t)) => {
+            Value::numeric(t).unwrap_or(value)
+        }
+        _ => value,
+    }
+}
+
+/// A WHERE-like condition on the values of rows, e.g.:
+/// `sender LIKE '%@example.com' AND date > 1700000000`.
+/// Supports comparisons, LIKE, GLOB, IN, BETWEEN, IS [NOT] NULL, AND, OR, NOT and parentheses;
+/// `rowid` is the rowid of the row, unless a column has that name
+#[derive(Clone, Debug)]
+pub struct Predicate {
+    expr: Expr,
+    pub source: String,
+}
+
+impl Predicate {
+    pub fn parse(source: &str) -> Result<Predicate, &'static str> {
+        let mut parser: Parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        let expr: Expr = parser.or()?;
+        if parser.peek().is_some() {
+            return Err("Unexpected token after the end of the condition");
+        }
+
+        Ok(Predicate {
+            expr,
+            source: source.to_string(),
+        })
+    }
+
+    /// Names of the columns the condition refers to
+    pub fn columns(&self) -> Vec<String> {
+        let mut columns: Vec<String> = vec![];
+        self.expr.columns(&mut columns);
+        columns.dedup();
+        columns
+    }
+
+    /// Evaluates the condition on a row: NULL results do not match
+    pub fn matches(
+        &self,
+        columns: &[String],
+        affinities: &[Option<Affinity>],
+        rowid: Option<i64>,
+        values: &[String],
+        storage_classes: &[Option<StorageClass>],
+    ) -> bool {
+        let row: RowContext = RowContext {
+            columns,
+            affinities,
+            rowid,
+            values,
+            storage_classes,
+        };
+        self.expr.eval(&row) == Some(true)
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token: Option<Token> = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(t) => t.kind == TokenKind::Word && t.value.eq_ignore_ascii_case(keyword),
+            None => false,
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found: bool = self.peek_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(t) if t.kind == TokenKind::Symbol && t.value == symbol => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind, error: &'static str) -> Result<(), &'static str> {
+        match self.next() {
+            Some(t) if t.kind == kind => Ok(()),
+            _ => Err(error),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, &'static str> {
+        let mut expr: Expr = self.and()?;
+        while self.eat_keyword("OR") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, &'static str> {
+        let mut expr: Expr = self.not()?;
+        while self.eat_keyword("AND") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, &'static str> {
+        match self.eat_keyword("NOT") {
+            true => Ok(Expr::Not(Box::new(self.not()?))),
+            false => self.comparison(),
+        }
+    }
+
+    fn compare_op(&mut self) -> Option<CompareOp> {
+        let op: CompareOp = if self.eat_symbol("=") {
+            self.eat_symbol("=");
+            CompareOp::Eq
+        } else if self.eat_symbol("!") {
+            if !self.eat_symbol("=") {
+                self.pos -= 1;
+                return None;
+            }
+            CompareOp::Ne
+        } else if self.eat_symbol("<") {
+            if self.eat_symbol("=") {
+                CompareOp::Le
+            } else if self.eat_symbol(">") {
+                CompareOp::Ne
+            } else {
+                CompareOp::Lt
+            }
+        } else if self.eat_symbol(">") {
+            match self.eat_symbol("=") {
+                true => CompareOp::Ge,
+                false => CompareOp::Gt,
+            }
+        } else {
+            return None;
+        };
+
+        Some(op)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, &'static str> {
+        let expr: Expr = self.operand()?;
+
+        if let Some(op) = self.compare_op() {
+            return Ok(Expr::Compare(Box::new(expr), op, Box::new(self.operand()?)));
+        }
+        if self.eat_keyword("IS") {
+            let negated: bool = self.eat_keyword("NOT");
+            if !self.eat_keyword("NULL") {
+                return Err("Expected NULL after IS");
+            }
+            return Ok(Expr::IsNull(Box::new(expr), negated));
+        }
+        if self.eat_keyword("NOTNULL") {
+            return Ok(Expr::IsNull(Box::new(expr), true));
+        }
+        if self.eat_keyword("ISNULL") {
+            return Ok(Expr::IsNull(Box::new(expr), false));
+        }
+
+        let negated: bool = self.eat_keyword("NOT");
+        if self.peek_keyword("LIKE") || self.peek_keyword("GLOB") {
+            let glob: bool = self.eat_keyword("GLOB");
+            self.eat_keyword("LIKE");
+            return Ok(Expr::Like {
+                expr: Box::new(expr),
+                pattern: Box::new(self.operand()?),
+                glob,
+                negated,
+            });
+        }
+        if self.eat_keyword("IN") {
+            self.expect(TokenKind::LParen, "Expected ( after IN")?;
+            let mut list: Vec<Expr> = vec![self.operand()?];
+            while let Some(TokenKind::Comma) = self.peek().map(|t| t.kind.clone()) {
+                self.pos += 1;
+                list.push(self.operand()?);
+            }
+            self.expect(TokenKind::RParen, "Expected ) at the end of IN list")?;
+            return Ok(Expr::In(Box::new(expr), list, negated));
+        }
+        if self.eat_keyword("BETWEEN") {
+            let low: Expr = self.operand()?;
+            if !self.eat_keyword("AND") {
+                return Err("Expected AND in BETWEEN");
+            }
+            let high: Expr = self.operand()?;
+            return Ok(Expr::Between(
+                Box::new(expr),
+                Box::new(low),
+                Box::new(high),
+                negated,
+            ));
+        }
+        if negated {
+            return Err("Expected LIKE, GLOB, IN or BETWEEN after NOT");
+        }
+
+        Ok(expr)
+    }
+
+    fn operand(&mut self) -> Result<Expr, &'static str> {
+        let token: Token = self.next().ok_or("Unexpected end of the condition")?;
+        match token.kind {
+            TokenKind::LParen => {
+                let expr: Expr = self.or()?;
+                self.expect(TokenKind::RParen, "Missing )")?;
+                Ok(expr)
+            }
+            TokenKind::Number => match Value::numeric(&token.value) {
+                Some(v) => Ok(Expr::Literal(v)),
+                None => Err("Invalid number"),
+            },
+            TokenKind::Symbol if token.value == "-" || token.value == "+" => {
+                match self.operand()? {
+                    Expr::Literal(Value::Integer(i)) if token.value == "-" => {
+                        Ok(Expr::Literal(Value::Integer(-i)))
+                    }
+                    Expr::Literal(Value::Real(f)) if token.value == "-" => {
+                        Ok(Expr::Literal(Value::Real(-f)))
+                    }
+                    expr @ Expr::Literal(Value::Integer(_) | Value::Real(_)) => Ok(expr),
+                    _ => Err("Sign must be followed by a number"),
+                }
+            }
+            TokenKind::String => Ok(Expr::Literal(Value::Text(token.value))),
+            TokenKind::Word if token.value.eq_ignore_ascii_case("NULL") => {
+                Ok(Expr::Literal(Value::Null))
+            }
+            TokenKind::Word | TokenKind::Quoted => Ok(Expr::Column(token.value)),
+            _ => Err("Expected a column, a value or ("),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns() -> Vec<String> {
+        ["id", "sender", "date", "code"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect()
+    }
+
+    fn affinities() -> Vec<Option<Affinity>> {
+        vec![
+            Some(Affinity::Integer),
+            Some(Affinity::Text),
+            Some(Affinity::Integer),
+            Some(Affinity::Text),
+        ]
+    }
+
+    /// Evaluates the condition on a row with rowid 7
+    fn matches(source: &str, values: &[&str]) -> bool {
+        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        Predicate::parse(source)
+            .unwrap()
+            .matches(&columns(), &affinities(), Some(7), &values, &[])
+    }
+
+    #[test]
+    fn parse_conditions() {
+        let predicate: Predicate = Predicate::parse(
+            "sender LIKE '%@example.com' AND (date > 1700000000 OR code IN (1, 2))",
+        )
+        .unwrap();
+        assert_eq!(predicate.columns(), vec!["sender", "date", "code"]);
+        assert!(Predicate::parse("date BETWEEN 1 AND 2 AND NOT sender IS NULL").is_ok());
+        assert!(Predicate::parse("sender NOT GLOB 'a*'").is_ok());
+    }
+
+    #[test]
+    fn parse_invalid_conditions() {
+        assert!(Predicate::parse("").is_err());
+        assert!(Predicate::parse("date >").is_err());
+        assert!(Predicate::parse("(date > 1").is_err());
+        assert!(Predicate::parse("date > 1 date").is_err());
+        assert!(Predicate::parse("sender LIKE 'x").is_err());
+    }
+
+    #[test]
+    fn comparisons_use_affinity() {
+        let row: [&str; 4] = ["1", "alice@example.com", "1700000001", "10"];
+        assert!(matches("date > 1700000000", &row));
+        assert!(matches("id = 1 AND date <> 5", &row));
+        // TEXT affinity: 10 is compared as text
+        assert!(matches("code = '10'", &row));
+        assert!(matches("code = 10", &row));
+        assert!(matches("rowid = 7", &row));
+        assert!(matches("date BETWEEN 1700000000 AND 1700000002", &row));
+        assert!(!matches("date NOT BETWEEN 1700000000 AND 1700000002", &row));
+        assert!(matches("id IN (3, 2, 1)", &row));
+    }
+
+    #[test]
+    fn null_values() {
+        let row: [&str; 4] = ["1", "NULL", "5", "x"];
+        assert!(matches("sender IS NULL", &row));
+        assert!(!matches("sender IS NOT NULL", &row));
+        // NULL = NULL is NULL, which does not match
+        assert!(!matches("sender = 'x'", &row));
+        assert!(!matches("NOT sender = 'x'", &row));
+        assert!(matches("sender = 'x' OR date = 5", &row));
+        assert!(!matches("id IN (2, NULL)", &row));
+        // Unknown columns are NULL
+        assert!(!matches("missing = 1", &row));
+    }
+
+    #[test]
+    fn like_and_glob() {
+        let row: [&str; 4] = ["1", "Alice@Example.com", "5", "x"];
+        assert!(matches("sender LIKE '%@example.com'", &row));
+        assert!(matches("sender LIKE 'a_ice%'", &row));
+        assert!(!matches("sender NOT LIKE 'ALICE%'", &row));
+        // GLOB is case sensitive
+        assert!(matches("sender GLOB 'A*@E?ample.com'", &row));
+        assert!(!matches("sender GLOB 'a*'", &row));
+        assert!(matches("sender NOT GLOB 'a*'", &row));
+    }
+
+    #[test]
+    fn values_use_storage_classes() {
+        let columns: Vec<String> = vec![String::from("a")];
+        let matches = |source: &str, value: &str, storage_class: StorageClass| -> bool {
+            Predicate::parse(source).unwrap().matches(
+                &columns,
+                &[None],
+                None,
+                &[value.to_string()],
+                &[Some(storage_class)],
+            )
+        };
+        // Text stored in a column without affinity is not a number
+        assert!(matches("a = '0123'", "0123", StorageClass::Text));
+        assert!(!matches("a = 123", "0123", StorageClass::Text));
+        assert!(matches("a > 1000", "0123", StorageClass::Text));
+        assert!(matches("a = 123", "123", StorageClass::Integer));
+        assert!(matches("a = 1.5", "1.5", StorageClass::Real));
+        // Text reading NULL is not NULL
+        assert!(matches("a = 'NULL'", "NULL", StorageClass::Text));
+        assert!(matches("a IS NULL", "NULL", StorageClass::Null));
+    }
+}
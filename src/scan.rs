@@ -0,0 +1,277 @@
+use log::debug;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::constants::SQLITE_MAGIC;
+use crate::evidence::FileHashes;
+
+/// Databases start at page-aligned offsets, and the smallest page is 512 bytes
+const SCAN_ALIGNMENT: usize = 512;
+
+/// Size of the chunks the scanned file is read in (a multiple of the alignment)
+const SCAN_CHUNK_SIZE: usize = 2048 * SCAN_ALIGNMENT;
+
+/// Length of the database header
+const HEADER_LEN: usize = 100;
+
+/// An SQLite database found inside another file
+#[derive(Serialize)]
+pub struct EmbeddedDatabase {
+    /// Offset of the header in the scanned file
+    pub offset: u64,
+    pub page_size: u32,
+    /// Size of the database in pages, according to its header (0 if not valid)
+    pub page_count: u32,
+    /// Bytes belonging to the database
+    pub length: u64,
+    /// The file ends before the size declared in the header
+    pub truncated: bool,
+}
+
+/// A database extracted from the scanned file, with the hashes of its bytes
+#[derive(Serialize)]
+pub struct ExtractedDatabase {
+    #[serde(flatten)]
+    pub database: EmbeddedDatabase,
+    pub file: FileHashes,
+}
+
+/// What has been extracted from a scanned file, written next to the extracted databases
+#[derive(Serialize)]
+pub struct ScanManifest {
+    pub source: String,
+    pub source_size: u64,
+    pub databases: Vec<ExtractedDatabase>,
+}
+
+/// Checks the fields of a database header having fixed or bounded values.
+/// Returns the page size and the size of the database in pages (0 if not valid)
+pub fn check_header(bytearray: &[u8]) -> Result<(u32, u32), &'static str> {
+    if bytearray.len() < HEADER_LEN {
+        return Err("Header too short");
+    }
+    if &bytearray[..15] != SQLITE_MAGIC.as_bytes() || bytearray[15] != 0 {
+        return Err("NOT AN SQLITE FILE");
+    }
+
+    let page_size: u32 = match u32::from_be_bytes([0, 0, bytearray[16], bytearray[17]]) {
+        1 => 65536,
+        p => p,
+    };
+    if page_size < 512 || !page_size.is_power_of_two() {
+        return Err("Invalid page size");
+    }
+    if !(1..=2).contains(&bytearray[18]) || !(1..=2).contains(&bytearray[19]) {
+        return Err("Invalid file format version");
+    }
+    if bytearray[21..24] != [64, 32, 32] {
+        return Err("Invalid payload fractions");
+    }
+    let read_u32 = |o: usize| -> u32 {
+        u32::from_be_bytes([
+            bytearray[o],
+            bytearray[o + 1],
+            bytearray[o + 2],
+            bytearray[o + 3],
+        ])
+    };
+    if read_u32(44) > 4 {
+        return Err("Invalid schema format number");
+    }
+    if read_u32(56) > 3 {
+        return Err("Invalid text encoding");
+    }
+
+    // The size in the header is valid only if written by the same change of the file
+    let page_count: u32 = match read_u32(92) == read_u32(24) {
+        true => read_u32(28),
+        false => 0,
+    };
+
+    Ok((page_size, page_count))
+}
+
+/// Fills the buffer from the reader, unless it ends before. Returns the number of bytes read
+fn read_chunk<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, &'static str> {
+    let mut filled: usize = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(_) => return Err("Cannot read the scanned file"),
+        }
+    }
+
+    Ok(filled)
+}
+
+/// Finds the databases whose header starts at a page-aligned offset of the file (of `file_len`
+/// bytes), read in chunks. A database without a valid size in its header extends up to the next
+/// one or to the end of the file
+pub fn find_databases<R: Read>(
+    reader: &mut R,
+    file_len: u64,
+) -> Result<Vec<EmbeddedDatabase>, &'static str> {
+    let mut databases: Vec<EmbeddedDatabase> = vec![];
+
+    // Chunks are aligned, so that every header is inside one of them
+    let mut buffer: Vec<u8> = vec![0; SCAN_CHUNK_SIZE];
+    let mut chunk_offset: u64 = 0;
+    loop {
+        let filled: usize = read_chunk(reader, &mut buffer)?;
+        let mut offset: usize = 0;
+        while offset + HEADER_LEN <= filled {
+            if let Ok((page_size, page_count)) = check_header(&buffer[offset..offset + HEADER_LEN])
+            {
+                debug!("SQLite header at offset {}", chunk_offset + offset as u64);
+                databases.push(EmbeddedDatabase {
+                    offset: chunk_offset + offset as u64,
+                    page_size,
+                    page_count,
+                    length: 0,
+                    truncated: false,
+                });
+            }
+            offset += SCAN_ALIGNMENT;
+        }
+        chunk_offset += filled as u64;
+        if filled < buffer.len() {
+            break;
+        }
+    }
+    let file_len: u64 = file_len.max(chunk_offset);
+
+    let ends: Vec<u64> = databases
+        .iter()
+        .skip(1)
+        .map(|d| d.offset)
+        .chain(std::iter::once(file_len))
+        .collect();
+    for (database, end) in databases.iter_mut().zip(ends) {
+        let available: u64 = file_len - database.offset;
+        match database.page_count {
+            0 => database.length = end - database.offset,
+            n => {
+                let declared: u64 = n as u64 * database.page_size as u64;
+                database.truncated = declared > available;
+                database.length = declared.min(available);
+            }
+        }
+    }
+
+    Ok(databases)
+}
+
+/// Copies the bytes of the database from the scanned file to `outpath`, hashing them
+pub fn extract_database<R: Read + Seek>(
+    reader: &mut R,
+    database: &EmbeddedDatabase,
+    outpath: &Path,
+) -> Result<FileHashes, &'static str> {
+    reader
+        .seek(SeekFrom::Start(database.offset))
+        .map_err(|_| "Cannot read the scanned file")?;
+    let outfile: File = File::create(outpath).map_err(|_| "Cannot create output file")?;
+    FileHashes::copy(
+        reader.take(database.length),
+        BufWriter::new(outfile),
+        outpath,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_dir;
+    use sha2::{Digest, Sha256};
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    /// Database of `page_count` pages of 512 bytes, the size being valid if `valid_size`
+    fn database(page_count: u32, valid_size: bool, fill: u8) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![fill; page_count as usize * 512];
+        bytes[..HEADER_LEN].fill(0);
+        bytes[..16].copy_from_slice(b"SQLite format 3\0");
+        bytes[16..18].copy_from_slice(&512u16.to_be_bytes());
+        bytes[18] = 1;
+        bytes[19] = 1;
+        bytes[21..24].copy_from_slice(&[64, 32, 32]);
+        bytes[24..28].copy_from_slice(&7u32.to_be_bytes());
+        bytes[28..32].copy_from_slice(&page_count.to_be_bytes());
+        bytes[44..48].copy_from_slice(&4u32.to_be_bytes());
+        bytes[56..60].copy_from_slice(&1u32.to_be_bytes());
+        match valid_size {
+            true => bytes[92..96].copy_from_slice(&7u32.to_be_bytes()),
+            false => bytes[92..96].copy_from_slice(&6u32.to_be_bytes()),
+        }
+        bytes
+    }
+
+    /// Reader returning at most 1000 bytes per read, so that chunks are filled in several reads
+    struct SlowReader(Cursor<Vec<u8>>);
+
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len: usize = buf.len().min(1000);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn check_headers() {
+        assert_eq!(check_header(&database(3, true, 0)), Ok((512, 3)));
+        assert_eq!(check_header(&database(3, false, 0)), Ok((512, 0)));
+        let mut invalid: Vec<u8> = database(1, true, 0);
+        invalid[16..18].copy_from_slice(&1000u16.to_be_bytes());
+        assert_eq!(check_header(&invalid), Err("Invalid page size"));
+        assert!(check_header(&database(1, true, 0)[..50]).is_err());
+    }
+
+    #[test]
+    fn databases_at_aligned_offsets() {
+        let mut data: Vec<u8> = vec![0xAA; SCAN_CHUNK_SIZE - SCAN_ALIGNMENT];
+        // Not page-aligned: ignored
+        data[700..700 + HEADER_LEN].copy_from_slice(&database(1, true, 0)[..HEADER_LEN]);
+        // Last aligned offset of the first chunk, the database continuing in the second one
+        let first: Vec<u8> = database(3, true, 1);
+        data.extend(&first);
+        data.extend(vec![0xAA; SCAN_ALIGNMENT]);
+        // Size not valid: up to the next database
+        let second: u64 = data.len() as u64;
+        data.extend(database(2, false, 2));
+        // Declaring more pages than the file has
+        let third: u64 = data.len() as u64;
+        data.extend(&database(4, true, 3)[..3 * 512]);
+
+        let databases: Vec<EmbeddedDatabase> = find_databases(
+            &mut SlowReader(Cursor::new(data.clone())),
+            data.len() as u64,
+        )
+        .unwrap();
+        let found: Vec<(u64, u32, u64, bool)> = databases
+            .iter()
+            .map(|d| (d.offset, d.page_count, d.length, d.truncated))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ((SCAN_CHUNK_SIZE - SCAN_ALIGNMENT) as u64, 3, 3 * 512, false),
+                (second, 0, third - second, false),
+                (third, 4, 3 * 512, true),
+            ]
+        );
+
+        // The extracted bytes are the ones of the database, hashed while copied
+        let dir: PathBuf = test_dir("scan");
+        let outpath: PathBuf = dir.join("first.db");
+        let hashes: FileHashes =
+            extract_database(&mut Cursor::new(&data), &databases[0], &outpath).unwrap();
+        assert_eq!(std::fs::read(&outpath).unwrap(), first);
+        assert_eq!(hashes.size, first.len() as u64);
+        assert_eq!(hashes.sha256, crate::utils::to_hex(&Sha256::digest(&first)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1061 @@
+use base64::{engine::general_purpose, Engine as _};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+/// Keywords which end the type name of a column and start its constraints
+const COLUMN_CONSTRAINT_KEYWORDS: [&str; 11] = [
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+/// Type affinity of a column, computed from its declared type
+/// (https://www.sqlite.org/datatype3.html#determination_of_column_affinity)
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum Affinity {
+    Integer,
+    Text,
+    #[default]
+    Blob,
+    Real,
+    Numeric,
+}
+
+impl Affinity {
+    pub fn from_declared_type(declared_type: &str) -> Affinity {
+        let t: String = declared_type.to_ascii_uppercase();
+
+        if t.contains("INT") {
+            Affinity::Integer
+        } else if t.contains("CHAR") || t.contains("CLOB") || t.contains("TEXT") {
+            Affinity::Text
+        } else if t.contains("BLOB") || t.trim().is_empty() {
+            Affinity::Blob
+        } else if t.contains("REAL") || t.contains("FLOA") || t.contains("DOUB") {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+}
+
+/// Expression of a generated column
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GeneratedColumn {
+    pub expression: String,
+    /// STORED columns are saved in the record, VIRTUAL ones are computed when read
+    pub stored: bool,
+}
+
+/// Definition of a single column of a table
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ColumnDef {
+    pub name: String,
+    pub declared_type: String,
+    pub affinity: Affinity,
+    pub primary_key: bool,
+    /// Column-level `PRIMARY KEY DESC` (such a column is not an alias of the rowid)
+    pub primary_key_desc: bool,
+    pub autoincrement: bool,
+    pub not_null: bool,
+    pub unique: bool,
+    /// Source text of the DEFAULT clause (e.g.: `'abc'`, `-1`, `CURRENT_TIMESTAMP`, `(expr)`)
+    pub default: Option<String>,
+    pub collate: Option<String>,
+    pub generated: Option<GeneratedColumn>,
+}
+
+impl ColumnDef {
+    /// VIRTUAL generated columns are not saved in the record
+    pub fn is_stored(&self) -> bool {
+        match &self.generated {
+            Some(g) => g.stored,
+            None => true,
+        }
+    }
+
+    /// Value of the DEFAULT clause, formatted like the values read from records
+    pub fn default_value(&self) -> String {
+        let default: &str = match &self.default {
+            Some(d) => d.trim(),
+            None => return String::from("NULL"),
+        };
+
+        // A constant expression between parentheses, e.g.: DEFAULT (0)
+        let literal: &str = match default.starts_with('(') && default.ends_with(')') {
+            true => default[1..default.len() - 1].trim(),
+            false => default,
+        };
+
+        if literal.len() >= 2 && literal.starts_with('\'') && literal.ends_with('\'') {
+            literal[1..literal.len() - 1].replace("''", "'")
+        } else if literal.len() >= 3
+            && (literal.starts_with("X'") || literal.starts_with("x'"))
+            && literal.ends_with('\'')
+        {
+            let hex: &str = &literal[2..literal.len() - 1];
+            let bytes: Vec<u8> = (0..hex.len() / 2)
+                .filter_map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok())
+                .collect();
+            general_purpose::STANDARD.encode(bytes)
+        } else if literal.eq_ignore_ascii_case("TRUE") {
+            String::from("1")
+        } else if literal.eq_ignore_ascii_case("FALSE") {
+            String::from("0")
+        } else if literal.eq_ignore_ascii_case("NULL") {
+            String::from("NULL")
+        } else {
+            literal.trim_start_matches('+').to_string()
+        }
+    }
+}
+
+/// Representation of a `CREATE TABLE` statement stored in sqlite_master
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TableSchema {
+    pub name: String,
+    /// Source text of the `CREATE` query
+    pub sql: String,
+    pub columns: Vec<ColumnDef>,
+    /// Columns listed in a table-level PRIMARY KEY constraint
+    pub primary_key: Vec<String>,
+    pub temporary: bool,
+    pub if_not_exists: bool,
+    pub without_rowid: bool,
+    pub strict: bool,
+    /// Index of the `INTEGER PRIMARY KEY` column, whose value is stored as rowid
+    pub rowid_alias: Option<usize>,
+    /// Module of a virtual table (e.g.: fts5, rtree)
+    pub module: Option<String>,
+    /// Arguments passed to the module of a virtual table
+    pub module_arguments: Vec<String>,
+}
+
+impl TableSchema {
+    /// Parses a `CREATE TABLE` query
+    pub fn parse(sql: &str) -> Result<TableSchema, &'static str> {
+        let tokens: Vec<Token> = tokenize(sql)?;
+        let mut parser: Parser = Parser {
+            sql,
+            tokens,
+            pos: 0,
+        };
+
+        let mut schema: TableSchema = parser.create_table()?;
+        schema.sql = sql.trim().to_string();
+
+        Ok(schema)
+    }
+
+    pub fn column_names(&self) -> Vec<String> {
+        self.columns.iter().map(|c| c.name.to_string()).collect()
+    }
+
+    /// Aligns the values of a record with the columns of the table.
+    /// Records written before an `ALTER TABLE ... ADD COLUMN` lack the new columns,
+    /// which are filled with their DEFAULT value; VIRTUAL generated columns are set to NULL.
+    /// Returns the names of the defaulted columns and the values exceeding the columns.
+    pub fn align_record(&self, record: &mut Vec<String>) -> (Vec<String>, Vec<String>) {
+        let mut values = std::mem::take(record).into_iter();
+        let mut defaulted: Vec<String> = vec![];
+
+        for column in self.columns.iter() {
+            if !column.is_stored() {
+                record.push(String::from("NULL"));
+                continue;
+            }
+
+            match values.next() {
+                Some(value) => record.push(value),
+                None => {
+                    record.push(column.default_value());
+                    defaulted.push(column.name.to_string());
+                }
+            }
+        }
+
+        (defaulted, values.collect())
+    }
+
+    /// A column is an alias of the rowid if it is the only primary key column
+    /// and its declared type is exactly INTEGER (https://www.sqlite.org/lang_createtable.html#rowid)
+    fn find_rowid_alias(&self) -> Option<usize> {
+        if self.without_rowid {
+            return None;
+        }
+
+        let pk_columns: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.primary_key)
+            .map(|(i, _)| i)
+            .collect();
+        if pk_columns.len() != 1 {
+            return None;
+        }
+
+        let column: &ColumnDef = &self.columns[pk_columns[0]];
+        if column.declared_type.eq_ignore_ascii_case("INTEGER") && !column.primary_key_desc {
+            Some(pk_columns[0])
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum TokenKind {
+    /// Bare word: keyword or unquoted identifier
+    Word,
+    /// Identifier between "", `` or []
+    Quoted,
+    /// String literal between ''
+    String,
+    Number,
+    /// Blob literal: X'...'
+    Blob,
+    LParen,
+    RParen,
+    Comma,
+    Dot,
+    Semicolon,
+    Symbol,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Token {
+    pub(crate) kind: TokenKind,
+    /// Unquoted value of the token
+    pub(crate) value: String,
+    /// Byte offsets of the token in the original query
+    start: usize,
+    end: usize,
+}
+
+/// Splits a query into tokens, dropping whitespaces and comments
+pub(crate) fn tokenize(sql: &str) -> Result<Vec<Token>, &'static str> {
+    let chars: Vec<(usize, char)> = sql.char_indices().collect();
+    let offset_of = |i: usize| -> usize {
+        match chars.get(i) {
+            Some((o, _)) => *o,
+            None => sql.len(),
+        }
+    };
+
+    let mut tokens: Vec<Token> = vec![];
+    let mut i: usize = 0;
+    while i < chars.len() {
+        let c: char = chars[i].1;
+        let start: usize = i;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        // Comments
+        if c == '-' && i + 1 < chars.len() && chars[i + 1].1 == '-' {
+            while i < chars.len() && chars[i].1 != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '/' && i + 1 < chars.len() && chars[i + 1].1 == '*' {
+            i += 2;
+            while i < chars.len()
+                && !(chars[i].1 == '*' && i + 1 < chars.len() && chars[i + 1].1 == '/')
+            {
+                i += 1;
+            }
+            i += 2;
+            continue;
+        }
+
+        let kind: TokenKind;
+        let mut value: String = String::new();
+
+        if (c == 'x' || c == 'X') && i + 1 < chars.len() && chars[i + 1].1 == '\'' {
+            i += 2;
+            while i < chars.len() && chars[i].1 != '\'' {
+                value.push(chars[i].1);
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err("Unterminated blob literal");
+            }
+            i += 1;
+            kind = TokenKind::Blob;
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len()
+                && (chars[i].1.is_alphanumeric() || chars[i].1 == '_' || chars[i].1 == '$')
+            {
+                value.push(chars[i].1);
+                i += 1;
+            }
+            kind = TokenKind::Word;
+        } else if c.is_ascii_digit()
+            || (c == '.' && i + 1 < chars.len() && chars[i + 1].1.is_ascii_digit())
+        {
+            while i < chars.len() && (chars[i].1.is_ascii_alphanumeric() || chars[i].1 == '.') {
+                value.push(chars[i].1);
+                // Exponent sign (e.g.: 1e-5)
+                if (chars[i].1 == 'e' || chars[i].1 == 'E')
+                    && i + 1 < chars.len()
+                    && (chars[i + 1].1 == '-' || chars[i + 1].1 == '+')
+                {
+                    i += 1;
+                    value.push(chars[i].1);
+                }
+                i += 1;
+            }
+            kind = TokenKind::Number;
+        } else if c == '"' || c == '`' || c == '[' || c == '\'' {
+            let closing: char = if c == '[' { ']' } else { c };
+            i += 1;
+            loop {
+                if i >= chars.len() {
+                    return Err("Unterminated quoted token");
+                }
+                if chars[i].1 == closing {
+                    // A doubled quote is an escaped quote (not for [])
+                    if closing != ']' && i + 1 < chars.len() && chars[i + 1].1 == closing {
+                        value.push(closing);
+                        i += 2;
+                        continue;
+                    }
+                    i += 1;
+                    break;
+                }
+                value.push(chars[i].1);
+                i += 1;
+            }
+            kind = if c == '\'' {
+                TokenKind::String
+            } else {
+                TokenKind::Quoted
+            };
+        } else {
+            i += 1;
+            value.push(c);
+            kind = match c {
+                '(' => TokenKind::LParen,
+                ')' => TokenKind::RParen,
+                ',' => TokenKind::Comma,
+                '.' => TokenKind::Dot,
+                ';' => TokenKind::Semicolon,
+                _ => TokenKind::Symbol,
+            };
+        }
+
+        tokens.push(Token {
+            kind,
+            value,
+            start: offset_of(start),
+            end: offset_of(i),
+        });
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    sql: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_kind(&self, kind: TokenKind) -> bool {
+        match self.peek() {
+            Some(t) => t.kind == kind,
+            None => false,
+        }
+    }
+
+    fn is_keyword_at(&self, pos: usize, keyword: &str) -> bool {
+        match self.tokens.get(pos) {
+            Some(t) => t.kind == TokenKind::Word && t.value.eq_ignore_ascii_case(keyword),
+            None => false,
+        }
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        self.is_keyword_at(self.pos, keyword)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token: Option<Token> = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_keyword(keyword) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_keyword(&mut self, keyword: &str, err: &'static str) -> Result<(), &'static str> {
+        match self.eat_keyword(keyword) {
+            true => Ok(()),
+            false => Err(err),
+        }
+    }
+
+    fn is_at_definition_end(&self) -> bool {
+        match self.peek() {
+            Some(t) => t.kind == TokenKind::Comma || t.kind == TokenKind::RParen,
+            None => true,
+        }
+    }
+
+    fn is_at_column_constraint(&self) -> bool {
+        COLUMN_CONSTRAINT_KEYWORDS
+            .iter()
+            .any(|keyword| self.peek_keyword(keyword))
+    }
+
+    /// Reads a (possibly quoted) identifier
+    fn identifier(&mut self) -> Result<String, &'static str> {
+        match self.next() {
+            Some(t) => match t.kind {
+                TokenKind::Word | TokenKind::Quoted | TokenKind::String => Ok(t.value),
+                _ => Err("Expected identifier in creation query"),
+            },
+            None => Err("Unexpected end of creation query"),
+        }
+    }
+
+    /// Reads a qualified name (e.g.: main.table) and returns its last part
+    fn qualified_name(&mut self) -> Result<String, &'static str> {
+        let mut name: String = self.identifier()?;
+        while self.peek_kind(TokenKind::Dot) {
+            self.pos += 1;
+            name = self.identifier()?;
+        }
+        Ok(name)
+    }
+
+    /// Consumes a parenthesized group and returns the source text between the parentheses
+    fn parenthesized(&mut self) -> Result<String, &'static str> {
+        let open: Token = match self.next() {
+            Some(t) if t.kind == TokenKind::LParen => t,
+            _ => return Err("Expected '(' in creation query"),
+        };
+
+        let mut depth: usize = 1;
+        while let Some(t) = self.next() {
+            match t.kind {
+                TokenKind::LParen => depth += 1,
+                TokenKind::RParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(self.sql[open.end..t.start].trim().to_string());
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Err("Unbalanced parentheses in creation query")
+    }
+
+    fn create_table(&mut self) -> Result<TableSchema, &'static str> {
+        let mut schema: TableSchema = TableSchema::default();
+
+        self.expect_keyword("CREATE", "Not a table creation query")?;
+        schema.temporary = self.eat_keyword("TEMP") || self.eat_keyword("TEMPORARY");
+        let is_virtual: bool = self.eat_keyword("VIRTUAL");
+        self.expect_keyword("TABLE", "Not a table creation query")?;
+
+        if self.peek_keyword("IF") {
+            self.pos += 1;
+            self.expect_keyword("NOT", "Expected IF NOT EXISTS")?;
+            self.expect_keyword("EXISTS", "Expected IF NOT EXISTS")?;
+            schema.if_not_exists = true;
+        }
+
+        schema.name = self.qualified_name()?;
+
+        if is_virtual {
+            self.virtual_table(&mut schema)?;
+            return Ok(schema);
+        }
+
+        if self.peek_keyword("AS") {
+            return Err("CREATE TABLE ... AS SELECT query has no column definitions");
+        }
+
+        match self.next() {
+            Some(t) if t.kind == TokenKind::LParen => (),
+            _ => return Err("Expected column definitions in creation query"),
+        }
+
+        loop {
+            if self.is_at_table_constraint() {
+                self.table_constraint(&mut schema)?;
+            } else {
+                let column: ColumnDef = self.column_def()?;
+                schema.columns.push(column);
+            }
+
+            match self.next() {
+                Some(t) if t.kind == TokenKind::Comma => continue,
+                Some(t) if t.kind == TokenKind::RParen => break,
+                Some(_) => return Err("Unexpected token in column definitions"),
+                None => return Err("Unexpected end of creation query"),
+            }
+        }
+
+        // Table options
+        while let Some(t) = self.next() {
+            match t.kind {
+                TokenKind::Comma => (),
+                TokenKind::Semicolon => break,
+                TokenKind::Word if t.value.eq_ignore_ascii_case("WITHOUT") => {
+                    self.expect_keyword("ROWID", "Expected WITHOUT ROWID")?;
+                    schema.without_rowid = true;
+                }
+                TokenKind::Word if t.value.eq_ignore_ascii_case("STRICT") => schema.strict = true,
+                _ => debug!("Unknown table option: {}", t.value),
+            }
+        }
+
+        if schema.columns.is_empty() {
+            return Err("No columns found in creation query");
+        }
+
+        // Columns in a table-level PRIMARY KEY
+        for column in schema.columns.iter_mut() {
+            if schema
+                .primary_key
+                .iter()
+                .any(|pk| pk.eq_ignore_ascii_case(&column.name))
+            {
+                column.primary_key = true;
+            }
+        }
+
+        schema.rowid_alias = schema.find_rowid_alias();
+
+        Ok(schema)
+    }
+
+    /// Reads `USING module(arguments)` of a virtual table and derives its columns from the arguments
+    fn virtual_table(&mut self, schema: &mut TableSchema) -> Result<(), &'static str> {
+        self.expect_keyword("USING", "Expected USING in virtual table creation query")?;
+        let module: String = self.identifier()?.to_ascii_lowercase();
+
+        if self.peek_kind(TokenKind::LParen) {
+            let mut argument_start: usize = self.next().unwrap().end;
+            let mut depth: usize = 0;
+            loop {
+                let t: Token = match self.next() {
+                    Some(t) => t,
+                    None => return Err("Unbalanced parentheses in creation query"),
+                };
+                match t.kind {
+                    TokenKind::LParen => depth += 1,
+                    TokenKind::RParen if depth > 0 => depth -= 1,
+                    TokenKind::RParen | TokenKind::Comma if depth == 0 => {
+                        let argument: &str = self.sql[argument_start..t.start].trim();
+                        if !argument.is_empty() {
+                            schema.module_arguments.push(argument.to_string());
+                        }
+                        argument_start = t.end;
+                        if t.kind == TokenKind::RParen {
+                            break;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let is_rtree: bool = module.starts_with("rtree");
+        for argument in schema.module_arguments.iter() {
+            // Options like tokenize=porter, content=..., prefix=...
+            if argument.contains('=')
+                || ((module == "fts3" || module == "fts4")
+                    && argument.to_ascii_lowercase().starts_with("tokenize"))
+            {
+                continue;
+            }
+
+            let name: Option<String> = tokenize(argument)?
+                .into_iter()
+                .find(|t| {
+                    t.kind == TokenKind::Word
+                        || t.kind == TokenKind::Quoted
+                        || t.kind == TokenKind::String
+                })
+                .map(|t| t.value);
+            let name: String = match name {
+                Some(n) => n,
+                None => continue,
+            };
+
+            let affinity: Affinity = match is_rtree {
+                true if schema.columns.is_empty() || module == "rtree_i32" => Affinity::Integer,
+                true if argument.starts_with('+') => Affinity::Blob,
+                true => Affinity::Real,
+                false => Affinity::Blob,
+            };
+
+            schema.columns.push(ColumnDef {
+                name,
+                affinity,
+                ..Default::default()
+            });
+        }
+
+        schema.module = Some(module);
+        Ok(())
+    }
+
+    fn is_at_table_constraint(&self) -> bool {
+        (self.peek_keyword("CONSTRAINT"))
+            || (self.peek_keyword("PRIMARY") && self.is_keyword_at(self.pos + 1, "KEY"))
+            || (self.peek_keyword("FOREIGN") && self.is_keyword_at(self.pos + 1, "KEY"))
+            || ((self.peek_keyword("UNIQUE") || self.peek_keyword("CHECK"))
+                && matches!(self.tokens.get(self.pos + 1), Some(t) if t.kind == TokenKind::LParen))
+    }
+
+    fn table_constraint(&mut self, schema: &mut TableSchema) -> Result<(), &'static str> {
+        if self.eat_keyword("CONSTRAINT") {
+            self.identifier()?;
+        }
+
+        if self.eat_keyword("PRIMARY") {
+            self.expect_keyword("KEY", "Expected PRIMARY KEY")?;
+            let indexed_columns: String = self.parenthesized()?;
+            let tokens: Vec<Token> = tokenize(&indexed_columns)?;
+            let mut expect_name: bool = true;
+            let mut depth: usize = 0;
+            for t in tokens.iter() {
+                match t.kind {
+                    TokenKind::LParen => depth += 1,
+                    TokenKind::RParen => depth -= 1,
+                    TokenKind::Comma if depth == 0 => expect_name = true,
+                    TokenKind::Word | TokenKind::Quoted | TokenKind::String if expect_name => {
+                        schema.primary_key.push(t.value.to_string());
+                        expect_name = false;
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        // UNIQUE, CHECK and FOREIGN KEY constraints do not change columns
+        while !self.is_at_definition_end() {
+            if self.peek_kind(TokenKind::LParen) {
+                self.parenthesized()?;
+            } else {
+                self.pos += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn column_def(&mut self) -> Result<ColumnDef, &'static str> {
+        let mut column: ColumnDef = ColumnDef {
+            name: self.identifier()?,
+            ..Default::default()
+        };
+
+        // Type name: one or more words, optionally followed by '(size)' or '(precision, scale)'
+        let mut type_words: Vec<String> = vec![];
+        while !self.is_at_column_constraint() {
+            match self.peek() {
+                Some(t) if t.kind == TokenKind::Word || t.kind == TokenKind::Quoted => {
+                    type_words.push(t.value.to_string());
+                    self.pos += 1;
+                }
+                _ => break,
+            }
+        }
+        column.declared_type = type_words.join(" ");
+        if !type_words.is_empty() && self.peek_kind(TokenKind::LParen) {
+            let size: String = self.parenthesized()?;
+            column.declared_type = format!("{}({})", column.declared_type, size);
+        }
+        column.affinity = Affinity::from_declared_type(&column.declared_type);
+
+        // Column constraints
+        while !self.is_at_definition_end() {
+            if self.eat_keyword("CONSTRAINT") {
+                self.identifier()?;
+            } else if self.eat_keyword("PRIMARY") {
+                self.expect_keyword("KEY", "Expected PRIMARY KEY")?;
+                column.primary_key = true;
+                let _ = self.eat_keyword("ASC");
+                column.primary_key_desc = self.eat_keyword("DESC");
+                self.conflict_clause()?;
+                column.autoincrement = self.eat_keyword("AUTOINCREMENT");
+            } else if self.eat_keyword("NOT") {
+                self.expect_keyword("NULL", "Expected NOT NULL")?;
+                column.not_null = true;
+                self.conflict_clause()?;
+            } else if self.eat_keyword("NULL") {
+                self.conflict_clause()?;
+            } else if self.eat_keyword("UNIQUE") {
+                column.unique = true;
+                self.conflict_clause()?;
+            } else if self.eat_keyword("CHECK") {
+                self.parenthesized()?;
+            } else if self.eat_keyword("DEFAULT") {
+                column.default = Some(self.default_value()?);
+            } else if self.eat_keyword("COLLATE") {
+                column.collate = Some(self.identifier()?);
+            } else if self.eat_keyword("REFERENCES") {
+                self.foreign_key_clause()?;
+            } else if self.peek_keyword("GENERATED") || self.peek_keyword("AS") {
+                if self.eat_keyword("GENERATED") {
+                    self.expect_keyword("ALWAYS", "Expected GENERATED ALWAYS")?;
+                }
+                self.expect_keyword("AS", "Expected AS in generated column")?;
+                let expression: String = self.parenthesized()?;
+                let stored: bool = self.eat_keyword("STORED");
+                let _ = self.eat_keyword("VIRTUAL");
+                column.generated = Some(GeneratedColumn { expression, stored });
+            } else {
+                let t: Token = self.next().unwrap();
+                debug!(
+                    "Skipping unknown token in column {}: {}",
+                    column.name, t.value
+                );
+            }
+        }
+
+        Ok(column)
+    }
+
+    /// Consumes an optional `ON CONFLICT <resolution>` clause
+    fn conflict_clause(&mut self) -> Result<(), &'static str> {
+        if self.peek_keyword("ON") && self.is_keyword_at(self.pos + 1, "CONFLICT") {
+            self.pos += 2;
+            self.identifier()?;
+        }
+        Ok(())
+    }
+
+    fn default_value(&mut self) -> Result<String, &'static str> {
+        let first: Token = match self.peek() {
+            Some(t) => t.clone(),
+            None => return Err("Expected DEFAULT value"),
+        };
+
+        match first.kind {
+            TokenKind::LParen => {
+                let start: usize = first.start;
+                self.parenthesized()?;
+                let end: usize = self.tokens[self.pos - 1].end;
+                Ok(self.sql[start..end].to_string())
+            }
+            // Signed number
+            TokenKind::Symbol if first.value == "-" || first.value == "+" => {
+                self.pos += 1;
+                match self.next() {
+                    Some(n) => Ok(self.sql[first.start..n.end].to_string()),
+                    None => Err("Expected DEFAULT value"),
+                }
+            }
+            _ => {
+                self.pos += 1;
+                Ok(self.sql[first.start..first.end].to_string())
+            }
+        }
+    }
+
+    /// Consumes the foreign table, its columns and the ON/MATCH/DEFERRABLE clauses
+    fn foreign_key_clause(&mut self) -> Result<(), &'static str> {
+        self.identifier()?;
+        if self.peek_kind(TokenKind::LParen) {
+            self.parenthesized()?;
+        }
+
+        loop {
+            if self.eat_keyword("SET") {
+                // SET NULL / SET DEFAULT must not be read as column constraints
+                self.pos += 1;
+            } else if self.peek_keyword("NOT") && self.is_keyword_at(self.pos + 1, "DEFERRABLE") {
+                self.pos += 2;
+            } else if self.is_at_definition_end() || self.is_at_column_constraint() {
+                break;
+            } else {
+                self.pos += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(sql: &str) -> TableSchema {
+        TableSchema::parse(sql).unwrap()
+    }
+
+    fn types(schema: &TableSchema) -> Vec<&str> {
+        schema
+            .columns
+            .iter()
+            .map(|c| c.declared_type.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn ios_sms_message() {
+        let schema: TableSchema = parse(
+            "CREATE TABLE message (ROWID INTEGER PRIMARY KEY AUTOINCREMENT, guid TEXT UNIQUE NOT NULL, \
+             text TEXT, replace INTEGER DEFAULT 0, service_center TEXT, handle_id INTEGER DEFAULT 0, \
+             subject TEXT, country TEXT, attributedBody BLOB, version INTEGER DEFAULT 0, \
+             type INTEGER DEFAULT 0, service TEXT, account TEXT, date INTEGER, \
+             is_from_me INTEGER DEFAULT 0, cache_roomnames TEXT, group_title TEXT DEFAULT NULL)",
+        );
+        assert_eq!(schema.name, "message");
+        assert_eq!(schema.columns.len(), 17);
+        assert_eq!(schema.rowid_alias, Some(0));
+        assert!(schema.columns[0].autoincrement);
+        assert!(schema.columns[1].unique && schema.columns[1].not_null);
+        assert_eq!(schema.columns[8].affinity, Affinity::Blob);
+        assert_eq!(schema.columns[3].default_value(), "0");
+        assert_eq!(schema.columns[16].default_value(), "NULL");
+    }
+
+    #[test]
+    fn chrome_history_urls() {
+        let schema: TableSchema = parse(
+            "CREATE TABLE urls(id INTEGER PRIMARY KEY AUTOINCREMENT,url LONGVARCHAR,\
+             title LONGVARCHAR,visit_count INTEGER DEFAULT 0 NOT NULL,\
+             typed_count INTEGER DEFAULT 0 NOT NULL,last_visit_time INTEGER NOT NULL,\
+             hidden INTEGER DEFAULT 0 NOT NULL)",
+        );
+        assert_eq!(
+            schema.column_names(),
+            vec![
+                "id",
+                "url",
+                "title",
+                "visit_count",
+                "typed_count",
+                "last_visit_time",
+                "hidden"
+            ]
+        );
+        assert_eq!(schema.columns[1].affinity, Affinity::Text);
+        assert!(schema.columns[3].not_null);
+        assert_eq!(schema.rowid_alias, Some(0));
+    }
+
+    #[test]
+    fn whatsapp_core_data() {
+        let schema: TableSchema = parse(
+            "CREATE TABLE ZWAMESSAGE ( Z_PK INTEGER PRIMARY KEY, Z_ENT INTEGER, Z_OPT INTEGER, \
+             ZISFROMME INTEGER, ZMESSAGEDATE TIMESTAMP, ZTEXT VARCHAR, ZMEDIASECTIONID VARCHAR )",
+        );
+        assert_eq!(schema.rowid_alias, Some(0));
+        assert_eq!(
+            types(&schema),
+            vec![
+                "INTEGER",
+                "INTEGER",
+                "INTEGER",
+                "INTEGER",
+                "TIMESTAMP",
+                "VARCHAR",
+                "VARCHAR"
+            ]
+        );
+        assert_eq!(schema.columns[4].affinity, Affinity::Numeric);
+    }
+
+    #[test]
+    fn quoted_identifiers() {
+        let schema: TableSchema = parse(
+            "CREATE TABLE \"my table\" (`order` INTEGER, [group] TEXT, \"a \"\"b\"\"\" REAL, 'c' BLOB)",
+        );
+        assert_eq!(schema.name, "my table");
+        assert_eq!(
+            schema.column_names(),
+            vec!["order", "group", "a \"b\"", "c"]
+        );
+        assert_eq!(schema.columns[2].affinity, Affinity::Real);
+    }
+
+    #[test]
+    fn qualified_name_and_if_not_exists() {
+        let schema: TableSchema = parse("CREATE TEMP TABLE IF NOT EXISTS main.\"t\" (a, b)");
+        assert_eq!(schema.name, "t");
+        assert!(schema.if_not_exists);
+        assert!(schema.temporary);
+        assert_eq!(types(&schema), vec!["", ""]);
+        assert_eq!(schema.columns[0].affinity, Affinity::Blob);
+    }
+
+    #[test]
+    fn nested_parentheses_in_default_and_check() {
+        let schema: TableSchema = parse(
+            "CREATE TABLE t (a INTEGER DEFAULT (abs(-(1 + 2))) CHECK (a > (0) AND (a < 10)), \
+             b TEXT DEFAULT (strftime('%s', 'now')), c NUMERIC(10, 2) DEFAULT -1.5, \
+             d TEXT DEFAULT 'it''s', e BLOB DEFAULT X'0102', f INTEGER DEFAULT (0))",
+        );
+        assert_eq!(schema.column_names(), vec!["a", "b", "c", "d", "e", "f"]);
+        assert_eq!(
+            schema.columns[0].default.as_deref(),
+            Some("(abs(-(1 + 2)))")
+        );
+        assert_eq!(
+            schema.columns[1].default.as_deref(),
+            Some("(strftime('%s', 'now'))")
+        );
+        assert_eq!(schema.columns[2].declared_type, "NUMERIC(10, 2)");
+        assert_eq!(schema.columns[2].default_value(), "-1.5");
+        assert_eq!(schema.columns[3].default_value(), "it's");
+        assert_eq!(schema.columns[4].default_value(), "AQI=");
+        assert_eq!(schema.columns[5].default_value(), "0");
+    }
+
+    #[test]
+    fn comments() {
+        let schema: TableSchema = parse(
+            "CREATE TABLE t ( -- the id\n id INTEGER PRIMARY KEY, /* a (comment, with) parentheses */ \
+             name TEXT -- trailing, comment\n )",
+        );
+        assert_eq!(schema.column_names(), vec!["id", "name"]);
+        assert_eq!(schema.rowid_alias, Some(0));
+    }
+
+    #[test]
+    fn table_constraints() {
+        let schema: TableSchema = parse(
+            "CREATE TABLE t (a INTEGER, b TEXT, c TEXT REFERENCES p(id) ON DELETE SET NULL NOT NULL, \
+             CONSTRAINT pk PRIMARY KEY (a), UNIQUE (b, c), CHECK (length(b) > 0), \
+             FOREIGN KEY (b) REFERENCES p (name) ON UPDATE CASCADE)",
+        );
+        assert_eq!(schema.column_names(), vec!["a", "b", "c"]);
+        assert_eq!(schema.primary_key, vec!["a"]);
+        assert!(schema.columns[0].primary_key);
+        assert!(schema.columns[2].not_null);
+        assert_eq!(schema.rowid_alias, Some(0));
+    }
+
+    #[test]
+    fn rowid_alias_rules() {
+        assert_eq!(
+            parse("CREATE TABLE t (a INT PRIMARY KEY)").rowid_alias,
+            None
+        );
+        assert_eq!(
+            parse("CREATE TABLE t (a INTEGER PRIMARY KEY DESC)").rowid_alias,
+            None
+        );
+        assert_eq!(
+            parse("CREATE TABLE t (a TEXT, b integer, PRIMARY KEY (b ASC))").rowid_alias,
+            Some(1)
+        );
+        assert_eq!(
+            parse("CREATE TABLE t (a INTEGER, b INTEGER, PRIMARY KEY (a, b))").rowid_alias,
+            None
+        );
+    }
+
+    #[test]
+    fn without_rowid_and_strict() {
+        let schema: TableSchema =
+            parse("CREATE TABLE t (k TEXT PRIMARY KEY, v ANY) WITHOUT ROWID, STRICT;");
+        assert!(schema.without_rowid);
+        assert!(schema.strict);
+        assert_eq!(schema.rowid_alias, None);
+
+        let schema: TableSchema = parse("CREATE TABLE t (k INTEGER PRIMARY KEY) STRICT");
+        assert!(!schema.without_rowid);
+        assert!(schema.strict);
+        assert_eq!(schema.rowid_alias, Some(0));
+    }
+
+    #[test]
+    fn generated_columns() {
+        let schema: TableSchema = parse(
+            "CREATE TABLE t (a INTEGER, b INTEGER GENERATED ALWAYS AS (a * (a + 1)) VIRTUAL, \
+             c TEXT AS (upper(a)) STORED, d TEXT)",
+        );
+        let b: &GeneratedColumn = schema.columns[1].generated.as_ref().unwrap();
+        assert_eq!(b.expression, "a * (a + 1)");
+        assert!(!b.stored);
+        assert!(schema.columns[2].generated.as_ref().unwrap().stored);
+        assert!(!schema.columns[1].is_stored());
+        assert!(schema.columns[2].is_stored());
+
+        // The VIRTUAL column is not in the record
+        let mut record: Vec<String> = vec!["1".to_string(), "A".to_string(), "x".to_string()];
+        let (defaulted, extra) = schema.align_record(&mut record);
+        assert_eq!(record, vec!["1", "NULL", "A", "x"]);
+        assert!(defaulted.is_empty() && extra.is_empty());
+    }
+
+    #[test]
+    fn align_record_defaults_and_extra_values() {
+        let schema: TableSchema =
+            parse("CREATE TABLE t (a TEXT, b INTEGER DEFAULT 7, c TEXT DEFAULT 'x')");
+
+        let mut record: Vec<String> = vec!["v".to_string()];
+        let (defaulted, extra) = schema.align_record(&mut record);
+        assert_eq!(record, vec!["v", "7", "x"]);
+        assert_eq!(defaulted, vec!["b", "c"]);
+        assert!(extra.is_empty());
+
+        let mut record: Vec<String> = ["1", "2", "3", "4"].iter().map(|s| s.to_string()).collect();
+        let (defaulted, extra) = schema.align_record(&mut record);
+        assert_eq!(record, vec!["1", "2", "3"]);
+        assert!(defaulted.is_empty());
+        assert_eq!(extra, vec!["4"]);
+    }
+
+    #[test]
+    fn virtual_tables() {
+        let schema: TableSchema = parse(
+            "CREATE VIRTUAL TABLE notes_fts USING fts5(title, body, content='notes', content_rowid='id', tokenize = 'porter unicode61')",
+        );
+        assert_eq!(schema.module.as_deref(), Some("fts5"));
+        assert_eq!(schema.column_names(), vec!["title", "body"]);
+        assert_eq!(schema.module_arguments.len(), 5);
+
+        let schema: TableSchema =
+            parse("CREATE VIRTUAL TABLE places USING rtree(id, minX, maxX, minY, maxY, +label)");
+        assert_eq!(schema.module.as_deref(), Some("rtree"));
+        assert_eq!(schema.columns[0].affinity, Affinity::Integer);
+        assert_eq!(schema.columns[1].affinity, Affinity::Real);
+        assert_eq!(schema.columns[5].name, "label");
+        assert_eq!(schema.columns[5].affinity, Affinity::Blob);
+    }
+
+    #[test]
+    fn invalid_queries() {
+        assert!(TableSchema::parse("CREATE INDEX i ON t(a)").is_err());
+        assert!(TableSchema::parse("CREATE TABLE t AS SELECT * FROM u").is_err());
+        assert!(TableSchema::parse("CREATE TABLE t (a TEXT DEFAULT 'x)").is_err());
+        assert!(TableSchema::parse("CREATE TABLE t (a TEXT CHECK (a > 0)").is_err());
+    }
+}
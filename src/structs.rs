@@ -1,10 +1,11 @@
-use crate::utils::{read_encoded_string, read_varint};
+use crate::utils::{read_encoded_string, read_int, read_varint};
 use base64::{engine::general_purpose, Engine as _};
 use log::debug;
 use serde::{Deserialize, Serialize};
 use std::str::from_utf8;
 
 use crate::constants::*;
+use crate::schema::TableSchema;
 
 pub static mut OVERFLOW_PAGES: Vec<u32> = vec![];
 pub static mut FREEPAGES: Vec<u32> = vec![];
@@ -162,11 +163,113 @@ impl OverflowPage<'_> {
 
 type Row = Vec<String>;
 
+/// Storage class of a value, from its serial type
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StorageClass {
+    Null,
+    Integer,
+    Real,
+    Text,
+    Blob,
+}
+
+impl StorageClass {
+    pub fn from_serial_type(serial_type: i64) -> StorageClass {
+        match serial_type {
+            0 => StorageClass::Null,
+            7 => StorageClass::Real,
+            1..=9 => StorageClass::Integer,
+            n if n % 2 == 0 => StorageClass::Blob,
+            _ => StorageClass::Text,
+        }
+    }
+}
+
+/// Storage class of each value of a record (None if unknown, e.g.: DEFAULT values)
+pub type StorageClasses = Vec<Option<StorageClass>>;
+
+/// Puts the rowid in place of the NULL value of the column aliasing it
+pub fn set_rowid_alias(row: &mut Row, classes: &mut StorageClasses, column: usize, rowid: i64) {
+    match row.get_mut(column) {
+        Some(value) if value == "NULL" => {
+            *value = rowid.to_string();
+            if let Some(class) = classes.get_mut(column) {
+                *class = Some(StorageClass::Integer);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Keeps the storage classes of the values at the given positions
+pub fn select_storage_classes(
+    classes: &[Option<StorageClass>],
+    positions: &[usize],
+) -> StorageClasses {
+    positions
+        .iter()
+        .map(|&i| classes.get(i).cloned().flatten())
+        .collect()
+}
+
+/// Aligns the storage classes of a record as `TableSchema::align_record` does with its values:
+/// generated columns not stored are NULL, the class of DEFAULT values is unknown
+pub fn align_storage_classes(classes: &mut StorageClasses, schema: &TableSchema) {
+    let mut stored = std::mem::take(classes).into_iter();
+    for column in schema.columns.iter() {
+        classes.push(match column.is_stored() {
+            true => stored.next().flatten(),
+            false => Some(StorageClass::Null),
+        });
+    }
+}
+
+/// Where a cell has been read from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Provenance {
+    /// Number of the page containing the cell (None if carved from data without a database header)
+    pub page: Option<u32>,
+    /// Absolute offset of the cell in the file it has been read from (main file or WAL)
+    pub offset: usize,
+    pub from_wal: bool,
+    /// Number (starting from 1) of the WAL frame containing the page
+    pub wal_frame: Option<u32>,
+    /// Absolute offset of the WAL frame (frame header included)
+    pub wal_frame_offset: Option<usize>,
+    /// Bytes of the cell stored in the page (overflow pages excluded)
+    pub raw: Vec<u8>,
+}
+
+/// Storage classes of the values of a record, from the serial types of its header
+fn storage_classes(header: &[u8]) -> StorageClasses {
+    let mut classes: StorageClasses = vec![];
+    let mut i: usize = 0;
+    while i < header.len() {
+        let (serial_type, size): (i64, usize) = read_varint(&header[i..]);
+        classes.push(Some(StorageClass::from_serial_type(serial_type)));
+        i += size;
+    }
+
+    classes
+}
+
 /// Representation of a cell contained in both table and index b-tree leaf pages
 #[derive(Serialize, Deserialize, Clone)]
 pub struct LeafCell {
-    rowid: Option<u32>,
+    rowid: Option<i64>,
     data: Row,
+    /// Columns missing from the record and filled with their DEFAULT value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    defaulted_columns: Option<Vec<String>>,
+    /// Values of the record exceeding the columns of the table (dropped columns or stale schema)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extra_values: Option<Row>,
+    /// Where the cell has been read from, if known
+    #[serde(skip)]
+    provenance: Option<Provenance>,
+    /// Storage classes of the values (blobs are base64 encoded in `data`)
+    #[serde(skip)]
+    storage_classes: StorageClasses,
 }
 
 impl LeafCell {
@@ -181,12 +284,12 @@ impl LeafCell {
         let (l, len_bytes_num): (i64, usize) = read_varint(&bytearray[cell_offset..]);
         debug!("cell length: {} ({})", l, len_bytes_num);
 
-        let mut rowid: Option<u32> = None;
+        let mut rowid: Option<i64> = None;
         cell_offset += len_bytes_num;
 
         if page_type == LEAF_TABLE_BTREE_PAGE || page_type == 0 {
             let (rid, rid_len): (i64, usize) = read_varint(&bytearray[cell_offset..]);
-            rowid = Some(rid as u32);
+            rowid = Some(rid);
             cell_offset += rid_len;
         }
 
@@ -219,65 +322,13 @@ impl LeafCell {
 
             match item_value {
                 0 => cell_record.push(String::from("NULL")),
-                1 => {
-                    cell_record.push(bytearray[start_content].to_string());
-                    start_content += 1;
-                    cur_size += 1;
-                }
-                2 => {
-                    cell_record.push(
-                        i32::from_be_bytes([
-                            0,
-                            0,
-                            bytearray[start_content],
-                            bytearray[start_content + 1],
-                        ])
-                        .to_string(),
-                    );
-                    start_content += 2;
-                    cur_size += 2;
-                }
-                3 => {
-                    cell_record.push(
-                        i32::from_be_bytes([
-                            0,
-                            bytearray[start_content],
-                            bytearray[start_content + 1],
-                            bytearray[start_content + 2],
-                        ])
-                        .to_string(),
-                    );
-                    start_content += 3;
-                    cur_size += 3;
-                }
-                4 => {
-                    cell_record.push(
-                        i32::from_be_bytes(
-                            bytearray[start_content..start_content + 4]
-                                .try_into()
-                                .expect("error"),
-                        )
-                        .to_string(),
-                    );
-                    start_content += 4;
-                    cur_size += 4;
-                }
-                5 => {
+                n @ 1..=5 => {
+                    let size: usize = [1, 2, 3, 4, 6][n as usize - 1];
                     cell_record.push(
-                        i64::from_be_bytes([
-                            0,
-                            0,
-                            bytearray[start_content],
-                            bytearray[start_content + 1],
-                            bytearray[start_content + 2],
-                            bytearray[start_content + 3],
-                            bytearray[start_content + 4],
-                            bytearray[start_content + 5],
-                        ])
-                        .to_string(),
+                        read_int(&bytearray[start_content..start_content + size]).to_string(),
                     );
-                    start_content += 6;
-                    cur_size += 6;
+                    start_content += size;
+                    cur_size += size as u32;
                 }
                 6 => {
                     cell_record.push(
@@ -540,9 +591,24 @@ impl LeafCell {
         LeafCell {
             rowid,
             data: cell_record,
+            defaulted_columns: None,
+            extra_values: None,
+            provenance: None,
+            storage_classes: storage_classes(header),
         }
     }
 
+    /// Parses a cell stored entirely in a page of the given size, e.g.: carved from data without
+    /// a database header (the page size of the main file is not used)
+    pub fn new_in_page(
+        bytearray: &[u8],
+        offset: usize,
+        page_type: u8,
+        page_size: usize,
+    ) -> LeafCell {
+        LeafCell::new(bytearray, offset, page_type, Some(page_size as u32))
+    }
+
     pub fn new_wal(
         bytearray: &[u8],
         offset: usize,
@@ -553,12 +619,12 @@ impl LeafCell {
         let (l, len_bytes_num): (i64, usize) = read_varint(&bytearray[cell_offset..]);
         debug!("cell length: {} ({})", l, len_bytes_num);
 
-        let mut rowid: Option<u32> = None;
+        let mut rowid: Option<i64> = None;
         cell_offset += len_bytes_num;
 
         if page_type == LEAF_TABLE_BTREE_PAGE || page_type == 0 {
             let (rid, rid_len): (i64, usize) = read_varint(&bytearray[cell_offset..]);
-            rowid = Some(rid as u32);
+            rowid = Some(rid);
             cell_offset += rid_len;
         }
 
@@ -592,65 +658,13 @@ impl LeafCell {
 
             match item_value {
                 0 => cell_record.push(String::from("NULL")),
-                1 => {
-                    cell_record.push(bytearray[start_content].to_string());
-                    start_content += 1;
-                    cur_size += 1;
-                }
-                2 => {
-                    cell_record.push(
-                        i32::from_be_bytes([
-                            0,
-                            0,
-                            bytearray[start_content],
-                            bytearray[start_content + 1],
-                        ])
-                        .to_string(),
-                    );
-                    start_content += 2;
-                    cur_size += 2;
-                }
-                3 => {
-                    cell_record.push(
-                        i32::from_be_bytes([
-                            0,
-                            bytearray[start_content],
-                            bytearray[start_content + 1],
-                            bytearray[start_content + 2],
-                        ])
-                        .to_string(),
-                    );
-                    start_content += 3;
-                    cur_size += 3;
-                }
-                4 => {
+                n @ 1..=5 => {
+                    let size: usize = [1, 2, 3, 4, 6][n as usize - 1];
                     cell_record.push(
-                        i32::from_be_bytes(
-                            bytearray[start_content..start_content + 4]
-                                .try_into()
-                                .expect("error"),
-                        )
-                        .to_string(),
+                        read_int(&bytearray[start_content..start_content + size]).to_string(),
                     );
-                    start_content += 4;
-                    cur_size += 4;
-                }
-                5 => {
-                    cell_record.push(
-                        i64::from_be_bytes([
-                            0,
-                            0,
-                            bytearray[start_content],
-                            bytearray[start_content + 1],
-                            bytearray[start_content + 2],
-                            bytearray[start_content + 3],
-                            bytearray[start_content + 4],
-                            bytearray[start_content + 5],
-                        ])
-                        .to_string(),
-                    );
-                    start_content += 6;
-                    cur_size += 6;
+                    start_content += size;
+                    cur_size += size as u32;
                 }
                 6 => {
                     cell_record.push(
@@ -941,10 +955,50 @@ impl LeafCell {
         LeafCell {
             rowid,
             data: cell_record,
+            defaulted_columns: None,
+            extra_values: None,
+            provenance: None,
+            storage_classes: storage_classes(header),
         }
     }
 
-    pub fn rowid(&self) -> Option<u32> {
+    /// Records the page, the file offset and the bytes of the cell
+    pub fn set_location(&mut self, page: Option<u32>, offset: usize, from_wal: bool, raw: &[u8]) {
+        self.provenance = Some(Provenance {
+            page,
+            offset,
+            from_wal,
+            wal_frame: None,
+            wal_frame_offset: None,
+            raw: raw.to_vec(),
+        });
+    }
+
+    /// Records the WAL frame containing the cell
+    pub fn set_wal_frame(&mut self, frame: u32, frame_offset: usize) {
+        if let Some(provenance) = self.provenance.as_mut() {
+            provenance.wal_frame = Some(frame);
+            provenance.wal_frame_offset = Some(frame_offset);
+        }
+    }
+
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+
+    /// Creates a cell from already decoded values (e.g.: rebuilt from shadow tables)
+    pub fn from_values(rowid: Option<i64>, data: Row) -> LeafCell {
+        LeafCell {
+            rowid,
+            data,
+            defaulted_columns: None,
+            extra_values: None,
+            provenance: None,
+            storage_classes: vec![],
+        }
+    }
+
+    pub fn rowid(&self) -> Option<i64> {
         self.rowid
     }
 
@@ -952,13 +1006,87 @@ impl LeafCell {
         self.data.clone()
     }
 
-    pub fn to_csv(&self) -> String {
-        let mut csv_string = String::from(format!("{};", self.rowid.unwrap()));
-        self.data
+    /// Values of the record, without copying them
+    pub fn values(&self) -> &[String] {
+        &self.data
+    }
+
+    pub fn defaulted_columns(&self) -> Option<&[String]> {
+        self.defaulted_columns.as_deref()
+    }
+
+    pub fn extra_values(&self) -> Option<&[String]> {
+        self.extra_values.as_deref()
+    }
+
+    pub fn storage_classes(&self) -> &[Option<StorageClass>] {
+        &self.storage_classes
+    }
+
+    /// Positions of the values stored as blobs
+    pub fn blob_positions(&self) -> Vec<usize> {
+        self.storage_classes
             .iter()
-            .for_each(|item| csv_string.push_str(format!("{};", item).as_str()));
+            .enumerate()
+            .filter(|(_, c)| **c == Some(StorageClass::Blob))
+            .map(|(i, _)| i)
+            .collect()
+    }
 
-        csv_string
+    /// Removes the first `count` values of the record
+    pub fn drop_values(&mut self, count: usize) {
+        self.data.drain(..count.min(self.data.len()));
+        self.storage_classes
+            .drain(..count.min(self.storage_classes.len()));
+    }
+
+    /// Keeps only the values at the given positions, in that order
+    pub fn select_values(&mut self, positions: &[usize]) {
+        self.data = positions
+            .iter()
+            .map(|&i| match self.data.get(i) {
+                Some(v) => v.to_string(),
+                None => String::from("NULL"),
+            })
+            .collect();
+        self.storage_classes = select_storage_classes(&self.storage_classes, positions);
+    }
+
+    /// Pads or trims the record to match the columns of the table, keeping track of the changes
+    pub fn align(&mut self, schema: &TableSchema) {
+        let (defaulted, extra): (Vec<String>, Row) = schema.align_record(&mut self.data);
+        if !self.storage_classes.is_empty() {
+            align_storage_classes(&mut self.storage_classes, schema);
+        }
+        if !defaulted.is_empty() {
+            self.defaulted_columns = Some(defaulted);
+        }
+        if !extra.is_empty() {
+            debug!(
+                "{:?} - {} values exceed table columns",
+                self.rowid,
+                extra.len()
+            );
+            self.extra_values = Some(extra);
+        }
+    }
+
+    /// Replaces the NULL stored in the record for an `INTEGER PRIMARY KEY` column with the rowid
+    pub fn set_rowid_alias(&mut self, column: usize) {
+        if let Some(rowid) = self.rowid {
+            set_rowid_alias(&mut self.data, &mut self.storage_classes, column, rowid);
+        }
+    }
+
+    /// Rowid (empty if unknown) followed by the values of the record
+    pub fn to_csv_record(&self) -> Vec<String> {
+        let mut record: Vec<String> = vec![match self.rowid {
+            Some(r) => r.to_string(),
+            None => String::new(),
+        }];
+        record.extend(self.data.iter().cloned());
+
+        record
     }
 }
 
@@ -992,7 +1120,7 @@ pub struct InteriorTableCell {
     /// Page number of the following table in the tree
     left_pointer: u32,
     /// Max value for rowid in following page
-    key: u32,
+    key: i64,
 }
 
 impl InteriorTableCell {
@@ -1003,13 +1131,17 @@ impl InteriorTableCell {
             left_pointer: u32::from_be_bytes(
                 bytearray[offset..offset + 4].try_into().expect("error"),
             ),
-            key: key as u32,
+            key,
         }
     }
 
     pub fn left_pointer(&self) -> u32 {
         self.left_pointer
     }
+
+    pub fn key(&self) -> i64 {
+        self.key
+    }
 }
 
 impl TryFrom<&Cell> for InteriorTableCell {
@@ -1102,6 +1234,29 @@ impl PageHeader {
     pub fn rightmost_ptr(&self) -> Option<u32> {
         self.rightmost_ptr
     }
+
+    pub fn first_freeblock_offset(&self) -> u32 {
+        self.first_freeblock_offset
+    }
+
+    pub fn cell_content_offset(&self) -> u32 {
+        self.cell_content_offset
+    }
+
+    pub fn fragmented_free_bytes(&self) -> u32 {
+        self.fragmented_free_bytes
+    }
+
+    /// Name of the type of page
+    pub fn page_type_name(&self) -> &'static str {
+        match self.page_type {
+            INTERIOR_INDEX_BTREE_PAGE => "interior index",
+            INTERIOR_TABLE_BTREE_PAGE => "interior table",
+            LEAF_INDEX_BTREE_PAGE => "leaf index",
+            LEAF_TABLE_BTREE_PAGE => "leaf table",
+            _ => "unknown",
+        }
+    }
 }
 
 impl std::fmt::Debug for PageHeader {
@@ -1151,7 +1306,7 @@ impl Page {
         debug!("Page: {} (0x{:02x?})", page_num + 1, page_offset);
 
         let mut live_cells: Vec<Cell> = vec![];
-        let mut deleted_cells: Vec<Cell> = vec![];
+        let deleted_cells: Vec<Cell> = vec![];
         let mut deleted_cells_count: u32 = 0;
 
         let header: PageHeader;
@@ -1242,7 +1397,7 @@ impl Page {
             for &cell_offset in cell_array.iter().take(header.cell_count as usize) {
                 let cell_address: usize = cell_offset + page_offset;
                 debug!("cell address: 0x{:02x?}", cell_address);
-                let cell: LeafCell = Self::parse_leaf_cell(
+                let mut cell: LeafCell = Self::parse_leaf_cell(
                     bytearray,
                     header.page_type,
                     cell_address,
@@ -1250,6 +1405,19 @@ impl Page {
                     //reserved_space,
                     is_wal,
                 );
+                let cell_len: usize = Self::cell_len(
+                    bytearray,
+                    cell_address,
+                    header.page_type,
+                    Self::usable_page_size(),
+                );
+                let cell_end: usize = (cell_address + cell_len).min(bytearray.len());
+                cell.set_location(
+                    Some(page_num + 1),
+                    cell_address,
+                    is_wal,
+                    &bytearray[cell_address..cell_end],
+                );
                 live_cells.push(Cell::LC(cell));
             }
 
@@ -1353,30 +1521,15 @@ impl Page {
         let (cell_size, _) = read_varint(&bytearray[cell_address..]);
 
         debug!("CELL SIZE: {}", cell_size);
-        let usable_page_size: u32;
-        unsafe {
-            usable_page_size = PAGE_SIZE as u32 - RESERVED_SPACE as u32;
-        }
-        debug!("Usable page size: {}", usable_page_size);
 
         // Check if cell contains whole data or there is an overflow
-        if cell_size as u32 <= usable_page_size - 35 {
-            cell = LeafCell::new(&bytearray, cell_address, page_type, None);
-        } else {
-            let m: u32 = ((usable_page_size - 12) * 32 / 255) - 23;
-            let k: u32 = m + ((cell_size as u32 - m) % (usable_page_size - 4));
-            if k <= usable_page_size - 35 {
-                debug!("K = {}; M = {}", k, m);
-                if is_wal {
-                    cell = LeafCell::new_wal(&bytearray, cell_address, page_type, Some(k));
-                } else {
-                    cell = LeafCell::new(&bytearray, cell_address, page_type, Some(k));
-                }
-            } else {
+        match Self::local_payload_size(cell_size as u32, page_type, Self::usable_page_size()) {
+            None => cell = LeafCell::new(bytearray, cell_address, page_type, None),
+            Some(local) => {
                 if is_wal {
-                    cell = LeafCell::new_wal(&bytearray, cell_address, page_type, Some(m));
+                    cell = LeafCell::new_wal(bytearray, cell_address, page_type, Some(local));
                 } else {
-                    cell = LeafCell::new(&bytearray, cell_address, page_type, Some(m));
+                    cell = LeafCell::new(bytearray, cell_address, page_type, Some(local));
                 }
             }
         }
@@ -1384,6 +1537,79 @@ impl Page {
         cell
     }
 
+    /// Page size of the main file, without the reserved space
+    fn usable_page_size() -> u32 {
+        unsafe { PAGE_SIZE as u32 - RESERVED_SPACE as u32 }
+    }
+
+    /// Bytes of the payload stored in the page when it overflows, `None` if it does not.
+    /// Index cells overflow sooner than table leaf cells
+    fn local_payload_size(payload_size: u32, page_type: u8, usable_page_size: u32) -> Option<u32> {
+        debug!("Usable page size: {}", usable_page_size);
+        let max_local: u32 = match page_type {
+            LEAF_INDEX_BTREE_PAGE | INTERIOR_INDEX_BTREE_PAGE => {
+                ((usable_page_size - 12) * 64 / 255) - 23
+            }
+            _ => usable_page_size - 35,
+        };
+
+        if payload_size <= max_local {
+            return None;
+        }
+        let m: u32 = ((usable_page_size - 12) * 32 / 255) - 23;
+        let k: u32 = m + ((payload_size - m) % (usable_page_size - 4));
+        debug!("K = {}; M = {}", k, m);
+        match k <= max_local {
+            true => Some(k),
+            false => Some(m),
+        }
+    }
+
+    /// Bytes of a cell stored in the page, by type of page: the left child pointer (interior
+    /// pages), the payload size (all but interior table pages), the rowid (table pages), the local
+    /// payload and the number of the first overflow page, if any
+    fn cell_len(
+        bytearray: &[u8],
+        cell_address: usize,
+        page_type: u8,
+        usable_page_size: u32,
+    ) -> usize {
+        let varint = |offset: usize| read_varint(&bytearray[offset.min(bytearray.len())..]);
+        match page_type {
+            INTERIOR_TABLE_BTREE_PAGE => 4 + varint(cell_address + 4).1,
+            LEAF_INDEX_BTREE_PAGE | INTERIOR_INDEX_BTREE_PAGE => {
+                let child_len: usize = match page_type {
+                    INTERIOR_INDEX_BTREE_PAGE => 4,
+                    _ => 0,
+                };
+                let (payload_size, payload_size_len): (i64, usize) =
+                    varint(cell_address + child_len);
+                let local: usize = match Self::local_payload_size(
+                    payload_size as u32,
+                    page_type,
+                    usable_page_size,
+                ) {
+                    None => payload_size as usize,
+                    Some(l) => l as usize + 4,
+                };
+                child_len + payload_size_len + local
+            }
+            _ => {
+                let (payload_size, payload_size_len): (i64, usize) = varint(cell_address);
+                let (_, rowid_len): (i64, usize) = varint(cell_address + payload_size_len);
+                let local: usize = match Self::local_payload_size(
+                    payload_size as u32,
+                    page_type,
+                    usable_page_size,
+                ) {
+                    None => payload_size as usize,
+                    Some(l) => l as usize + 4,
+                };
+                payload_size_len + rowid_len + local
+            }
+        }
+    }
+
     pub fn number(&self) -> u32 {
         self.number
     }
@@ -1392,10 +1618,28 @@ impl Page {
         self.header.clone()
     }
 
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Entries of the cell pointer array beyond the cell count
+    pub fn deleted_cells_count(&self) -> u32 {
+        self.deleted_cells_count
+    }
+
     pub fn live_cells(&self) -> Vec<Cell> {
         self.live_cells.clone()
     }
 
+    /// Records the WAL frame containing the page in its cells
+    pub fn set_wal_frame(&mut self, frame: u32, frame_offset: usize) {
+        self.live_cells.iter_mut().for_each(|cell| {
+            if let Cell::LC(c) = cell {
+                c.set_wal_frame(frame, frame_offset);
+            }
+        });
+    }
+
     pub fn is_internal_table_page(&self) -> bool {
         self.header.page_type == INTERIOR_TABLE_BTREE_PAGE
     }
@@ -1450,7 +1694,7 @@ impl Page {
         }
     }
 
-    pub fn contains_rowid(&self, rowid: Option<u32>) -> bool {
+    pub fn contains_rowid(&self, rowid: Option<i64>) -> bool {
         let mut res: bool = false;
         for cell in self.live_cells.iter(){
             if LeafCell::try_from(cell).unwrap().rowid == rowid {
@@ -1462,8 +1706,8 @@ impl Page {
         res
     }*/
 
-    pub fn get_all_rowids(&self) -> Vec<u32> {
-        let mut rowids: Vec<u32> = vec![];
+    pub fn get_all_rowids(&self) -> Vec<i64> {
+        let mut rowids: Vec<i64> = vec![];
 
         for cell in self.live_cells.iter() {
             rowids.push(LeafCell::try_from(cell).unwrap().rowid.unwrap())
@@ -1472,7 +1716,7 @@ impl Page {
         rowids
     }
 
-    pub fn get_cell_by_rowid(&self, rowid: u32) -> Option<LeafCell> {
+    pub fn get_cell_by_rowid(&self, rowid: i64) -> Option<LeafCell> {
         for cell in self.live_cells.iter() {
             let leafcell = LeafCell::try_from(cell).unwrap();
             if leafcell.rowid.unwrap() == rowid {
@@ -1516,3 +1760,104 @@ impl std::fmt::Debug for Page {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{sqlite3, test_dir};
+    use crate::utils::write_varint;
+    use std::path::PathBuf;
+
+    /// Bytes of a cell: the given prefix, then a payload of `payload_size` bytes
+    fn cell(prefix: Vec<Vec<u8>>, payload_size: usize) -> Vec<u8> {
+        let mut bytes: Vec<u8> = prefix.concat();
+        bytes.extend(vec![0; payload_size]);
+        bytes
+    }
+
+    #[test]
+    fn cell_len_by_page_type() {
+        let child: Vec<u8> = vec![0, 0, 0, 7];
+        let cases: Vec<(u8, Vec<u8>, usize)> = vec![
+            (
+                LEAF_TABLE_BTREE_PAGE,
+                cell(vec![write_varint(10), write_varint(300)], 10),
+                13,
+            ),
+            (
+                INTERIOR_TABLE_BTREE_PAGE,
+                cell(vec![child.clone(), write_varint(300)], 0),
+                6,
+            ),
+            (LEAF_INDEX_BTREE_PAGE, cell(vec![write_varint(10)], 10), 11),
+            (
+                INTERIOR_INDEX_BTREE_PAGE,
+                cell(vec![child.clone(), write_varint(10)], 10),
+                15,
+            ),
+            // Stored entirely in a table leaf page, overflowing in an index page
+            (
+                LEAF_TABLE_BTREE_PAGE,
+                cell(vec![write_varint(2000), write_varint(1)], 2000),
+                2003,
+            ),
+            (
+                LEAF_INDEX_BTREE_PAGE,
+                cell(vec![write_varint(2000)], 2000),
+                2 + 489 + 4,
+            ),
+            (
+                INTERIOR_INDEX_BTREE_PAGE,
+                cell(vec![child, write_varint(2000)], 2000),
+                4 + 2 + 489 + 4,
+            ),
+        ];
+        for (page_type, bytes, len) in cases {
+            assert_eq!(
+                Page::cell_len(&bytes, 0, page_type, 4096),
+                len,
+                "{}",
+                page_type
+            );
+        }
+    }
+
+    #[test]
+    fn provenance_of_live_cells() {
+        let dir: PathBuf = test_dir("provenance");
+        let db: PathBuf = dir.join("test.db");
+        let rows: String = (1..=20)
+            .map(|i| format!("INSERT INTO t VALUES ({}, '{}');", i, "x".repeat(i * 7)))
+            .collect();
+        let sql: String = format!("PRAGMA page_size = 4096; CREATE TABLE t (a, b); {}", rows);
+        sqlite3(&db, &sql);
+        let bytearray: Vec<u8> = std::fs::read(&db).unwrap();
+        unsafe {
+            PAGE_SIZE = 4096;
+            RESERVED_SPACE = 0;
+        }
+
+        let page: Page = Page::new(&bytearray, 4096, 1, false);
+        let mut cells: Vec<Provenance> = page
+            .live_cells()
+            .iter()
+            .filter_map(|c| match c {
+                Cell::LC(c) => c.provenance().cloned(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cells.len(), 20);
+        cells.sort_by_key(|p| p.offset);
+        // Cells are written back to back up to the end of the page
+        for (cell, next) in cells.iter().zip(cells.iter().skip(1)) {
+            assert_eq!(cell.page, Some(2));
+            assert!(!cell.from_wal);
+            assert_eq!(cell.offset + cell.raw.len(), next.offset);
+            assert_eq!(cell.raw, &bytearray[cell.offset..next.offset]);
+        }
+        let last: &Provenance = cells.last().unwrap();
+        assert_eq!(last.offset + last.raw.len(), 2 * 4096);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
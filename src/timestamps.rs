@@ -0,0 +1,411 @@
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::db::{DataBase, RowStatus, Table};
+
+const NANOS_PER_SECOND: i128 = 1_000_000_000;
+
+/// Seconds between 1601-01-01 (Windows and WebKit epoch) and 1970-01-01
+const EPOCH_1601_OFFSET: i128 = 11_644_473_600;
+
+/// Seconds between 1970-01-01 and 2001-01-01 (Cocoa epoch)
+const EPOCH_2001_OFFSET: i128 = 978_307_200;
+
+/// Julian day of 1970-01-01
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2_440_587.5;
+
+/* Dates accepted when guessing the format of a value: from 1995-01-01 to 2040-01-01 */
+const PLAUSIBLE_FROM: i128 = 788_918_400 * NANOS_PER_SECOND;
+const PLAUSIBLE_TO: i128 = 2_208_988_800 * NANOS_PER_SECOND;
+
+/// Smaller values (counters, flags, ...) are never guessed as timestamps, except Julian days
+/// (which are all below it, so that they cannot be mistaken for early Cocoa dates)
+const MIN_MAGNITUDE: f64 = 1e7;
+
+/// Ratio of values of a column which must decode to plausible dates
+const DETECTION_THRESHOLD: f64 = 0.9;
+
+/// Same as `DETECTION_THRESHOLD`, for columns whose name suggests a timestamp
+const NAMED_DETECTION_THRESHOLD: f64 = 0.5;
+
+/// Whether a timestamp (in nanoseconds) falls between `PLAUSIBLE_FROM` and `PLAUSIBLE_TO`
+pub fn is_plausible(nanos: i128) -> bool {
+    (PLAUSIBLE_FROM..PLAUSIBLE_TO).contains(&nanos)
+}
+
+/// Format of a timestamp
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Epoch {
+    /// Seconds since 1970-01-01
+    UnixSeconds,
+    UnixMillis,
+    UnixMicros,
+    UnixNanos,
+    /// Seconds since 2001-01-01 (Mac absolute time)
+    Cocoa,
+    /// Microseconds since 1601-01-01 (Chrome)
+    WebKit,
+    /// 100 nanoseconds intervals since 1601-01-01 (Windows)
+    FileTime,
+    /// Days since noon of 4714-11-24 BC
+    JulianDay,
+}
+
+impl Epoch {
+    /// Formats tried when guessing, in order of preference
+    pub const ALL: [Epoch; 8] = [
+        Epoch::UnixSeconds,
+        Epoch::UnixMillis,
+        Epoch::UnixMicros,
+        Epoch::UnixNanos,
+        Epoch::Cocoa,
+        Epoch::WebKit,
+        Epoch::FileTime,
+        Epoch::JulianDay,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Epoch::UnixSeconds => "unix_seconds",
+            Epoch::UnixMillis => "unix_millis",
+            Epoch::UnixMicros => "unix_micros",
+            Epoch::UnixNanos => "unix_nanos",
+            Epoch::Cocoa => "cocoa",
+            Epoch::WebKit => "web_kit",
+            Epoch::FileTime => "file_time",
+            Epoch::JulianDay => "julian_day",
+        }
+    }
+
+    /// Converts a value (as read from the record) to nanoseconds since 1970-01-01
+    pub fn to_unix_nanos(self, value: &str) -> Option<i128> {
+        match value.parse::<i64>() {
+            Ok(i) => {
+                let i: i128 = i as i128;
+                match self {
+                    Epoch::UnixSeconds => Some(i * NANOS_PER_SECOND),
+                    Epoch::UnixMillis => Some(i * 1_000_000),
+                    Epoch::UnixMicros => Some(i * 1_000),
+                    Epoch::UnixNanos => Some(i),
+                    Epoch::Cocoa => Some((i + EPOCH_2001_OFFSET) * NANOS_PER_SECOND),
+                    Epoch::WebKit => Some((i - EPOCH_1601_OFFSET * 1_000_000) * 1_000),
+                    Epoch::FileTime => Some((i - EPOCH_1601_OFFSET * 10_000_000) * 100),
+                    Epoch::JulianDay => self.real_to_unix_nanos(i as f64),
+                }
+            }
+            Err(_) => match value.parse::<f64>() {
+                Ok(f) if f.is_finite() => self.real_to_unix_nanos(f),
+                _ => None,
+            },
+        }
+    }
+
+    fn real_to_unix_nanos(&self, value: f64) -> Option<i128> {
+        let seconds: f64 = match self {
+            Epoch::UnixSeconds => value,
+            Epoch::UnixMillis => value / 1e3,
+            Epoch::UnixMicros => value / 1e6,
+            Epoch::UnixNanos => value / 1e9,
+            Epoch::Cocoa => value + EPOCH_2001_OFFSET as f64,
+            Epoch::WebKit => value / 1e6 - EPOCH_1601_OFFSET as f64,
+            Epoch::FileTime => value / 1e7 - EPOCH_1601_OFFSET as f64,
+            Epoch::JulianDay => (value - UNIX_EPOCH_JULIAN_DAY) * 86_400.0,
+        };
+        // Beyond year 9999 dates cannot be formatted
+        match seconds.abs() < 2.5e11 {
+            true => Some((seconds * 1e9) as i128),
+            false => None,
+        }
+    }
+
+    /// Formats under which the value decodes to a plausible date.
+    /// Julian days are only guessed for values with a fractional part, since 7-digit
+    /// integers (identifiers, counters, ...) would all pass as recent dates
+    pub fn guess(value: &str) -> Vec<Epoch> {
+        let number: f64 = value.parse::<f64>().unwrap_or(0.0);
+        Epoch::ALL
+            .iter()
+            .filter(|epoch| match epoch {
+                Epoch::JulianDay => number.fract() != 0.0,
+                _ => number.abs() >= MIN_MAGNITUDE,
+            })
+            .filter(|epoch| match epoch.to_unix_nanos(value) {
+                Some(n) => is_plausible(n),
+                None => false,
+            })
+            .copied()
+            .collect()
+    }
+
+    /// ISO-8601 representation (UTC) of a value, if it can be converted
+    pub fn decode(&self, value: &str) -> Option<String> {
+        self.to_unix_nanos(value).and_then(to_iso8601)
+    }
+}
+
+/// Name of the field holding the ISO-8601 representation of a timestamp column
+pub fn iso8601_field(column: &str) -> String {
+    format!("{}_iso8601", column)
+}
+
+/// Column names suggesting a timestamp: `*date*`, `*time*` and `*_at`
+pub fn is_timestamp_name(column: &str) -> bool {
+    let column: String = column.to_lowercase();
+    column.contains("date") || column.contains("time") || column.ends_with("_at")
+}
+
+/// ISO-8601 representation (UTC) of a timestamp in nanoseconds
+pub fn to_iso8601(nanos: i128) -> Option<String> {
+    OffsetDateTime::from_unix_timestamp_nanos(nanos)
+        .ok()?
+        .format(&Rfc3339)
+        .ok()
+}
+
+/// Formats of the timestamp columns, by table and column name.
+/// A `null` format marks a column which is not a timestamp.
+pub type TimestampConfig = HashMap<String, HashMap<String, Option<Epoch>>>;
+
+/// Reads the JSON configuration of timestamp columns, e.g.:
+/// `{"message": {"date": "cocoa", "flags": null}}`
+pub fn load_config(path: &str) -> Result<TimestampConfig, &'static str> {
+    let content: String = read_to_string(path).map_err(|_| "Cannot read timestamps config")?;
+    serde_json::from_str(&content).map_err(|_| "Invalid timestamps config")
+}
+
+/// Finds the timestamp columns of a table: the ones listed in the configuration
+/// and the ones whose numeric values mostly decode to plausible dates in the same format
+/// (a lower ratio is enough if the name of the column suggests a timestamp)
+pub fn timestamp_columns(table: &Table, config: Option<&TimestampConfig>) -> Vec<(usize, Epoch)> {
+    let overrides: Option<&HashMap<String, Option<Epoch>>> =
+        config.and_then(|c| c.get(&table.name));
+    let rows: Vec<&[String]> = table.iter_keyed_rows().map(|r| r.values).collect();
+
+    let mut columns: Vec<(usize, Epoch)> = vec![];
+    for (i, column) in table.columns.iter().enumerate() {
+        match overrides.and_then(|o| o.get(column)) {
+            Some(Some(epoch)) => {
+                columns.push((i, *epoch));
+                continue;
+            }
+            Some(None) => continue,
+            None => (),
+        }
+        // The rowid is never a timestamp
+        if table.schema.rowid_alias == Some(i) {
+            continue;
+        }
+
+        let mut numeric: usize = 0;
+        let mut votes: HashMap<Epoch, usize> = HashMap::new();
+        for row in rows.iter() {
+            let value: &str = match row.get(i) {
+                Some(v) if v != "NULL" => v,
+                _ => continue,
+            };
+            if value.parse::<f64>().is_err() {
+                continue;
+            }
+            numeric += 1;
+            for epoch in Epoch::guess(value).into_iter() {
+                *votes.entry(epoch).or_default() += 1;
+            }
+        }
+        if numeric == 0 {
+            continue;
+        }
+
+        let threshold: f64 = match is_timestamp_name(column) {
+            true => NAMED_DETECTION_THRESHOLD,
+            false => DETECTION_THRESHOLD,
+        };
+        // Reversed, so that the first format wins among the ones with the same votes
+        let best: Option<Epoch> = Epoch::ALL
+            .iter()
+            .rev()
+            .filter(|e| *votes.get(e).unwrap_or(&0) as f64 >= numeric as f64 * threshold)
+            .max_by_key(|e| votes.get(e).unwrap_or(&0))
+            .copied();
+        if let Some(epoch) = best {
+            debug!("{}.{}: {} timestamps", table.name, column, epoch.name());
+            columns.push((i, epoch));
+        }
+    }
+
+    columns
+}
+
+/// An event of the timeline: a timestamp found in a row
+pub struct TimelineEvent {
+    pub nanos: i128,
+    pub table: String,
+    pub column: String,
+    pub rowid: Option<i64>,
+    pub status: RowStatus,
+    pub epoch: Epoch,
+    pub raw_value: String,
+    /// Other values of the row
+    pub summary: String,
+}
+
+/// Length of the summary of the row in timeline events
+const SUMMARY_LEN: usize = 200;
+
+/// Collects the timestamps of all the tables, sorted by time: live rows, rows found in the WAL
+/// and, if recovered, rows of dropped tables and freelist pages (cells deleted in freeblocks of
+/// live pages are not recovered). Values outside of the plausible dates are skipped
+pub fn build_timeline(db: &DataBase, config: Option<&TimestampConfig>) -> Vec<TimelineEvent> {
+    let mut events: Vec<TimelineEvent> = vec![];
+    let mut implausible: usize = 0;
+
+    for table in db.tables().iter() {
+        let columns: Vec<(usize, Epoch)> = timestamp_columns(table, config);
+        if columns.is_empty() {
+            continue;
+        }
+        info!(
+            "{}: timestamp columns {:?}",
+            table.name,
+            columns
+                .iter()
+                .map(|(i, e)| format!("{} ({})", table.columns[*i], e.name()))
+                .collect::<Vec<String>>()
+        );
+
+        for row in table.keyed_rows().iter() {
+            let mut summary: String = row
+                .values
+                .iter()
+                .enumerate()
+                .filter(|(i, v)| *v != "NULL" && !columns.iter().any(|(c, _)| c == i))
+                .map(|(i, v)| format!("{}={}", table.columns.get(i).unwrap_or(&String::new()), v))
+                .collect::<Vec<String>>()
+                .join(" ");
+            if summary.chars().count() > SUMMARY_LEN {
+                summary = summary.chars().take(SUMMARY_LEN).collect::<String>() + "...";
+            }
+
+            for (i, epoch) in columns.iter() {
+                let raw_value: &String = match row.values.get(*i) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                match epoch.to_unix_nanos(raw_value) {
+                    Some(nanos) if !is_plausible(nanos) => implausible += 1,
+                    Some(nanos) => events.push(TimelineEvent {
+                        nanos,
+                        table: table.name.to_string(),
+                        column: table.columns[*i].to_string(),
+                        rowid: row.rowid,
+                        status: row.status,
+                        epoch: *epoch,
+                        raw_value: raw_value.to_string(),
+                        summary: summary.to_string(),
+                    }),
+                    None => (),
+                }
+            }
+        }
+    }
+
+    if implausible > 0 {
+        info!(
+            "{} timestamps outside of plausible dates skipped",
+            implausible
+        );
+    }
+
+    events.sort_by_key(|e| e.nanos);
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::TableSchema;
+    use crate::structs::LeafCell;
+
+    #[test]
+    fn known_value_of_each_epoch() {
+        // 2020-09-13T12:26:40Z in every format
+        let values: Vec<(Epoch, &str)> = vec![
+            (Epoch::UnixSeconds, "1600000000"),
+            (Epoch::UnixMillis, "1600000000000"),
+            (Epoch::UnixMicros, "1600000000000000"),
+            (Epoch::UnixNanos, "1600000000000000000"),
+            (Epoch::Cocoa, "621692800"),
+            (Epoch::WebKit, "13244473600000000"),
+            (Epoch::FileTime, "132444736000000000"),
+        ];
+        for (epoch, value) in values.into_iter() {
+            assert_eq!(
+                epoch.decode(value).as_deref(),
+                Some("2020-09-13T12:26:40Z"),
+                "{}",
+                epoch.name()
+            );
+        }
+        assert_eq!(
+            Epoch::JulianDay.decode("2459000.5").as_deref(),
+            Some("2020-05-31T00:00:00Z")
+        );
+        assert_eq!(
+            Epoch::UnixSeconds.decode("1.5").as_deref(),
+            Some("1970-01-01T00:00:01.5Z")
+        );
+        assert_eq!(Epoch::UnixSeconds.decode("text"), None);
+        assert_eq!(Epoch::UnixSeconds.decode("1e300"), None);
+    }
+
+    #[test]
+    fn guess_rejects_small_counters() {
+        assert!(Epoch::guess("42").is_empty());
+        assert!(Epoch::guess("1234567").is_empty());
+        assert!(Epoch::guess("2459000").is_empty());
+        assert_eq!(Epoch::guess("2459000.5"), vec![Epoch::JulianDay]);
+        assert!(Epoch::guess("1600000000").contains(&Epoch::UnixSeconds));
+        assert!(Epoch::guess("621692800").contains(&Epoch::Cocoa));
+        assert!(!is_plausible(
+            Epoch::UnixSeconds.to_unix_nanos("1").unwrap()
+        ));
+    }
+
+    #[test]
+    fn columns_detected_by_name() {
+        let schema: TableSchema = TableSchema::parse(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, created_at INTEGER, total INTEGER)",
+        )
+        .unwrap();
+        // 6 of 10 values are plausible dates: enough for a timestamp name only
+        let rows: Vec<LeafCell> = (0..10)
+            .map(|i| {
+                let value: String = match i < 6 {
+                    true => (1_600_000_000 + i).to_string(),
+                    false => i.to_string(),
+                };
+                LeafCell::from_values(Some(i), vec![String::from("NULL"), value.clone(), value])
+            })
+            .collect();
+        let table: Table = Table::from_rows(schema, rows);
+
+        assert_eq!(
+            timestamp_columns(&table, None),
+            vec![(1, Epoch::UnixSeconds)]
+        );
+
+        let config: TimestampConfig =
+            serde_json::from_str(r#"{"t": {"created_at": null, "total": "unix_seconds"}}"#)
+                .unwrap();
+        assert_eq!(
+            timestamp_columns(&table, Some(&config)),
+            vec![(2, Epoch::UnixSeconds)]
+        );
+        assert!(is_timestamp_name("lastModifiedDate"));
+        assert!(!is_timestamp_name("total"));
+    }
+}
@@ -1,17 +1,7 @@
-use lazy_static::lazy_static;
 use log::warn;
-use regex::Regex;
 use std::cell::RefCell;
 use std::str::from_utf8;
 
-lazy_static! {
-    static ref CREATE_TABLE_RE: Regex =
-        Regex::new(r#"(?i)^CREATE TABLE [^(]+\(([a-zA-Z0-9_ ,%()\[\]'"]*)\)"#).unwrap();
-    static ref VARCHAR_RE: Regex = Regex::new(r"[N]+VARCHAR[ ]*(\(.*\))*").unwrap();
-    static ref CAST_FUNC_RE: Regex =
-        Regex::new(r"\(CAST\([a-zA-Z0-9, '_%()]*AS INTEGER\)\)").unwrap();
-}
-
 //https://github.com/MidasLamb/sqlite-varint/blob/master/src/lib.rs
 pub fn read_varint(bytes: &[u8]) -> (i64, usize) {
     let mut varint: i64 = 0;
@@ -32,92 +22,115 @@ pub fn read_varint(bytes: &[u8]) -> (i64, usize) {
     (varint, bytes_read)
 }
 
-// Extracts columns name from creation query
-pub fn get_column_names_from_creation_query(query: &str) -> Result<Vec<String>, &'static str> {
-    match CREATE_TABLE_RE.captures(
-        query
-            .replace("\n", "")
-            .replace("\r", "")
-            .replace("\t", " ")
-            .as_str(),
-    ) {
-        Some(captures) => {
-            let column_definition = captures.get(1).unwrap().as_str();
-            let cast_func_removed = CAST_FUNC_RE.replace(column_definition, "");
-            let mut columns: Vec<String> = vec![];
-
-            for column_def in cast_func_removed.split(',') {
-                // This words defines last part of the columns section.
-                if column_def
-                    .trim()
-                    .to_ascii_uppercase()
-                    .starts_with("CONSTRAINT")
-                    || column_def
-                        .trim()
-                        .to_ascii_uppercase()
-                        .starts_with("FOREIGN")
-                    || column_def.trim().to_ascii_uppercase().starts_with("CHECK")
-                    || column_def.trim().to_ascii_uppercase().starts_with("UNIQUE")
-                    || column_def
-                        .trim()
-                        .to_ascii_uppercase()
-                        .starts_with("PRIMARY")
-                {
-                    break;
-                }
+/// Big-endian two's complement integer of up to 8 bytes (record serial types 1 to 6)
+pub fn read_int(bytes: &[u8]) -> i64 {
+    let value: i64 = bytes
+        .iter()
+        .fold(0, |value, byte| (value << 8) | *byte as i64);
+    let unused: u32 = 64 - 8 * bytes.len() as u32;
+    (value << unused) >> unused
+}
 
-                //TODO: Removed for now
-                /*if VARCHAR_RE.replace(column_def, "").contains("VARCHAR"){
-                    continue;
-                }*/
-
-                columns.push(
-                    column_def
-                        .trim()
-                        .replace('"', "")
-                        .replace("'", "")
-                        .split(' ')
-                        .next()
-                        .unwrap()
-                        .to_string(),
-                );
-            }
+/// Lowercase hexadecimal representation of bytes (e.g.: digests)
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
 
-            Ok(columns)
-        }
-        None => Err("Error parsing table creation query. Maybe this is a VIRTUAL table"),
+/// Name usable as a file name on any system: path separators, reserved and control characters
+/// are replaced and reserved Windows names are prefixed
+pub fn sanitize_file_name(name: &str) -> String {
+    let mut stem: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim_end_matches(['.', ' '])
+        .to_string();
+    if stem.is_empty() || stem.starts_with('.') {
+        stem = format!("_{}", stem);
     }
-}
 
-/*pub fn combine_columns_values(columns: Vec<String>, values: &mut Vec<String>) -> HashMap<String, String> {
+    let device: String = stem.split('.').next().unwrap_or_default().to_uppercase();
+    let is_reserved: bool = ["CON", "PRN", "AUX", "NUL"].contains(&device.as_str())
+        || ((device.starts_with("COM") || device.starts_with("LPT"))
+            && device.len() == 4
+            && device.ends_with(|c: char| c.is_ascii_digit()));
+    if is_reserved {
+        stem = format!("_{}", stem);
+    }
 
+    stem
+}
 
-    // In some cases, it happens that if last column value is NULL,
-    //  it is not included in cell header (so there is no reference of it at all)
-    if columns.len() == values.len() + 1 {
-        values.insert(columns.len() - 2 , String::from("NULL"));
+/// Matches text against a pattern with a wildcard for any sequence (`any`) and one for any character
+/// (`one`): `*` and `?` for globs of table names (ignoring case, like SQLite does for identifiers),
+/// `%` and `_` for LIKE
+pub fn glob_match(pattern: &str, text: &str, any: char, one: char, ignore_case: bool) -> bool {
+    let fold = |s: &str| -> Vec<char> {
+        match ignore_case {
+            true => s.to_lowercase().chars().collect(),
+            false => s.chars().collect(),
+        }
+    };
+    let (pattern, text): (Vec<char>, Vec<char>) = (fold(pattern), fold(text));
+
+    // Position after the last `any` in the pattern and the text position it has been matched to
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut p, mut t): (usize, usize) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(c) if *c == any => {
+                p += 1;
+                backtrack = Some((p, t));
+            }
+            Some(c) if *c == one || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
     }
 
-    if columns.len() != values.len() {
-        trace!("DIFFERENT LENGTH OF COLUMNS AND VALUES!");
-        trace!("COLUMNS: {:?} ({})", columns, columns.len());
-        trace!("VALUES: {:?} ({})", values, values.len());
-        return HashMap::new();
+    pattern[p..].iter().all(|c| *c == any)
+}
+
+/// Encodes a value as an SQLite varint (inverse of `read_varint`)
+pub fn write_varint(value: i64) -> Vec<u8> {
+    let mut v: u64 = value as u64;
+
+    // 9 bytes: 8 bytes of 7 bits plus a full last byte
+    if v >> 56 != 0 {
+        let mut bytes: Vec<u8> = vec![0; 9];
+        bytes[8] = v as u8;
+        v >>= 8;
+        for i in (0..8).rev() {
+            bytes[i] = (v & 0x7f) as u8 | 0x80;
+            v >>= 7;
+        }
+        return bytes;
     }
 
-    let mut map: HashMap<String, String> = HashMap::new();
-    for (col, val) in columns.into_iter().zip(values) {
-        map.insert(col, val.to_string());
+    let mut bytes: Vec<u8> = vec![(v & 0x7f) as u8];
+    v >>= 7;
+    while v > 0 {
+        bytes.push((v & 0x7f) as u8 | 0x80);
+        v >>= 7;
     }
+    bytes.reverse();
 
-    map
+    bytes
 }
 
-
-pub fn is_table_without_rowid(query: &str) -> bool {
-    query.trim().to_ascii_uppercase().replace(";", "").ends_with("WITHOUT ROWID")
-}*/
-
 thread_local! {
     pub static STRING_ENCODING: RefCell<u32> = RefCell::new(1);
 }
@@ -252,3 +265,71 @@ pub fn read_overflow_page_for_blob(bytearray: &[u8], page_num: u32, page_size: u
     final_bytearray
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_are_sign_extended() {
+        assert_eq!(read_int(&[0xFB]), -5);
+        assert_eq!(read_int(&[0x7F]), 127);
+        assert_eq!(read_int(&[0xFE, 0xD4]), -300);
+        assert_eq!(read_int(&[0xFE, 0xEE, 0x90]), -70000);
+        assert_eq!(read_int(&[0xFF, 0xFF, 0xFF, 0xFF]), -1);
+        assert_eq!(read_int(&[0xFF, 0xFF, 0x4D, 0x2F, 0xA2, 0x00]), -3000000000);
+        assert_eq!(read_int(&i64::MIN.to_be_bytes()), i64::MIN);
+    }
+
+    #[test]
+    fn glob_of_table_names() {
+        assert!(glob_match("mess*", "Messages", '*', '?', true));
+        assert!(glob_match("*_fts_*", "notes_fts_data", '*', '?', true));
+        assert!(glob_match("t?", "T1", '*', '?', true));
+        assert!(!glob_match("t?", "t12", '*', '?', true));
+        assert!(glob_match("*", "", '*', '?', true));
+        assert!(!glob_match("a*b", "acbd", '*', '?', true));
+    }
+
+    #[test]
+    fn like_patterns() {
+        assert!(glob_match(
+            "%@example.com",
+            "bob@EXAMPLE.com",
+            '%',
+            '_',
+            true
+        ));
+        assert!(glob_match("a%b%c", "a--b--b--c", '%', '_', true));
+        assert!(glob_match("_b_", "abc", '%', '_', true));
+        assert!(!glob_match("_b_", "abcd", '%', '_', true));
+        // `*` and `?` are not wildcards in LIKE
+        assert!(!glob_match("a*", "abc", '%', '_', true));
+    }
+
+    #[test]
+    fn case_sensitive_match() {
+        assert!(glob_match("A*", "Abc", '*', '?', false));
+        assert!(!glob_match("a*", "Abc", '*', '?', false));
+    }
+
+    #[test]
+    fn sanitized_file_names() {
+        assert_eq!(sanitize_file_name("messages"), "messages");
+        assert_eq!(sanitize_file_name("café"), "café");
+        assert_eq!(sanitize_file_name("a/b"), "a_b");
+        assert_eq!(sanitize_file_name("..\\x"), "_.._x");
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "_.._.._etc_passwd");
+        assert_eq!(sanitize_file_name("a:b*c?\"d<e>f|g"), "a_b_c__d_e_f_g");
+        assert_eq!(sanitize_file_name("tab\tname\n"), "tab_name_");
+        assert_eq!(sanitize_file_name("name. "), "name");
+        assert_eq!(sanitize_file_name(""), "_");
+        assert_eq!(sanitize_file_name("..."), "_");
+        // Reserved device names on Windows, also with an extension
+        assert_eq!(sanitize_file_name("con"), "_con");
+        assert_eq!(sanitize_file_name("COM1.csv"), "_COM1.csv");
+        assert_eq!(sanitize_file_name("lpt9"), "_lpt9");
+        assert_eq!(sanitize_file_name("com10"), "com10");
+        assert_eq!(sanitize_file_name("console"), "console");
+    }
+}
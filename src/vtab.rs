@@ -0,0 +1,424 @@
+use base64::{engine::general_purpose, Engine as _};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::db::Table;
+use crate::fts5::Fts5Index;
+use crate::schema::TableSchema;
+use crate::structs::LeafCell;
+
+/// Suffixes of the shadow tables created by each module
+fn shadow_suffixes(module: &str) -> &'static [&'static str] {
+    match module {
+        "fts3" | "fts4" => &["content", "segments", "segdir", "docsize", "stat"],
+        "fts5" => &["data", "idx", "content", "docsize", "config"],
+        "rtree" | "rtree_i32" | "geopoly" => &["node", "parent", "rowid"],
+        _ => &[],
+    }
+}
+
+/// External content table of an FTS table (`content=...` option)
+pub fn content_table(schema: &TableSchema) -> Option<String> {
+    match schema.module.as_deref() {
+        Some(module) if module.starts_with("fts") => schema
+            .module_arguments
+            .iter()
+            .filter_map(|argument| argument.split_once('='))
+            .find(|(option, _)| option.trim().eq_ignore_ascii_case("content"))
+            .map(|(_, value)| {
+                value
+                    .trim()
+                    .trim_matches(|c| c == '\'' || c == '"')
+                    .to_string()
+            }),
+        _ => None,
+    }
+}
+
+/// Representation of a virtual table and of the shadow tables holding its content
+#[derive(Serialize, Deserialize, Clone)]
+pub struct VirtualTable {
+    pub name: String,
+    pub module: String,
+    pub arguments: Vec<String>,
+    pub columns: Vec<String>,
+    pub shadow_tables: Vec<String>,
+    /// External content table of FTS tables (`content=...` option)
+    pub content_table: Option<String>,
+    /// Terms decoded from the segments of FTS5 tables
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fts5_index: Option<Fts5Index>,
+    #[serde(skip)]
+    pub schema: TableSchema,
+}
+
+impl VirtualTable {
+    /// Links the virtual table to its shadow tables
+    pub fn new(schema: TableSchema, tables: &mut [Table]) -> VirtualTable {
+        let module: String = schema.module.clone().unwrap_or_default();
+
+        let mut shadow_tables: Vec<String> = vec![];
+        for suffix in shadow_suffixes(&module).iter() {
+            let shadow_name: String = format!("{}_{}", schema.name, suffix);
+            for table in tables.iter_mut() {
+                if table.name.eq_ignore_ascii_case(&shadow_name) {
+                    table.shadow_of = Some(schema.name.to_string());
+                    shadow_tables.push(table.name.to_string());
+                }
+            }
+        }
+        debug!(
+            "{} ({}) shadow tables: {:?}",
+            schema.name, module, shadow_tables
+        );
+
+        let content_table: Option<String> = content_table(&schema);
+
+        VirtualTable {
+            name: schema.name.to_string(),
+            module,
+            arguments: schema.module_arguments.clone(),
+            columns: schema.column_names(),
+            shadow_tables,
+            content_table,
+            fts5_index: None,
+            schema,
+        }
+    }
+
+    fn shadow_table<'a>(&self, tables: &'a [Table], suffix: &str) -> Option<&'a Table> {
+        let shadow_name: String = format!("{}_{}", self.name, suffix);
+        tables
+            .iter()
+            .find(|table| table.name.eq_ignore_ascii_case(&shadow_name))
+    }
+
+    /// Rebuilds the rows of the virtual table from its shadow tables
+    pub fn rebuild(&self, tables: &[Table]) -> Option<Table> {
+        match self.module.as_str() {
+            "fts3" | "fts4" | "fts5" => self.rebuild_fts(tables),
+            "rtree" | "rtree_i32" => self.rebuild_rtree(tables),
+            module => {
+                info!("{}: cannot rebuild content of {} module", self.name, module);
+                None
+            }
+        }
+    }
+
+    /// Decodes the FTS5 index stored in the %_data table.
+    /// Terms whose rowid is not in the content table belong to deleted rows.
+    pub fn decode_fts5_index(&mut self, tables: &[Table]) {
+        if self.module != "fts5" {
+            return;
+        }
+
+        let detail: Option<String> = self
+            .schema
+            .module_arguments
+            .iter()
+            .filter_map(|argument| argument.split_once('='))
+            .find(|(option, _)| option.trim().eq_ignore_ascii_case("detail"))
+            .map(|(_, value)| {
+                value
+                    .trim()
+                    .trim_matches(|c| c == '\'' || c == '"')
+                    .to_lowercase()
+            });
+        match detail.as_deref() {
+            None | Some("full") => (),
+            Some(d) => {
+                warn!(
+                    "{}: FTS5 index with detail={} not supported, skipping",
+                    self.name, d
+                );
+                return;
+            }
+        }
+
+        let data_table: &Table = match self.shadow_table(tables, "data") {
+            Some(t) => t,
+            None => {
+                warn!("{}: data shadow table not found", self.name);
+                return;
+            }
+        };
+
+        // Live blocks first, then the ones found in WAL frames
+        let mut rows: Vec<(Option<i64>, Vec<String>)> = data_table
+            .rows
+            .iter()
+            .map(|cell| (cell.rowid(), cell.data()))
+            .collect();
+        if let Some(diff) = &data_table.wal {
+            rows.extend(diff.rows());
+        }
+        let blocks: Vec<(i64, Vec<u8>)> = rows
+            .iter()
+            .filter_map(|(rowid, data)| {
+                let blob: Vec<u8> = general_purpose::STANDARD.decode(data.last()?).ok()?;
+                Some(((*rowid)?, blob))
+            })
+            .collect();
+
+        let content_name: Option<String> = match &self.content_table {
+            Some(t) if t.is_empty() => None,
+            Some(t) => Some(t.to_string()),
+            None => Some(format!("{}_content", self.name)),
+        };
+        let content_rowids: Option<HashSet<i64>> = content_name.and_then(|name| {
+            tables
+                .iter()
+                .find(|table| table.name.eq_ignore_ascii_case(&name))
+                .map(|table| table.rows.iter().filter_map(|cell| cell.rowid()).collect())
+        });
+
+        let index: Fts5Index = Fts5Index::new(&blocks, content_rowids.as_ref());
+        info!(
+            "{}: decoded {} FTS5 index entries ({} orphan rowids)",
+            self.name,
+            index.entries.len(),
+            index.orphan_rowids.len()
+        );
+        self.fts5_index = Some(index);
+    }
+
+    /// FTS content tables hold the docid followed by the value of each column
+    fn rebuild_fts(&self, tables: &[Table]) -> Option<Table> {
+        match &self.content_table {
+            Some(t) if t.is_empty() => {
+                info!("{}: contentless FTS table", self.name);
+                return None;
+            }
+            Some(t) => {
+                info!("{}: content is stored in external table {}", self.name, t);
+                return None;
+            }
+            None => (),
+        }
+
+        match self.shadow_table(tables, "content") {
+            Some(content) => Some(content.rebuild_as(self.schema.clone(), 1)),
+            None => {
+                warn!("{}: content shadow table not found", self.name);
+                None
+            }
+        }
+    }
+
+    /// R*Tree entries are stored in the blobs of the %_node table:
+    /// 2 bytes of depth, 2 bytes of entry count and, for each entry,
+    /// the 8-bytes rowid followed by two 4-bytes coordinates per dimension.
+    /// Leaf nodes are the ones referenced by the %_rowid table.
+    fn rebuild_rtree(&self, tables: &[Table]) -> Option<Table> {
+        let (node_table, rowid_table): (&Table, &Table) = match (
+            self.shadow_table(tables, "node"),
+            self.shadow_table(tables, "rowid"),
+        ) {
+            (Some(n), Some(r)) => (n, r),
+            _ => {
+                warn!("{}: R*Tree shadow tables not found", self.name);
+                return None;
+            }
+        };
+
+        let is_i32: bool = self.module == "rtree_i32";
+        let coordinates: usize = self
+            .schema
+            .module_arguments
+            .iter()
+            .filter(|argument| !argument.contains('=') && !argument.starts_with('+'))
+            .count()
+            .saturating_sub(1);
+
+        // rowid -> auxiliary columns
+        let mut leaves: HashMap<i64, Vec<String>> = HashMap::new();
+        let mut leaf_nodes: Vec<String> = vec![];
+        for row in rowid_table.rows.iter() {
+            let data: Vec<String> = row.data();
+            if data.len() < 2 {
+                continue;
+            }
+            leaf_nodes.push(data[1].to_string());
+            match row.rowid() {
+                Some(rowid) => leaves.insert(rowid, data[2..].to_vec()),
+                None => None,
+            };
+        }
+
+        let mut rows: Vec<LeafCell> = vec![];
+        for node in node_table.rows.iter() {
+            let data: Vec<String> = node.data();
+            if data.len() < 2 || !leaf_nodes.contains(&data[0]) {
+                continue;
+            }
+
+            let blob: Vec<u8> = match general_purpose::STANDARD.decode(&data[1]) {
+                Ok(b) => b,
+                Err(_) => continue,
+            };
+            let entries: Vec<(i64, Vec<String>)> = match read_rtree_node(&blob, coordinates, is_i32)
+            {
+                Some(e) => e,
+                None => {
+                    debug!("{}: truncated R*Tree node {} skipped", self.name, data[0]);
+                    continue;
+                }
+            };
+            for (rowid, mut values) in entries.into_iter() {
+                if let Some(auxiliary) = leaves.get(&rowid) {
+                    values.extend(auxiliary.iter().cloned());
+                }
+
+                rows.push(LeafCell::from_values(Some(rowid), values));
+            }
+        }
+
+        Some(Table::from_rows(self.schema.clone(), rows))
+    }
+}
+
+/// Reads the entries of an R*Tree node blob: rowid followed by its coordinates.
+/// Returns `None` if the node is shorter than its entry count says (e.g.: truncated or carved)
+fn read_rtree_node(
+    blob: &[u8],
+    coordinates: usize,
+    is_i32: bool,
+) -> Option<Vec<(i64, Vec<String>)>> {
+    let count: usize = u16::from_be_bytes(blob.get(2..4)?.try_into().ok()?) as usize;
+    let entry_size: usize = 8 + 4 * coordinates;
+
+    let mut entries: Vec<(i64, Vec<String>)> = vec![];
+    for i in 0..count {
+        let offset: usize = 4 + i * entry_size;
+        let rowid: i64 = i64::from_be_bytes(blob.get(offset..offset + 8)?.try_into().ok()?);
+        let mut values: Vec<String> = vec![rowid.to_string()];
+        for c in 0..coordinates {
+            let start: usize = offset + 8 + 4 * c;
+            let bytes: [u8; 4] = blob.get(start..start + 4)?.try_into().ok()?;
+            values.push(match is_i32 {
+                true => i32::from_be_bytes(bytes).to_string(),
+                false => f32::from_be_bytes(bytes).to_string(),
+            });
+        }
+        entries.push((rowid, values));
+    }
+
+    Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{sqlite3, test_dir};
+    use std::fs::remove_dir_all;
+    use std::path::PathBuf;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn table(sql: &str, rows: Vec<Vec<String>>) -> Table {
+        let cells: Vec<LeafCell> = rows
+            .into_iter()
+            .map(|values| LeafCell::from_values(values[0].parse().ok(), values))
+            .collect();
+        Table::from_rows(TableSchema::parse(sql).unwrap(), cells)
+    }
+
+    #[test]
+    fn rtree_from_shadow_tables() {
+        let dir: PathBuf = test_dir("rtree");
+        let output: String = sqlite3(
+            &dir.join("rtree.db"),
+            "CREATE VIRTUAL TABLE r USING rtree(id, minx, maxx, miny, maxy, +label);
+            INSERT INTO r VALUES (1, 0.5, 1.5, -2, 2, 'a'), (7, 10, 20, 30, 40.25, 'b');
+            SELECT nodeno, hex(data) FROM r_node;
+            SELECT rowid, nodeno, a0 FROM r_rowid;",
+        );
+        remove_dir_all(&dir).unwrap();
+        let lines: Vec<Vec<&str>> = output.lines().map(|l| l.split('|').collect()).collect();
+        let blob: Vec<u8> = from_hex(lines[0][1]);
+
+        // Both entries are in the root node, with 4 coordinates each
+        let entries: Vec<(i64, Vec<String>)> = read_rtree_node(&blob, 4, false).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                (1, ["1", "0.5", "1.5", "-2", "2"].map(String::from).to_vec()),
+                (
+                    7,
+                    ["7", "10", "20", "30", "40.25"].map(String::from).to_vec()
+                ),
+            ]
+        );
+
+        // Truncated in the middle of the coordinates of the second entry
+        assert!(read_rtree_node(&blob[..4 + 24 + 12], 4, false).is_none());
+        assert!(read_rtree_node(&blob[..3], 4, false).is_none());
+        assert_eq!(
+            read_rtree_node(&blob[..4 + 24 + 24], 4, false)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let node = |blob: &[u8]| {
+            vec![
+                String::from(lines[0][0]),
+                general_purpose::STANDARD.encode(blob),
+            ]
+        };
+        let rowid_table: Table = table(
+            "CREATE TABLE \"r_rowid\"(rowid INTEGER PRIMARY KEY,nodeno,a0)",
+            lines[1..]
+                .iter()
+                .map(|l| l.iter().map(|v| v.to_string()).collect())
+                .collect(),
+        );
+        let schema: TableSchema = TableSchema::parse(
+            "CREATE VIRTUAL TABLE r USING rtree(id, minx, maxx, miny, maxy, +label)",
+        )
+        .unwrap();
+
+        let mut tables: Vec<Table> = vec![
+            table(
+                "CREATE TABLE \"r_node\"(nodeno INTEGER PRIMARY KEY,data)",
+                vec![node(&blob)],
+            ),
+            rowid_table.clone(),
+        ];
+        let rtree: VirtualTable = VirtualTable::new(schema.clone(), &mut tables);
+        assert_eq!(rtree.shadow_tables, vec!["r_node", "r_rowid"]);
+        let rebuilt: Table = rtree.rebuild(&tables).unwrap();
+        assert_eq!(
+            rebuilt
+                .rows
+                .iter()
+                .map(|r| r.data())
+                .collect::<Vec<Vec<String>>>(),
+            vec![
+                ["1", "0.5", "1.5", "-2", "2", "a"]
+                    .map(String::from)
+                    .to_vec(),
+                ["7", "10", "20", "30", "40.25", "b"]
+                    .map(String::from)
+                    .to_vec(),
+            ]
+        );
+
+        // A truncated node is skipped, not read past its end
+        let mut tables: Vec<Table> = vec![
+            table(
+                "CREATE TABLE \"r_node\"(nodeno INTEGER PRIMARY KEY,data)",
+                vec![node(&blob[..4 + 24 + 12])],
+            ),
+            rowid_table,
+        ];
+        let rtree: VirtualTable = VirtualTable::new(schema, &mut tables);
+        assert!(rtree.rebuild(&tables).unwrap().rows.is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::constants::{WAL_FILE_HEADER_LEN, WAL_FRAME_HEADER_LEN};
 use crate::structs::{Page, FREEPAGES, OVERFLOW_PAGES};
 use log::{debug, info, warn};
 
@@ -58,6 +59,14 @@ impl WALFrameHeader {
     pub fn page_num(&self) -> u32 {
         self.page_num
     }
+
+    /// Size of the database in pages after the commit, for commit frames
+    pub fn commit_size(&self) -> Option<u32> {
+        match self.page_count_after_commit {
+            0 => None,
+            n => Some(n),
+        }
+    }
 }
 
 impl std::fmt::Debug for WALFrameHeader {
@@ -118,7 +127,8 @@ impl WALFrame {
             }
         }
 
-        let page: Page = Page::new(bytearray, offset + 24, header.page_num - 1, true);
+        let mut page: Page = Page::new(bytearray, offset + 24, header.page_num - 1, true);
+        page.set_wal_frame(frame_num + 1, offset);
 
         Some(WALFrame {
             i: frame_num,
@@ -163,6 +173,18 @@ pub struct WALFileHeader {
 }
 
 impl WALFileHeader {
+    /// Parses the header of a WAL file, checking its magic
+    pub fn parse(wal_bytearray: &[u8]) -> Result<WALFileHeader, &'static str> {
+        if wal_bytearray.len() < WAL_FILE_HEADER_LEN {
+            return Err("WAL file too short");
+        }
+        let header: WALFileHeader = WALFileHeader::new(wal_bytearray, wal_bytearray.len() as u64);
+        match header.magic {
+            WAL_MAGIC_LE | WAL_MAGIC_BE => Ok(header),
+            _ => Err("NOT A WAL FILE"),
+        }
+    }
+
     fn new(wal_bytearray: &[u8], file_size: u64) -> WALFileHeader {
         let page_size = u32::from_be_bytes([
             wal_bytearray[8],
@@ -219,6 +241,106 @@ impl WALFileHeader {
     }
 }
 
+/// Magic of WAL files whose checksums are computed on little-endian words
+const WAL_MAGIC_LE: u32 = 0x377f0682;
+
+/// Magic of WAL files whose checksums are computed on big-endian words
+const WAL_MAGIC_BE: u32 = 0x377f0683;
+
+/// Cumulative checksum of the WAL, continued from `(s0, s1)` over `data`
+fn wal_checksum(data: &[u8], big_endian: bool, (mut s0, mut s1): (u32, u32)) -> (u32, u32) {
+    for words in data.chunks_exact(8) {
+        let (x0, x1): (u32, u32) = match big_endian {
+            true => (
+                u32::from_be_bytes([words[0], words[1], words[2], words[3]]),
+                u32::from_be_bytes([words[4], words[5], words[6], words[7]]),
+            ),
+            false => (
+                u32::from_le_bytes([words[0], words[1], words[2], words[3]]),
+                u32::from_le_bytes([words[4], words[5], words[6], words[7]]),
+            ),
+        };
+        s0 = s0.wrapping_add(x0).wrapping_add(s1);
+        s1 = s1.wrapping_add(x1).wrapping_add(s0);
+    }
+
+    (s0, s1)
+}
+
+/// A frame of the WAL, as found in the file (whether its page is parsed or not)
+pub struct FrameSummary {
+    /// Position of the frame in the file, starting from 1
+    pub number: u32,
+    pub offset: usize,
+    pub page_num: u32,
+    /// Size of the database in pages after the commit, for commit frames
+    pub commit_size: Option<u32>,
+    /// The salts of the frame match the ones of the header
+    /// (frames left from before the last checkpoint do not)
+    pub salt_match: bool,
+    /// The cumulative checksum of the frame is correct
+    pub checksum_match: bool,
+    /// Transaction the frame belongs to, starting from 1; `None` for frames after the last commit
+    pub transaction: Option<u32>,
+}
+
+/// Lists the frames of a WAL file, checking their salts and checksums and grouping them by transaction.
+/// Like SQLite, frames after the first invalid one are not considered valid.
+pub fn list_frames(bytearray: &[u8]) -> Result<Vec<FrameSummary>, &'static str> {
+    let header: WALFileHeader = WALFileHeader::parse(bytearray)?;
+    let big_endian: bool = header.magic == WAL_MAGIC_BE;
+    if header.page_size == 0 {
+        return Err("Invalid page size in WAL header");
+    }
+
+    let mut checksum: (u32, u32) = wal_checksum(&bytearray[..24], big_endian, (0, 0));
+    let mut valid: bool = checksum == (header.checksum1, header.checksum2);
+    if !valid {
+        warn!("Wrong checksum of the WAL header");
+    }
+
+    let mut frames: Vec<FrameSummary> = vec![];
+    let mut pending: Vec<usize> = vec![];
+    let mut transaction: u32 = 0;
+    for i in 0..header.frame_count {
+        let offset: usize =
+            WAL_FILE_HEADER_LEN + i as usize * (header.page_size as usize + WAL_FRAME_HEADER_LEN);
+        let frame_header: WALFrameHeader = WALFrameHeader::new(bytearray, offset);
+        let page_end: usize = offset + WAL_FRAME_HEADER_LEN + header.page_size as usize;
+
+        let salt_match: bool =
+            frame_header.salt1 == header.salt1 && frame_header.salt2 == header.salt2;
+        checksum = wal_checksum(&bytearray[offset..offset + 8], big_endian, checksum);
+        checksum = wal_checksum(
+            &bytearray[offset + WAL_FRAME_HEADER_LEN..page_end],
+            big_endian,
+            checksum,
+        );
+        let checksum_match: bool = checksum == (frame_header.checksum1, frame_header.checksum2);
+        valid = valid && salt_match && checksum_match;
+
+        pending.push(frames.len());
+        frames.push(FrameSummary {
+            number: i + 1,
+            offset,
+            page_num: frame_header.page_num,
+            commit_size: frame_header.commit_size(),
+            salt_match,
+            checksum_match,
+            transaction: None,
+        });
+
+        if valid && frame_header.commit_size().is_some() {
+            transaction += 1;
+            for j in pending.drain(..) {
+                frames[j].transaction = Some(transaction);
+            }
+        }
+    }
+
+    Ok(frames)
+}
+
 impl std::fmt::Debug for WALFileHeader {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let mut res: std::fmt::Result;
@@ -275,6 +397,26 @@ impl WALFile {
         self.frames.clone()
     }
 
+    /// Last version of the page written by a committed transaction, if any (frames left by
+    /// a previous generation of the WAL, with other salts, are ignored)
+    pub fn committed_page(&self, number: u32) -> Option<Page> {
+        let mut committed: Option<&WALFrame> = None;
+        let mut pending: Option<&WALFrame> = None;
+        for frame in self.frames.iter() {
+            if (frame.header.salt1, frame.header.salt2) != (self.header.salt1, self.header.salt2) {
+                continue;
+            }
+            if frame.header.page_num == number {
+                pending = Some(frame);
+            }
+            if frame.header.commit_size().is_some() && pending.is_some() {
+                committed = pending.take();
+            }
+        }
+
+        committed.map(|frame| frame.page())
+    }
+
     /*pub fn header(&self) -> WALFileHeader {
         self.header.clone()
     }
@@ -329,3 +471,49 @@ impl std::fmt::Debug for WALFile {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header of a WAL written by SQLite (little-endian checksums), with its checksum
+    const HEADER: [u8; 32] = [
+        0x37, 0x7f, 0x06, 0x82, 0x00, 0x2d, 0xe2, 0x18, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x81, 0x23, 0x30, 0xb0, 0x22, 0xd0, 0xbf, 0x93, 0x86, 0x17, 0x26, 0x94, 0xd2, 0xbe,
+        0xce, 0x5b,
+    ];
+
+    #[test]
+    fn checksum_of_sqlite_header() {
+        assert_eq!(
+            wal_checksum(&HEADER[..24], false, (0, 0)),
+            (
+                u32::from_be_bytes([HEADER[24], HEADER[25], HEADER[26], HEADER[27]]),
+                u32::from_be_bytes([HEADER[28], HEADER[29], HEADER[30], HEADER[31]])
+            )
+        );
+    }
+
+    #[test]
+    fn checksum_byte_order() {
+        let data: [u8; 16] = [1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0];
+        assert_eq!(wal_checksum(&data, false, (0, 0)), (7, 14));
+        assert_eq!(
+            wal_checksum(&data, true, (0, 0)),
+            (0x0700_0000, 0x0E00_0000)
+        );
+    }
+
+    #[test]
+    fn checksum_is_cumulative() {
+        let data: [u8; 16] = [1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0];
+        let first: (u32, u32) = wal_checksum(&data[..8], false, (0, 0));
+        assert_eq!(wal_checksum(&data[8..], false, first), (7, 14));
+    }
+
+    #[test]
+    fn checksum_wraps() {
+        let data: [u8; 8] = [0xff; 8];
+        assert_eq!(wal_checksum(&data, false, (1, 0)), (0, 0xffff_ffff));
+    }
+}